@@ -0,0 +1,101 @@
+use crate::ingest::PostRecord;
+use serde::Deserialize;
+
+//** NOTICE **
+// Just enough of an AppView client for what the lib needs, getAuthorFeed for backfill and getPosts
+// for posts the publisher likes. Only the fields we read are deserialized
+//
+
+/// Most posts getAuthorFeed hands back at a time
+const AUTHOR_FEED_PAGE_SIZE: u8 = 100;
+/// Their own posts and thread replies to themselves, so threads come in whole
+const AUTHOR_FEED_FILTER: &str = "posts_and_author_threads";
+
+#[derive(Debug, Deserialize)]
+pub struct AuthorFeedPage {
+    pub cursor: Option<String>,
+    pub feed: Vec<FeedViewPost>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FeedViewPost {
+    pub post: PostView,
+    /// Set on reposts, those are someone else's post
+    pub reason: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PostView {
+    pub uri: String,
+    pub cid: String,
+    pub author: PostAuthor,
+    pub record: PostRecord,
+    pub indexed_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PostAuthor {
+    pub did: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GetPostsOutput {
+    posts: Vec<PostView>,
+}
+
+pub struct AppViewClient {
+    client: reqwest::Client,
+    appview_url: String,
+}
+
+impl AppViewClient {
+    pub fn new(appview_url: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            appview_url: appview_url.into(),
+        }
+    }
+
+    pub async fn get_author_feed(
+        &self,
+        actor: &str,
+        cursor: Option<&str>,
+    ) -> anyhow::Result<AuthorFeedPage> {
+        let mut query = vec![
+            ("actor", actor.to_string()),
+            ("limit", AUTHOR_FEED_PAGE_SIZE.to_string()),
+            ("filter", AUTHOR_FEED_FILTER.to_string()),
+        ];
+        if let Some(cursor) = cursor {
+            query.push(("cursor", cursor.to_string()));
+        }
+        self.get("app.bsky.feed.getAuthorFeed", &query).await
+    }
+
+    pub async fn get_posts(&self, uris: Vec<String>) -> anyhow::Result<Vec<PostView>> {
+        let query: Vec<(&str, String)> = uris.into_iter().map(|uri| ("uris", uri)).collect();
+        let output: GetPostsOutput = self.get("app.bsky.feed.getPosts", &query).await?;
+        Ok(output.posts)
+    }
+
+    async fn get<T: for<'de> Deserialize<'de>>(
+        &self,
+        method: &str,
+        query: &[(&str, String)],
+    ) -> anyhow::Result<T> {
+        let body = self
+            .client
+            .get(format!(
+                "{}/xrpc/{method}",
+                self.appview_url.trim_end_matches('/')
+            ))
+            .query(query)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        Ok(serde_json::from_str(&body)?)
+    }
+}
@@ -0,0 +1,377 @@
+use async_trait::async_trait;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::Utc;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+//** NOTICE **
+// Clients send a service auth JWT signed by the user's atproto signing key with getFeedSkeleton.
+// Checking it against the key in their DID document is what lets us trust the requester DID. The
+// DID is looked up before the signature can be checked, so anyone can make us look one up. Only
+// did:plc and did:web on a public hostname are resolved, and without following redirects
+//
+
+/// Multicodec prefixes on a publicKeyMultibase
+const SECP256K1_PREFIX: [u8; 2] = [0xe7, 0x01];
+const P256_PREFIX: [u8; 2] = [0x80, 0x24];
+/// How long a resolved signing key is trusted before looking up the DID document again
+const KEY_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+/// Most signing keys kept, the oldest goes once it is full
+const KEY_CACHE_CAPACITY: usize = 10_000;
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(3);
+/// Longest a token can still be valid for. Clients ask for ones that last a minute
+const MAX_TOKEN_LIFETIME: i64 = 5 * 60;
+/// How far ahead of our clock a token can say it was issued
+const MAX_CLOCK_SKEW: i64 = 30;
+/// did:plc identifiers are 24 base32 characters
+const PLC_ID_LENGTH: usize = 24;
+/// did:web hosts that are never resolved, on top of IP addresses and ports
+const PRIVATE_HOST_SUFFIXES: [&str; 6] = [
+    "localhost",
+    "local",
+    "internal",
+    "intranet",
+    "lan",
+    "home.arpa",
+];
+
+#[derive(Clone, Debug)]
+pub enum PublicKey {
+    K256(k256::ecdsa::VerifyingKey),
+    P256(p256::ecdsa::VerifyingKey),
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    Malformed(String),
+    UnsupportedAlgorithm(String),
+    Expired,
+    /// Issued in the future, or valid for longer than any client asks for
+    BadLifetime(String),
+    WrongAudience(String),
+    WrongMethod(String),
+    BadSignature,
+    Resolve(String),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::Malformed(reason) => write!(f, "Malformed service auth token: {reason}"),
+            AuthError::UnsupportedAlgorithm(alg) => write!(f, "Unsupported JWT algorithm {alg}"),
+            AuthError::Expired => write!(f, "Service auth token has expired"),
+            AuthError::BadLifetime(reason) => write!(f, "Service auth token {reason}"),
+            AuthError::WrongAudience(aud) => write!(f, "Service auth token is for {aud}"),
+            AuthError::WrongMethod(lxm) => write!(f, "Service auth token is for {lxm}"),
+            AuthError::BadSignature => write!(f, "Service auth token signature is invalid"),
+            AuthError::Resolve(reason) => write!(f, "Could not resolve requester DID: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+/// Looks up the atproto signing key of a DID. The real one reads DID documents over HTTP,
+/// tests hand keys over directly
+#[async_trait]
+pub trait DidResolver: Send + Sync {
+    async fn resolve_signing_key(&self, did: &str) -> Result<PublicKey, AuthError>;
+
+    /// Drops a key that no longer checks out, returns whether one was cached
+    async fn forget_signing_key(&self, _did: &str) -> bool {
+        false
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DidDocument {
+    #[serde(default)]
+    verification_method: Vec<VerificationMethod>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VerificationMethod {
+    id: String,
+    public_key_multibase: Option<String>,
+}
+
+/// Pulls the `#atproto` signing key out of a DID document
+pub fn signing_key_from_did_document(document: &str) -> Result<PublicKey, AuthError> {
+    let document: DidDocument = serde_json::from_str(document)
+        .map_err(|err| AuthError::Resolve(format!("Bad DID document: {err}")))?;
+    let multibase_key = document
+        .verification_method
+        .into_iter()
+        .find(|method| method.id.ends_with("#atproto"))
+        .and_then(|method| method.public_key_multibase)
+        .ok_or_else(|| AuthError::Resolve("DID document has no #atproto key".to_string()))?;
+    parse_multibase_key(&multibase_key)
+}
+
+pub fn parse_multibase_key(multibase_key: &str) -> Result<PublicKey, AuthError> {
+    let (_, bytes) = multibase::decode(multibase_key)
+        .map_err(|err| AuthError::Resolve(format!("Bad publicKeyMultibase: {err}")))?;
+    if let Some(key) = bytes.strip_prefix(&SECP256K1_PREFIX) {
+        return k256::ecdsa::VerifyingKey::from_sec1_bytes(key)
+            .map(PublicKey::K256)
+            .map_err(|err| AuthError::Resolve(format!("Bad secp256k1 key: {err}")));
+    }
+    if let Some(key) = bytes.strip_prefix(&P256_PREFIX) {
+        return p256::ecdsa::VerifyingKey::from_sec1_bytes(key)
+            .map(PublicKey::P256)
+            .map_err(|err| AuthError::Resolve(format!("Bad P-256 key: {err}")));
+    }
+    Err(AuthError::Resolve("Unknown key type".to_string()))
+}
+
+/// Resolves did:plc from the PLC directory and did:web from the domain's did.json
+pub struct HttpDidResolver {
+    client: reqwest::Client,
+    plc_directory: String,
+    cache: Mutex<HashMap<String, (PublicKey, Instant)>>,
+}
+
+impl HttpDidResolver {
+    pub fn new(plc_directory: impl Into<String>) -> Self {
+        Self {
+            //A DID document that redirects could point us anywhere
+            client: reqwest::Client::builder()
+                .redirect(reqwest::redirect::Policy::none())
+                .timeout(RESOLVE_TIMEOUT)
+                .build()
+                .expect("TLS backend can be initialized"),
+            plc_directory: plc_directory.into(),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn did_document_url(&self, did: &str) -> Result<String, AuthError> {
+        if let Some(id) = did.strip_prefix("did:plc:") {
+            if id.len() != PLC_ID_LENGTH
+                || !id.bytes().all(|c| matches!(c, b'a'..=b'z' | b'2'..=b'7'))
+            {
+                return Err(AuthError::Resolve(format!("{did} is not a valid did:plc")));
+            }
+            Ok(format!(
+                "{}/{did}",
+                self.plc_directory.trim_end_matches('/')
+            ))
+        } else if let Some(host) = did.strip_prefix("did:web:") {
+            if !is_public_hostname(host) {
+                return Err(AuthError::Resolve(format!(
+                    "{did} is not on a public hostname"
+                )));
+            }
+            Ok(format!("https://{host}/.well-known/did.json"))
+        } else {
+            Err(AuthError::Resolve(format!("Unsupported DID method {did}")))
+        }
+    }
+
+    async fn cache_key(&self, did: &str, key: PublicKey) {
+        let mut cache = self.cache.lock().await;
+        if cache.len() >= KEY_CACHE_CAPACITY {
+            cache.retain(|_, (_, resolved_at)| resolved_at.elapsed() < KEY_CACHE_TTL);
+        }
+        if cache.len() >= KEY_CACHE_CAPACITY {
+            let oldest = cache
+                .iter()
+                .min_by_key(|(_, (_, resolved_at))| *resolved_at)
+                .map(|(did, _)| did.clone());
+            if let Some(oldest) = oldest {
+                cache.remove(&oldest);
+            }
+        }
+        cache.insert(did.to_string(), (key, Instant::now()));
+    }
+}
+
+/// A lowercase DNS name with a dot in it, no IP address, port, path or private name. Ports and
+/// paths would come percent encoded, and IPv6 addresses have colons, so none of them pass
+pub fn is_public_hostname(host: &str) -> bool {
+    if host.is_empty() || host.len() > 253 {
+        return false;
+    }
+    let labels: Vec<&str> = host.split('.').collect();
+    let valid_labels = labels.len() >= 2
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .bytes()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        });
+    //An all number top level domain would make it an IPv4 address
+    let top_level = labels.last().copied().unwrap_or_default();
+    valid_labels
+        && !top_level.bytes().all(|c| c.is_ascii_digit())
+        && !PRIVATE_HOST_SUFFIXES.iter().any(|suffix| {
+            host == *suffix
+                || host
+                    .strip_suffix(suffix)
+                    .is_some_and(|rest| rest.ends_with('.'))
+        })
+}
+
+#[async_trait]
+impl DidResolver for HttpDidResolver {
+    async fn resolve_signing_key(&self, did: &str) -> Result<PublicKey, AuthError> {
+        if let Some((key, resolved_at)) = self.cache.lock().await.get(did) {
+            if resolved_at.elapsed() < KEY_CACHE_TTL {
+                return Ok(key.clone());
+            }
+        }
+
+        let url = self.did_document_url(did)?;
+        let document = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| AuthError::Resolve(err.to_string()))?
+            .text()
+            .await
+            .map_err(|err| AuthError::Resolve(err.to_string()))?;
+        let key = signing_key_from_did_document(&document)?;
+        self.cache_key(did, key.clone()).await;
+        Ok(key)
+    }
+
+    async fn forget_signing_key(&self, did: &str) -> bool {
+        self.cache.lock().await.remove(did).is_some()
+    }
+}
+
+/// Resolver for tests and local runs that already knows every key
+#[derive(Default)]
+pub struct StaticDidResolver {
+    pub keys: HashMap<String, PublicKey>,
+}
+
+#[async_trait]
+impl DidResolver for StaticDidResolver {
+    async fn resolve_signing_key(&self, did: &str) -> Result<PublicKey, AuthError> {
+        self.keys
+            .get(did)
+            .cloned()
+            .ok_or_else(|| AuthError::Resolve(format!("Unknown DID {did}")))
+    }
+}
+
+#[derive(Deserialize)]
+struct JwtHeader {
+    alg: String,
+}
+
+#[derive(Deserialize)]
+struct JwtClaims {
+    iss: String,
+    aud: String,
+    iat: i64,
+    exp: i64,
+    lxm: String,
+}
+
+/// Checks a service auth JWT was made for us and this method, is short lived, and signed by the
+/// issuer's key. Returns the requester DID
+pub async fn verify_service_jwt(
+    token: &str,
+    service_did: &str,
+    method: &str,
+    resolver: &dyn DidResolver,
+) -> Result<String, AuthError> {
+    let token = token.strip_prefix("Bearer ").unwrap_or(token).trim();
+    let parts: Vec<&str> = token.split('.').collect();
+    let [header, claims, signature] = parts.as_slice() else {
+        return Err(AuthError::Malformed("Expected three parts".to_string()));
+    };
+
+    let header: JwtHeader = decode_part(header)?;
+    let claims: JwtClaims = decode_part(claims)?;
+    let now = Utc::now().timestamp();
+    if claims.exp <= now {
+        return Err(AuthError::Expired);
+    }
+    if claims.iat > now + MAX_CLOCK_SKEW {
+        return Err(AuthError::BadLifetime(
+            "was issued in the future".to_string(),
+        ));
+    }
+    if claims.exp - now > MAX_TOKEN_LIFETIME {
+        return Err(AuthError::BadLifetime(format!(
+            "is valid for more than {MAX_TOKEN_LIFETIME}s"
+        )));
+    }
+    if claims.aud != service_did {
+        return Err(AuthError::WrongAudience(claims.aud));
+    }
+    if claims.lxm != method {
+        return Err(AuthError::WrongMethod(claims.lxm));
+    }
+
+    //Services sign as did#service_id with a key other than #atproto, only users can ask for a feed
+    if !claims.iss.starts_with("did:") || claims.iss.contains('#') {
+        return Err(AuthError::Malformed(format!(
+            "{} is not a user DID",
+            claims.iss
+        )));
+    }
+    let requester_did = claims.iss;
+    let signature = URL_SAFE_NO_PAD
+        .decode(signature)
+        .map_err(|err| AuthError::Malformed(err.to_string()))?;
+    let signed_part = &token[..token.rfind('.').unwrap_or(0)];
+
+    let key = resolver.resolve_signing_key(&requester_did).await?;
+    match check_signature(&header.alg, key, signed_part, &signature) {
+        //The user may have rotated their key since it was cached
+        Err(AuthError::BadSignature) if resolver.forget_signing_key(&requester_did).await => {
+            let key = resolver.resolve_signing_key(&requester_did).await?;
+            check_signature(&header.alg, key, signed_part, &signature)?;
+        }
+        result => result?,
+    }
+
+    Ok(requester_did)
+}
+
+fn check_signature(
+    alg: &str,
+    key: PublicKey,
+    signed_part: &str,
+    signature: &[u8],
+) -> Result<(), AuthError> {
+    match (alg, key) {
+        ("ES256K", PublicKey::K256(key)) => {
+            use k256::ecdsa::signature::Verifier;
+            let signature = k256::ecdsa::Signature::from_slice(signature)
+                .map_err(|_| AuthError::BadSignature)?;
+            key.verify(signed_part.as_bytes(), &signature)
+                .map_err(|_| AuthError::BadSignature)
+        }
+        ("ES256", PublicKey::P256(key)) => {
+            use p256::ecdsa::signature::Verifier;
+            let signature = p256::ecdsa::Signature::from_slice(signature)
+                .map_err(|_| AuthError::BadSignature)?;
+            key.verify(signed_part.as_bytes(), &signature)
+                .map_err(|_| AuthError::BadSignature)
+        }
+        (alg, _) => Err(AuthError::UnsupportedAlgorithm(alg.to_string())),
+    }
+}
+
+fn decode_part<T: for<'de> Deserialize<'de>>(part: &str) -> Result<T, AuthError> {
+    let bytes = URL_SAFE_NO_PAD
+        .decode(part)
+        .map_err(|err| AuthError::Malformed(err.to_string()))?;
+    serde_json::from_slice(&bytes).map_err(|err| AuthError::Malformed(err.to_string()))
+}
@@ -0,0 +1,82 @@
+use crate::appview::AppViewClient;
+use crate::ingest::{ingest_post, IngestOutcome};
+use crate::metrics::POSTS_STORED;
+use crate::store::FeedStore;
+use chrono::DateTime;
+use log::info;
+
+//** NOTICE **
+// A fresh feed.db is empty until the firehose catches enough posts. Backfill reads the history of
+// authors we know post good stuff from the AppView and runs it through the same ingest as live posts
+//
+
+#[derive(Debug, Default, PartialEq)]
+pub struct BackfillReport {
+    pub seen: usize,
+    pub stored: usize,
+    pub shares: usize,
+    /// Not for the feed, removed by a moderator before or from a blocked author
+    pub rejected: usize,
+    /// Reposts and posts older than the date limit
+    pub skipped: usize,
+}
+
+/// Pages back through an author's posts (DID or handle) until one was indexed before `since`
+/// (unix seconds). Posts are stored with when they were made, not when they were backfilled
+pub async fn backfill_author(
+    store: &dyn FeedStore,
+    appview: &AppViewClient,
+    actor: &str,
+    since: i64,
+) -> anyhow::Result<BackfillReport> {
+    let mut report = BackfillReport::default();
+    let mut cursor: Option<String> = None;
+    loop {
+        let page = appview.get_author_feed(actor, cursor.as_deref()).await?;
+        for item in page.feed {
+            report.seen += 1;
+            if item.reason.is_some() {
+                report.skipped += 1;
+                continue;
+            }
+            //indexedAt goes in order unlike createdAt, which can be anything the client wanted
+            let indexed_at = DateTime::parse_from_rfc3339(&item.post.indexed_at)
+                .map(|indexed_at| indexed_at.timestamp())
+                .unwrap_or(i64::MIN);
+            if indexed_at < since {
+                report.skipped += 1;
+                info!("Backfilled {actor} back to {since}");
+                return Ok(report);
+            }
+
+            let timestamp = item
+                .post
+                .record
+                .created_at_timestamp()
+                .unwrap_or(indexed_at);
+            let post = item.post.record.into_incoming_post(
+                item.post.uri,
+                item.post.author.did,
+                Some(item.post.cid),
+                timestamp,
+            );
+            match ingest_post(store, post).await? {
+                IngestOutcome::Stored => {
+                    POSTS_STORED.with_label_values(&["backfill"]).inc();
+                    report.stored += 1;
+                }
+                IngestOutcome::CountedAsShare => report.shares += 1,
+                IngestOutcome::Rejected | IngestOutcome::Moderated | IngestOutcome::Blocked => {
+                    report.rejected += 1
+                }
+            }
+        }
+
+        match page.cursor {
+            Some(next) if cursor.as_deref() != Some(next.as_str()) => cursor = Some(next),
+            _ => break,
+        }
+    }
+    info!("Backfilled all of {actor}");
+    Ok(report)
+}
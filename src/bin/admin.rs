@@ -2,8 +2,20 @@ use atrium_api::app::bsky::feed::defs::{PostView, PostViewEmbedRefs};
 use atrium_api::client::AtpServiceClient;
 use atrium_api::types::{Union, Unknown};
 use atrium_xrpc_client::reqwest::ReqwestClient;
-use bsky_thread_and_blog_feed::db::{delete_post, load_feed_from_db};
+use bsky_thread_and_blog_feed::appview::AppViewClient;
+use bsky_thread_and_blog_feed::backfill::backfill_author;
+use bsky_thread_and_blog_feed::config::{Config, ConfigArgs};
+use bsky_thread_and_blog_feed::handler::FeedIngestHandler;
+use bsky_thread_and_blog_feed::health::FirehoseHealth;
+use bsky_thread_and_blog_feed::models::{AuthorList, InteractionCounts};
+use bsky_thread_and_blog_feed::pipeline::IngestPipeline;
+use bsky_thread_and_blog_feed::replay::{replay_file, ReplaySpeed};
+use bsky_thread_and_blog_feed::store::{open_store, FeedResult, SharedStore};
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use clap::{Parser, Subcommand};
+use color_eyre::eyre::eyre;
 use color_eyre::Result;
+use dotenv::dotenv;
 use ipld_core::ipld::Ipld;
 use log::info;
 use ratatui::style::palette::tailwind;
@@ -20,25 +32,128 @@ use ratatui::{
 };
 use skyfeed::Uri;
 use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
     sync::{Arc, RwLock},
     time::Duration,
 };
 use tokio::sync::Mutex;
-use tokio_rusqlite::Connection;
 use tokio_stream::StreamExt;
 
+#[derive(Parser)]
+#[command(about = "Moderate the posts in the feed")]
+struct Cli {
+    #[command(flatten)]
+    config: ConfigArgs,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Imports the post history of authors through the same classifier as the firehose
+    Backfill {
+        /// DIDs or handles of the authors
+        #[arg(required = true)]
+        authors: Vec<String>,
+        /// Oldest day to import posts from, defaults to 30 days ago
+        #[arg(long)]
+        since: Option<NaiveDate>,
+    },
+    /// Plays recorded Jetstream events (one JSON event per line) through the feed's handler
+    Replay {
+        /// The recording
+        file: PathBuf,
+        /// How many times faster than recorded to play it, plays it as fast as possible if not set
+        #[arg(long)]
+        speed: Option<f64>,
+    },
+    /// Takes a post out of the feed for good, nothing stores it again until it is restored
+    Remove {
+        /// at:// uri of the post
+        uri: String,
+        #[arg(long, default_value = DEFAULT_REMOVAL_REASON)]
+        reason: String,
+    },
+    /// Lets a removed post back into the feed
+    Restore {
+        /// at:// uri of the post
+        uri: String,
+    },
+    /// Lists removed posts with why and when
+    Removed,
+    /// Rejects every post from an author before it is classified
+    Block {
+        did: String,
+        #[arg(long)]
+        reason: Option<String>,
+        /// Last day of the block, blocked for good if not set
+        #[arg(long)]
+        until: Option<NaiveDate>,
+    },
+    Unblock {
+        did: String,
+    },
+    /// Boosts the posts of an author that makes it into the feed
+    Trust {
+        did: String,
+        #[arg(long)]
+        reason: Option<String>,
+        /// Last day of the boost, trusted for good if not set
+        #[arg(long)]
+        until: Option<NaiveDate>,
+    },
+    Untrust {
+        did: String,
+    },
+    /// Lists blocked and trusted authors
+    Authors,
+}
+
+/// How far back a backfill goes when no --since is given
+const DEFAULT_BACKFILL_DAYS: i64 = 30;
+/// Reason recorded for posts removed with d in the TUI or without --reason
+const DEFAULT_REMOVAL_REASON: &str = "Removed in the admin tool";
+
 #[tokio::main]
 async fn main() -> Result<()> {
     color_eyre::install()?;
+    dotenv().ok();
+    let cli = Cli::parse();
+    //Load the config before taking over the terminal so errors are readable
+    let config = Config::load(cli.config)?;
+    match cli.command {
+        Some(Command::Backfill { authors, since }) => {
+            return backfill(&config, authors, since).await
+        }
+        Some(Command::Replay { file, speed }) => return replay(&config, &file, speed).await,
+        Some(Command::Remove { uri, reason }) => return remove(&config, uri, reason).await,
+        Some(Command::Restore { uri }) => return restore(&config, uri).await,
+        Some(Command::Removed) => return removed(&config).await,
+        Some(Command::Block { did, reason, until }) => {
+            return list_author(&config, AuthorList::Blocked, did, reason, until).await
+        }
+        Some(Command::Unblock { did }) => {
+            return unlist_author(&config, AuthorList::Blocked, did).await
+        }
+        Some(Command::Trust { did, reason, until }) => {
+            return list_author(&config, AuthorList::Trusted, did, reason, until).await
+        }
+        Some(Command::Untrust { did }) => {
+            return unlist_author(&config, AuthorList::Trusted, did).await
+        }
+        Some(Command::Authors) => return authors(&config).await,
+        None => {}
+    }
+    let client = AtpServiceClient::new(ReqwestClient::new(&config.appview_url));
+    let store = connect(&config).await?;
     let terminal = ratatui::init();
-    let client = AtpServiceClient::new(ReqwestClient::new("https://public.api.bsky.app"));
-    let connection = Connection::open("./feed.db").await?;
 
     let app = App {
         should_quit: false,
         feed_display: FeedDisplayWidget {
             state: Arc::new(RwLock::new(FeedPostState::default())),
-            db: connection,
+            store,
             bsky_client: Arc::new(Mutex::new(client)),
             feed_offset: 0,
             feed_limit: 25,
@@ -49,6 +164,145 @@ async fn main() -> Result<()> {
     app_result
 }
 
+/// The db the config points at, migrated
+async fn connect(config: &Config) -> Result<SharedStore> {
+    open_store(config).await.map_err(|err| eyre!(err))
+}
+
+async fn backfill(config: &Config, authors: Vec<String>, since: Option<NaiveDate>) -> Result<()> {
+    let store = connect(config).await?;
+    let appview = AppViewClient::new(config.appview_url.clone());
+    let since = match since {
+        Some(since) => since.and_time(NaiveTime::MIN).and_utc().timestamp(),
+        None => (Utc::now() - chrono::Duration::days(DEFAULT_BACKFILL_DAYS)).timestamp(),
+    };
+    for author in authors {
+        match backfill_author(store.as_ref(), &appview, &author, since).await {
+            Ok(report) => println!(
+                "{author}: {} seen, {} stored, {} shares of stored links, {} not for the feed, {} skipped",
+                report.seen, report.stored, report.shares, report.rejected, report.skipped
+            ),
+            Err(err) => eprintln!("{author}: backfill failed: {err}"),
+        }
+    }
+    Ok(())
+}
+
+async fn replay(config: &Config, file: &Path, speed: Option<f64>) -> Result<()> {
+    let store = connect(config).await?;
+    let mut handler = FeedIngestHandler {
+        pipeline: IngestPipeline::start(store, "replay", config.ingest.clone()),
+        appview: Arc::new(AppViewClient::new(config.appview_url.clone())),
+        //Without a publisher likes are stored but nothing gets curated
+        feed_author_did: config.publisher_did.clone().unwrap_or_default(),
+        firehose: Arc::new(FirehoseHealth::new(
+            config.firehose_stale_after,
+            config.firehose_max_lag,
+        )),
+    };
+    let speed = speed.map_or(ReplaySpeed::Instant, ReplaySpeed::Scaled);
+    let report = replay_file(file, &mut handler, speed).await?;
+    println!(
+        "{}: {} events replayed, {} not for the feed, {} malformed",
+        file.display(),
+        report.events,
+        report.ignored,
+        report.malformed
+    );
+    Ok(())
+}
+
+async fn remove(config: &Config, uri: String, reason: String) -> Result<()> {
+    let store = connect(config).await?;
+    store
+        .moderate_post(uri.clone(), reason)
+        .await
+        .map_err(|err| eyre!(err))?;
+    println!("Removed {uri}");
+    Ok(())
+}
+
+async fn restore(config: &Config, uri: String) -> Result<()> {
+    let store = connect(config).await?;
+    if store
+        .restore_post(uri.clone())
+        .await
+        .map_err(|err| eyre!(err))?
+    {
+        println!("Restored {uri}");
+    } else {
+        println!("{uri} was not removed");
+    }
+    Ok(())
+}
+
+async fn removed(config: &Config) -> Result<()> {
+    let store = connect(config).await?;
+    for tombstone in store.list_tombstones().await.map_err(|err| eyre!(err))? {
+        let removed_at = DateTime::from_timestamp(tombstone.removed_at, 0)
+            .map(|removed_at| removed_at.to_rfc3339())
+            .unwrap_or_default();
+        println!("{removed_at} {} {}", tombstone.uri, tombstone.reason);
+    }
+    Ok(())
+}
+
+async fn list_author(
+    config: &Config,
+    list: AuthorList,
+    did: String,
+    reason: Option<String>,
+    until: Option<NaiveDate>,
+) -> Result<()> {
+    let store = connect(config).await?;
+    //Through the end of the last day
+    let expires_at = until
+        .and_then(|until| until.succ_opt())
+        .map(|until| until.and_time(NaiveTime::MIN).and_utc().timestamp());
+    store
+        .add_to_author_list(list, did.clone(), reason, expires_at)
+        .await
+        .map_err(|err| eyre!(err))?;
+    println!("Added {did} to {}", list.table());
+    Ok(())
+}
+
+async fn unlist_author(config: &Config, list: AuthorList, did: String) -> Result<()> {
+    let store = connect(config).await?;
+    if store
+        .remove_from_author_list(list, did.clone())
+        .await
+        .map_err(|err| eyre!(err))?
+    {
+        println!("Removed {did} from {}", list.table());
+    } else {
+        println!("{did} was not in {}", list.table());
+    }
+    Ok(())
+}
+
+async fn authors(config: &Config) -> Result<()> {
+    let store = connect(config).await?;
+    for list in [AuthorList::Blocked, AuthorList::Trusted] {
+        println!("{}:", list.table());
+        for author in store.list_authors(list).await.map_err(|err| eyre!(err))? {
+            let expires = match author
+                .expires_at
+                .and_then(|at| DateTime::from_timestamp(at, 0))
+            {
+                Some(expires_at) => format!("until {}", expires_at.to_rfc3339()),
+                None => "for good".to_string(),
+            };
+            println!(
+                "  {} {expires} {}",
+                author.did,
+                author.reason.unwrap_or_default()
+            );
+        }
+    }
+    Ok(())
+}
+
 // #[derive(Debug)]
 struct App {
     should_quit: bool,
@@ -85,8 +339,16 @@ impl App {
     async fn handle_event(&mut self, event: &Event) {
         if let Event::Key(key) = event {
             if key.kind == KeyEventKind::Press {
+                if self.feed_display.is_typing_search() {
+                    if self.feed_display.type_search(key.code) {
+                        self.feed_display.feed_offset = 0;
+                        self.feed_display.clone().fetch_posts().await;
+                    }
+                    return;
+                }
                 match key.code {
                     KeyCode::Char('q') | KeyCode::Esc => self.should_quit = true,
+                    KeyCode::Char('/') => self.feed_display.start_search(),
                     KeyCode::Char('j') | KeyCode::Down => {
                         self.feed_display.clone().scroll_down().await
                     }
@@ -106,7 +368,7 @@ impl App {
 #[derive(Clone)]
 struct FeedDisplayWidget {
     state: Arc<RwLock<FeedPostState>>,
-    db: Connection,
+    store: SharedStore,
     bsky_client: Arc<Mutex<AtpServiceClient<ReqwestClient>>>,
     feed_offset: u64,
     feed_limit: u64,
@@ -115,6 +377,12 @@ struct FeedDisplayWidget {
 #[derive(Debug, Default)]
 struct FeedPostState {
     posts: Vec<PostView>,
+    //Show more/less feedback from sendInteractions keyed by post uri
+    interactions: HashMap<String, InteractionCounts>,
+    //What is being typed after pressing /, None when not typing a search
+    search_input: Option<String>,
+    //Shows posts matching this instead of the feed
+    search_query: Option<String>,
     loading_state: LoadingState,
     table_state: TableState,
 }
@@ -143,9 +411,38 @@ impl FeedDisplayWidget {
     pub async fn fetch_posts(self) {
         self.set_loading_state(LoadingState::Loading);
 
-        let posts = load_feed_from_db(&self.db, self.feed_limit, self.feed_offset).await;
+        let search_query = self.state.read().unwrap().search_query.clone();
+        let posts_uris: FeedResult<Vec<String>> = match search_query {
+            Some(query) => self
+                .store
+                .search_posts(query, self.feed_limit, self.feed_offset)
+                .await
+                .map(|results| results.into_iter().map(|result| result.uri).collect()),
+            None => self
+                .store
+                .load_feed(self.feed_limit, self.feed_offset, None, None)
+                .await
+                .map(|posts| posts.into_iter().map(|post| post.uri).collect()),
+        };
+        let posts_uris = match posts_uris {
+            Ok(posts_uris) => posts_uris,
+            Err(error) => {
+                self.on_err(error.to_string());
+                return;
+            }
+        };
+        if posts_uris.is_empty() {
+            self.on_load(vec![]);
+            return;
+        }
 
-        let posts_uris: Vec<String> = posts.iter().map(|post| post.uri.clone()).collect();
+        match self.store.get_interaction_counts(posts_uris.clone()).await {
+            Ok(interactions) => self.state.write().unwrap().interactions = interactions,
+            Err(error) => {
+                self.on_err(error.to_string());
+                return;
+            }
+        }
 
         let client = self.bsky_client.lock().await;
         //TODO need pagination can only get 25 at a time
@@ -170,7 +467,7 @@ impl FeedDisplayWidget {
             }
         }
         self.set_loading_state(LoadingState::Loaded);
-        info!("Loaded {} posts from the feed", posts.len());
+        info!("Loaded {} posts from the feed", posts_uris.len());
     }
     fn on_load(&self, posts: Vec<PostView>) {
         let mut state = self.state.write().unwrap();
@@ -181,6 +478,41 @@ impl FeedDisplayWidget {
         }
     }
 
+    fn is_typing_search(&self) -> bool {
+        self.state.read().unwrap().search_input.is_some()
+    }
+
+    fn start_search(&self) {
+        self.state.write().unwrap().search_input = Some(String::new());
+    }
+
+    /// Handles a key while typing a search, returns true once it is submitted with enter
+    fn type_search(&self, key: KeyCode) -> bool {
+        let mut state = self.state.write().unwrap();
+        match key {
+            KeyCode::Char(typed) => {
+                if let Some(input) = state.search_input.as_mut() {
+                    input.push(typed);
+                }
+            }
+            KeyCode::Backspace => {
+                if let Some(input) = state.search_input.as_mut() {
+                    input.pop();
+                }
+            }
+            KeyCode::Esc => state.search_input = None,
+            KeyCode::Enter => {
+                let query = state.search_input.take().unwrap_or_default();
+                //Searching for nothing goes back to the feed
+                state.search_query =
+                    Some(query.trim().to_string()).filter(|query| !query.is_empty());
+                return true;
+            }
+            _ => {}
+        }
+        false
+    }
+
     fn on_err(&self, error_message: String) {
         self.set_loading_state(LoadingState::Error(error_message));
     }
@@ -223,7 +555,13 @@ impl FeedDisplayWidget {
             Some(selected) => {
                 let selected_post_view = self.state.read().unwrap().posts[selected].clone();
                 let post_uri = selected_post_view.uri.clone();
-                delete_post(&self.db, post_uri).await;
+                if let Err(error) = self
+                    .store
+                    .moderate_post(post_uri, DEFAULT_REMOVAL_REASON.to_string())
+                    .await
+                {
+                    self.on_err(error.to_string());
+                }
             }
         }
         // self.fetch_posts().await;
@@ -236,10 +574,15 @@ impl Widget for &FeedDisplayWidget {
 
         // a block with a right aligned title with the loading state on the right
         let loading_state = Line::from(format!("{:?}", state.loading_state)).right_aligned();
+        let title = match (&state.search_input, &state.search_query) {
+            (Some(input), _) => format!("Search: {input}▏"),
+            (None, Some(query)) => format!("Posts matching \"{query}\""),
+            (None, None) => "Posts currently showing in the feed".to_string(),
+        };
         let block = Block::bordered()
-            .title("Posts currently showing in the feed")
+            .title(title)
             .title(loading_state)
-            .title_bottom("j/k to scroll | r to refresh | d to delete | q to quit");
+            .title_bottom("j/k to scroll | r to refresh | / to search | d to remove | q to quit");
 
         let post_content = state.posts.iter().enumerate().map(|(i, post_view)| {
             let post_text: String = match &post_view.record {
@@ -258,6 +601,11 @@ impl Widget for &FeedDisplayWidget {
             let author = post_view.author.handle.clone();
             let author_string = author.as_str();
             let likes = post_view.like_count.unwrap_or(0);
+            let interactions = state
+                .interactions
+                .get(&post_view.uri)
+                .cloned()
+                .unwrap_or_default();
 
             // let mut pinned = "📌";
             // if i > 0 {
@@ -285,7 +633,8 @@ impl Widget for &FeedDisplayWidget {
             };
             let url = format!("https://atp.tools/{}", post_view.uri);
             [Cell::from(Text::from(format!(
-                "{one_liner}\n@{author_string} | {likes} likes | {media_type}\n{url}"
+                "{one_liner}\n@{author_string} | {likes} likes | {media_type} | 👍 {} more 👎 {} less\n{url}",
+                interactions.show_more, interactions.show_less
             )))]
             .into_iter()
             .collect::<Row>()
@@ -1,49 +1,184 @@
-use atrium_api::app::bsky::feed::defs::PostView;
-use atrium_api::app::bsky::feed::get_posts::{Error, Output};
-use atrium_api::client::AtpServiceClient;
-use atrium_api::types::{LimitedNonZeroU8, Unknown};
-use atrium_xrpc_client::reqwest::ReqwestClient;
-use bsky_thread_and_blog_feed::db::{get_posts_count, initialize_db, load_feed_from_db};
-use bsky_thread_and_blog_feed::does_the_post_belong_to_the_feed;
-use bsky_thread_and_blog_feed::models::{PostScoring, TextInPost};
+use bsky_thread_and_blog_feed::appview::AppViewClient;
+use bsky_thread_and_blog_feed::auth::HttpDidResolver;
+use bsky_thread_and_blog_feed::config::{Config, ConfigArgs, EventSourceConfig};
+use bsky_thread_and_blog_feed::events::FeedEvent;
+use bsky_thread_and_blog_feed::handler::FeedIngestHandler;
+use bsky_thread_and_blog_feed::health::FirehoseHealth;
+use bsky_thread_and_blog_feed::ingest::{
+    EmbedImage, ExternalLink, PostRecord, RecordEmbed, ReplyRef, StrongRef,
+};
+use bsky_thread_and_blog_feed::metrics::count_db_error;
+use bsky_thread_and_blog_feed::pipeline::IngestPipeline;
+use bsky_thread_and_blog_feed::server::{
+    feed_skeleton, start_server, FeedGeneratorIdentity, GetFeedSkeletonParams, ServerState,
+};
+use bsky_thread_and_blog_feed::source::{
+    ChannelSource, EventSource, JetstreamSource, ReplaySource,
+};
+use bsky_thread_and_blog_feed::store::open_store;
 use chrono::Utc;
+use clap::Parser;
 use dotenv::dotenv;
-use ipld_core::ipld::Ipld;
 use log::{error, info};
 use skyfeed::{Did, Embed, Feed, FeedHandler, FeedResult, MediaEmbed, Post, Request, Uri};
 use std::{sync::Arc, time::Duration};
-use tokio::sync::Mutex;
-use tokio_rusqlite::{params, Connection};
+use tokio::sync::mpsc;
+
+/// How often the firehose cursor is written to the db
+const CURSOR_SAVE_INTERVAL: Duration = Duration::from_secs(5);
+/// skyfeed events waiting to be handled before skyfeed has to wait too
+const SKYFEED_EVENT_BUFFER: usize = 1024;
+/// How long we remember which posts a viewer was served
+const SERVED_POSTS_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+#[derive(Parser)]
+#[command(about = "Bluesky feed generator for tech threads and blog posts")]
+struct Cli {
+    #[command(flatten)]
+    config: ConfigArgs,
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     dotenv().ok();
-    let db = Connection::open("./feed.db").await?;
-    initialize_db(&db).await;
-    let client = AtpServiceClient::new(ReqwestClient::new("https://public.api.bsky.app"));
-    let publisher_did = std::env::var("PUBLISHER_DID").expect("PUBLISHER_DID not set");
-    let mut feed = MyFeed {
-        handler: MyFeedHandler {
-            db: db.clone(),
-            bsky_client: Arc::new(Mutex::new(client)),
-            feed_author_did: publisher_did,
+    let config = Config::load(Cli::parse().config)?;
+    let publisher_did = config.publisher_did()?;
+    let feed_generator_hostname = config.feed_generator_hostname()?;
+
+    let store = open_store(&config).await?;
+    let firehose = Arc::new(FirehoseHealth::new(
+        config.firehose_stale_after,
+        config.firehose_max_lag,
+    ));
+    let mut ingest = FeedIngestHandler {
+        pipeline: IngestPipeline::start(
+            store.clone(),
+            config.event_source.name(),
+            config.ingest.clone(),
+        ),
+        appview: Arc::new(AppViewClient::new(config.appview_url.clone())),
+        feed_author_did: publisher_did.clone(),
+        firehose: firehose.clone(),
+    };
+    //Only moves once the events are stored
+    let cursor = ingest.pipeline.cursor();
+    let resumes_from_cursor = config.event_source.resumes_from_cursor();
+    if resumes_from_cursor {
+        match cursor.load(store.as_ref()).await {
+            Ok(Some(saved)) => info!("Resuming from cursor {saved}"),
+            Ok(None) => info!("No saved cursor, starting from live"),
+            Err(err) => {
+                error!("Failed to load the cursor: {err}");
+                count_db_error("load_cursor");
+            }
+        }
+    } else {
+        info!(
+            "{} cannot resume from a cursor, events sent while the feed was down are missed",
+            config.event_source.name()
+        );
+    }
+
+    //skyfeed only connects when it is the source, the other sources are read here
+    let (mut source, skyfeed_events): (Box<dyn EventSource>, Option<mpsc::Sender<FeedEvent>>) =
+        match &config.event_source {
+            EventSourceConfig::Firehose => {
+                let (sender, receiver) = mpsc::channel(SKYFEED_EVENT_BUFFER);
+                (Box::new(ChannelSource::new(receiver)), Some(sender))
+            }
+            EventSourceConfig::Jetstream { url } => (
+                Box::new(JetstreamSource::new(url.clone(), cursor.clone())),
+                None,
+            ),
+            EventSourceConfig::Replay { file, speed } => (
+                Box::new(ReplaySource {
+                    path: file.clone(),
+                    speed: *speed,
+                }),
+                None,
+            ),
+        };
+    info!("Reading events from {}", config.event_source.name());
+    let source_task = tokio::spawn(async move {
+        match source.run(&mut ingest).await {
+            Ok(()) => info!("Event source has no more events"),
+            Err(err) => error!("Event source stopped: {err}"),
+        }
+    });
+
+    //Every feed published from this generator, the first one is also handed to skyfeed
+    let skyfeed_name = config.main_feed_name().to_string();
+    let server_state = ServerState {
+        store: store.clone(),
+        max_posts_per_author: config.max_posts_per_author(),
+        identity: FeedGeneratorIdentity {
+            hostname: feed_generator_hostname,
+            publisher_did,
+            feed_names: config.feed_names.clone(),
         },
+        did_resolver: Arc::new(HttpDidResolver::new(config.plc_directory.clone())),
+        hide_seen_posts: config.hide_seen_posts,
+        firehose,
     };
+    //skyfeed's server can only send bare uris, this one sends feedContext too and takes sendInteractions
+    let xrpc_server = start_server(
+        server_state.clone(),
+        (config.bind_address, config.xrpc_port),
+    );
+
+    let cursor_store = store.clone();
+    let mut cursor_interval = tokio::time::interval(CURSOR_SAVE_INTERVAL);
+    let cursor_task = tokio::spawn(async move {
+        //Nothing would read it back
+        if !resumes_from_cursor {
+            return;
+        }
+        loop {
+            cursor_interval.tick().await;
+            if let Err(err) = cursor.save(cursor_store.as_ref()).await {
+                error!("Failed to save the firehose cursor: {err}");
+                count_db_error("save_cursor");
+            }
+        }
+    });
 
-    let mut cleanup_interval = tokio::time::interval(Duration::from_secs(10));
+    let retention = config.retention.clone();
+    let mut cleanup_interval = tokio::time::interval(retention.interval);
     let cleanup_task = tokio::spawn(async move {
         loop {
             cleanup_interval.tick().await;
-            cleanup_posts(&db).await;
+            if let Err(err) = store
+                .apply_retention(&retention, Utc::now().timestamp())
+                .await
+            {
+                error!("Failed to archive old posts: {err}");
+                count_db_error("apply_retention");
+            }
+            let forget_before = Utc::now().timestamp() - SERVED_POSTS_MAX_AGE.as_secs() as i64;
+            if let Err(err) = store.forget_served_posts_before(forget_before).await {
+                error!("Failed to forget old served posts: {err}");
+                count_db_error("forget_served_posts");
+            }
         }
     });
 
-    tokio::join!(
-        feed.start("TechThreadsAndMore", ([192, 168, 1, 221], 3030)),
-        cleanup_task
-    )
-    .1
-    .expect("Starting tasks failed")
+    let skyfeed = async move {
+        if let Some(events) = skyfeed_events {
+            let mut feed = MyFeed {
+                handler: MyFeedHandler {
+                    events,
+                    main_feed_uri: server_state.identity.feed_uris()[0].clone(),
+                    state: server_state,
+                },
+            };
+            feed.start(skyfeed_name, (config.bind_address, config.port))
+                .await;
+        }
+    };
+
+    tokio::join!(skyfeed, cleanup_task, cursor_task, source_task, xrpc_server)
+        .1
+        .expect("Starting tasks failed")
 }
 
 struct MyFeed {
@@ -58,218 +193,131 @@ impl Feed<MyFeedHandler> for MyFeed {
 
 #[derive(Clone)]
 struct MyFeedHandler {
-    db: Connection,
-    bsky_client: Arc<Mutex<AtpServiceClient<ReqwestClient>>>,
-    feed_author_did: String,
+    /// Read by the ChannelSource in main
+    events: mpsc::Sender<FeedEvent>,
+    /// The only feed skyfeed serves
+    main_feed_uri: String,
+    state: ServerState,
+}
+
+impl MyFeedHandler {
+    async fn send(&self, event: FeedEvent) {
+        if self.events.send(event).await.is_err() {
+            error!("The event source stopped, dropping an event");
+        }
+    }
 }
 
+/// Turns skyfeed's events into the same FeedEvents every other source produces. skyfeed only
+/// gives posts the createdAt their author wrote, so every event is stamped with when it got here
 impl FeedHandler for MyFeedHandler {
     async fn insert_post(&mut self, post: Post) {
-        //Extracting all the Text from the post
-        let mut text_types: Vec<TextInPost> = vec![TextInPost::Post(post.text.clone())];
-        match &post.embed {
-            None => {}
-            Some(embed) => match embed {
-                Embed::Video(video) => {
-                    text_types.push(TextInPost::Video(video.alt_text.clone()));
-                }
-                Embed::External(external) => {
-                    text_types.push(TextInPost::External(external.title.clone()));
-                    text_types.push(TextInPost::External(external.description.clone()));
-                }
-                Embed::Quote(_) => {}
-                Embed::QuoteWithMedia(_, media_embedded) => match media_embedded {
-                    MediaEmbed::Images(images) => {
-                        for image in images {
-                            text_types.push(TextInPost::Picture(image.alt_text.clone()));
-                        }
-                    }
-                    MediaEmbed::Video(_) => {}
-                    MediaEmbed::External(_) => {}
+        let embed = post.embed.as_ref().map(|embed| match embed {
+            Embed::Video(video) => RecordEmbed::Video {
+                alt: Some(video.alt_text.clone()),
+            },
+            Embed::External(external) => RecordEmbed::External {
+                external: ExternalLink {
+                    uri: external.uri.clone(),
+                    title: external.title.clone(),
+                    description: external.description.clone(),
                 },
-                Embed::Images(images) => {
-                    for image in images {
-                        text_types.push(TextInPost::Picture(image.alt_text.clone()));
-                    }
-                }
             },
-        }
-
-        let save_post = does_the_post_belong_to_the_feed(text_types.clone());
-        match save_post {
-            None => {}
-            Some(scoring) => {
-                info!("Storing {post:?}");
-                let _ = self.db.call(move |db| {
-                    db.execute(
-                        "INSERT OR REPLACE INTO posts (uri, text, pinned, deleted, priority, timestamp) VALUES (?1, ?2, 0, 0, ?3, ?4)",
-                        params![ &post.uri.0, &post.text, scoring.priority, &post.timestamp.timestamp()],
-                    ).map_err(|err| err.into())
-                }).await;
-            }
-        }
+            Embed::Quote(_) => RecordEmbed::Record,
+            Embed::QuoteWithMedia(_, media_embedded) => RecordEmbed::RecordWithMedia {
+                media: Box::new(match media_embedded {
+                    MediaEmbed::Images(images) => RecordEmbed::Images {
+                        images: images
+                            .iter()
+                            .map(|image| EmbedImage {
+                                alt: image.alt_text.clone(),
+                            })
+                            .collect(),
+                    },
+                    MediaEmbed::Video(_) => RecordEmbed::Video { alt: None },
+                    MediaEmbed::External(external) => RecordEmbed::External {
+                        external: ExternalLink {
+                            uri: external.uri.clone(),
+                            title: external.title.clone(),
+                            description: external.description.clone(),
+                        },
+                    },
+                }),
+            },
+            Embed::Images(images) => RecordEmbed::Images {
+                images: images
+                    .iter()
+                    .map(|image| EmbedImage {
+                        alt: image.alt_text.clone(),
+                    })
+                    .collect(),
+            },
+        });
+        self.send(FeedEvent::Post {
+            time_us: Utc::now().timestamp_micros(),
+            uri: post.uri.0,
+            author_did: post.author_did.0,
+            cid: Some(post.cid.0),
+            record: PostRecord {
+                text: post.text,
+                created_at: Some(post.timestamp.to_rfc3339()),
+                embed,
+                langs: post.langs,
+                reply: post.reply.map(|reply| ReplyRef {
+                    parent: StrongRef {
+                        uri: reply.parent.0,
+                    },
+                    root: StrongRef { uri: reply.root.0 },
+                }),
+            },
+        })
+        .await;
     }
 
     async fn delete_post(&mut self, uri: Uri) {
-        self.db
-            .call(move |db| {
-                db.execute("DELETE FROM posts WHERE uri = ?1", params![&uri.0])
-                    .map_err(|err| err.into())
-            })
-            .await
-            .unwrap();
+        self.send(FeedEvent::DeletePost {
+            time_us: Utc::now().timestamp_micros(),
+            uri: uri.0,
+        })
+        .await;
     }
 
     async fn like_post(&mut self, like_uri: Uri, liked_post_uri: Uri, user_who_liked: Did) {
-        if user_who_liked.0 == self.feed_author_did {
-            info!("Hey you just liked something");
-
-            let client = self.bsky_client.lock().await;
-            let get_posts_call = client
-                .service
-                .app
-                .bsky
-                .feed
-                .get_posts(
-                    atrium_api::app::bsky::feed::get_posts::ParametersData {
-                        uris: vec![liked_post_uri.0.clone()],
-                    }
-                    .into(),
-                )
-                .await;
-
-            match get_posts_call {
-                Ok(result) => {
-                    for post in result.posts.clone() {
-                        let post_text: String = match &post.record {
-                            Unknown::Object(map) => match map.get("text") {
-                                Some(data_model) => match &**data_model {
-                                    Ipld::String(text) => text.clone(),
-                                    Ipld::Null => "(Null content)".to_string(),
-                                    other => format!("(Unexpected format: {:?})", other),
-                                },
-                                None => "(No text content)".to_string(),
-                            },
-                            Unknown::Null => "No post content".to_string(),
-                            Unknown::Other(_) => "Other?".to_string(),
-                        };
-
-                        let scoring = does_the_post_belong_to_the_feed(vec![TextInPost::Post(
-                            post_text.clone(),
-                        )]);
-
-                        //TODO DRY
-                        match scoring {
-                            None => {}
-                            Some(score) => {
-                                let dt = Utc::now();
-                                let timestamp: i64 = dt.timestamp();
-                                self.db
-                                    .call(move |db| {
-                                        db.execute(
-                                            "INSERT OR REPLACE INTO posts (uri, text, pinned, deleted, priority, timestamp) VALUES (?1, ?2, 0, 0, ?3, ?4)",
-                                            params![ &post.uri, &post_text, score.priority, &timestamp],
-                                        ).map_err(|err| err.into())
-                                    })
-                                    .await
-                                    .unwrap();
-                            }
-                        }
-                    }
-                }
-                Err(err) => {
-                    error!("{}", err);
-                }
-            }
-        }
-
-        self.db
-            .call(move |db| {
-                db.execute(
-                    "INSERT INTO likes (post_uri, like_uri)
-             SELECT ?1, ?2
-             WHERE EXISTS (SELECT 1 FROM posts WHERE uri = ?1)",
-                    params![&liked_post_uri.0, &like_uri.0],
-                )
-                .map_err(|err| err.into())
-            })
-            .await
-            .unwrap();
+        self.send(FeedEvent::Like {
+            time_us: Utc::now().timestamp_micros(),
+            like_uri: like_uri.0,
+            liked_post_uri: liked_post_uri.0,
+            liker_did: user_who_liked.0,
+        })
+        .await;
     }
 
     async fn delete_like(&mut self, like_uri: Uri) {
-        self.db
-            .call(move |db| {
-                db.execute(
-                    "DELETE FROM likes WHERE like_uri = ?1",
-                    params![&like_uri.0],
-                )
-                .map_err(|err| err.into())
-            })
-            .await
-            .unwrap();
+        self.send(FeedEvent::DeleteLike {
+            time_us: Utc::now().timestamp_micros(),
+            like_uri: like_uri.0,
+        })
+        .await;
     }
 
+    /// The same getFeedSkeleton as the XRPC server, but skyfeed does not pass the Authorization
+    /// header on and only sends back uris, so this is the signed out feed without feedContext
     async fn serve_feed(&self, request: Request) -> FeedResult {
-        // http://0.0.0.0:3030/xrpc/app.bsky.feed.getFeedSkeleton?feed=at://did:plc:rnpkyqnmsw4ipey6eotbdnnf/app.bsky.feed.generator/TechThreadsAndMore&limit=5
-        info!("Serving {request:?}");
-        let posts_per_page: u8 = match request.limit {
-            None => 0,
-            Some(limit) => u8::from(limit),
+        let params = GetFeedSkeletonParams {
+            feed: self.main_feed_uri.clone(),
+            limit: request.limit.map(|limit| u8::from(limit) as u64),
+            cursor: request.cursor,
         };
-
-        let start_index = request
-            .cursor
-            .as_deref()
-            .and_then(|c| c.parse::<usize>().ok())
-            .unwrap_or(0);
-
-        let post_uris =
-            load_feed_from_db(&self.db, posts_per_page as u64, start_index as u64).await;
-        let mut posts: Vec<Uri> = post_uris.into_iter().map(|post| Uri(post.uri)).collect();
-        //TODO prepane the pinned post? Manually? idk
-
-        let total_posts: u64 = get_posts_count(&self.db).await;
-        let next_cursor = if (start_index as u64) + (posts_per_page as u64) < total_posts {
-            Some(((start_index as u64) + (posts_per_page as u64)).to_string())
-        } else {
-            None
-        };
-        info!("Served {} posts", posts.len());
-        FeedResult {
-            cursor: next_cursor,
-            feed: posts,
-        }
-    }
-}
-
-async fn cleanup_posts(db: &Connection) {
-    const MAX_POSTS: usize = 10_000;
-    let count = db
-        .call(|db| {
-            db.execute(
-                &format!(
-                    "
-                DELETE FROM posts
-                WHERE uri NOT IN (
-                    SELECT uri
-                    FROM posts
-                    ORDER BY timestamp DESC
-                    LIMIT {MAX_POSTS}
-                );
-                "
-                ),
-                [],
-            )
-            .map_err(|err| err.into())
-        })
-        .await;
-    match count {
-        Ok(cleaned_posts) => {
-            info!("Cleaned up {cleaned_posts} posts");
-        }
-        Err(err) => {
-            info!("Failed to cleanup posts: {err:?}");
+        match feed_skeleton(params, None, &self.state, "skyfeed").await {
+            Ok(output) => FeedResult {
+                cursor: output.cursor,
+                feed: output.feed.into_iter().map(|item| Uri(item.post)).collect(),
+            },
+            //skyfeed has no way to send an error back, it was logged and the XRPC server answers with one
+            Err(_) => FeedResult {
+                cursor: None,
+                feed: vec![],
+            },
         }
     }
 }
@@ -0,0 +1,659 @@
+use crate::health::{DEFAULT_MAX_LAG, DEFAULT_STALE_AFTER};
+use crate::pipeline::{IngestOptions, QueueFullPolicy};
+use crate::replay::ReplaySpeed;
+use crate::retention::{ArchiveTarget, RetentionLimits, RetentionPolicy};
+use crate::source::DEFAULT_JETSTREAM_URL;
+use crate::sqlite::SqliteOptions;
+use clap::Args;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+//** NOTICE **
+// Settings come from, lowest to highest precedence: the TOML config file, environment variables
+// (.env is loaded first) and then CLI flags. Both the feed and admin binaries load it the same way
+//
+
+const DEFAULT_CONFIG_PATH: &str = "./feed.toml";
+const DEFAULT_DATABASE_PATH: &str = "./feed.db";
+const DEFAULT_APPVIEW_URL: &str = "https://public.api.bsky.app";
+const DEFAULT_PLC_DIRECTORY: &str = "https://plc.directory";
+const DEFAULT_FEED_NAME: &str = "TechThreadsAndMore";
+const DEFAULT_BIND_ADDRESS: &str = "0.0.0.0";
+const DEFAULT_PORT: u16 = 3030;
+const DEFAULT_XRPC_PORT: u16 = 3031;
+const DEFAULT_MAX_POSTS_PER_AUTHOR: usize = 3;
+const DEFAULT_EVENT_SOURCE: &str = "jetstream";
+const DEFAULT_ARCHIVE_DIR: &str = "./archive";
+
+/// Flags shared by both binaries, each one can also be set with the env var next to it
+#[derive(Args, Debug, Default, Clone)]
+pub struct ConfigArgs {
+    /// TOML config file, it is fine for the default one to not exist
+    #[arg(long, env = "CONFIG_PATH")]
+    pub config: Option<PathBuf>,
+    #[arg(long, env = "DATABASE_PATH")]
+    pub database_path: Option<PathBuf>,
+    /// postgres:// url of a db to use instead of database_path, needs the postgres feature
+    #[arg(long, env = "DATABASE_URL")]
+    pub database_url: Option<String>,
+    /// Read-only SQLite connections the feed and admin tool read through
+    #[arg(long, env = "SQLITE_READ_CONNECTIONS")]
+    pub sqlite_read_connections: Option<usize>,
+    /// Milliseconds a SQLite call waits for the other binary to finish writing before it fails
+    #[arg(long, env = "SQLITE_BUSY_TIMEOUT_MS")]
+    pub sqlite_busy_timeout_ms: Option<u64>,
+    /// AppView used to look up posts
+    #[arg(long, env = "APPVIEW_URL")]
+    pub appview_url: Option<String>,
+    #[arg(long, env = "PLC_DIRECTORY")]
+    pub plc_directory: Option<String>,
+    /// Account the feed records are published under
+    #[arg(long, env = "PUBLISHER_DID")]
+    pub publisher_did: Option<String>,
+    /// Public hostname of the feed generator, also makes up its did:web
+    #[arg(long, env = "FEED_GENERATOR_HOSTNAME")]
+    pub feed_generator_hostname: Option<String>,
+    /// Comma separated feed record names, the first one is the main feed
+    #[arg(long, env = "FEED_NAMES", value_delimiter = ',')]
+    pub feed_names: Option<Vec<String>>,
+    #[arg(long, env = "BIND_ADDRESS")]
+    pub bind_address: Option<String>,
+    /// Port for skyfeed's getFeedSkeleton server, only up with the firehose source. It serves the
+    /// main feed signed out and without feedContext, the reverse proxy goes to xrpc_port
+    #[arg(long, env = "PORT")]
+    pub port: Option<u16>,
+    /// Port for our own XRPC server
+    #[arg(long, env = "XRPC_PORT")]
+    pub xrpc_port: Option<u16>,
+    /// How many posts one author can have on a page of the feed, 0 turns the cap off
+    #[arg(long, env = "MAX_POSTS_PER_AUTHOR")]
+    pub max_posts_per_author: Option<usize>,
+    #[arg(long, env = "HIDE_SEEN_POSTS")]
+    pub hide_seen_posts: Option<bool>,
+    /// Seconds without a firehose event before /readyz reports the feed as not ready
+    #[arg(long, env = "FIREHOSE_STALE_AFTER_SECS")]
+    pub firehose_stale_after_secs: Option<u64>,
+    /// Seconds the latest firehose event can be behind the clock before /readyz reports not ready
+    #[arg(long, env = "FIREHOSE_MAX_LAG_SECS")]
+    pub firehose_max_lag_secs: Option<u64>,
+    /// Where the feed reads events from: jetstream, firehose or replay. firehose does not see
+    /// account deletions or takedowns
+    #[arg(long, env = "EVENT_SOURCE")]
+    pub event_source: Option<String>,
+    #[arg(long, env = "JETSTREAM_URL")]
+    pub jetstream_url: Option<String>,
+    /// Recorded Jetstream events to read when event_source is replay
+    #[arg(long, env = "REPLAY_FILE")]
+    pub replay_file: Option<PathBuf>,
+    /// How many times faster than recorded to replay, as fast as possible when not set
+    #[arg(long, env = "REPLAY_SPEED")]
+    pub replay_speed: Option<f64>,
+    /// Posts classified at the same time, defaults to the number of CPUs
+    #[arg(long, env = "INGEST_WORKERS")]
+    pub ingest_workers: Option<usize>,
+    /// Events that can wait to be classified and written
+    #[arg(long, env = "INGEST_QUEUE_CAPACITY")]
+    pub ingest_queue_capacity: Option<usize>,
+    /// Most writes committed in one transaction
+    #[arg(long, env = "INGEST_BATCH_SIZE")]
+    pub ingest_batch_size: Option<usize>,
+    /// What an event does when the queue is full: wait for room or drop, dropped events are not replayed
+    /// after a restart
+    #[arg(long, env = "INGEST_QUEUE_FULL")]
+    pub ingest_queue_full: Option<String>,
+    /// Newest unpinned posts kept before older ones are archived, 0 keeps any number
+    #[arg(long, env = "RETENTION_MAX_POSTS")]
+    pub retention_max_posts: Option<usize>,
+    /// Days an unpinned post is kept before it is archived, kept forever when not set
+    #[arg(long, env = "RETENTION_MAX_AGE_DAYS")]
+    pub retention_max_age_days: Option<u64>,
+    /// Where archived posts go: table or jsonl
+    #[arg(long, env = "RETENTION_ARCHIVE")]
+    pub retention_archive: Option<String>,
+    /// Directory for the gzipped JSONL files when retention_archive is jsonl
+    #[arg(long, env = "RETENTION_ARCHIVE_DIR")]
+    pub retention_archive_dir: Option<PathBuf>,
+    /// Seconds between retention runs
+    #[arg(long, env = "RETENTION_INTERVAL_SECS")]
+    pub retention_interval_secs: Option<u64>,
+}
+
+/// What can be set in the TOML config file
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    pub database_path: Option<PathBuf>,
+    pub database_url: Option<String>,
+    pub sqlite_read_connections: Option<usize>,
+    pub sqlite_busy_timeout_ms: Option<u64>,
+    pub appview_url: Option<String>,
+    pub plc_directory: Option<String>,
+    pub publisher_did: Option<String>,
+    pub feed_generator_hostname: Option<String>,
+    pub feed_names: Option<Vec<String>>,
+    pub bind_address: Option<String>,
+    pub port: Option<u16>,
+    pub xrpc_port: Option<u16>,
+    pub max_posts_per_author: Option<usize>,
+    pub hide_seen_posts: Option<bool>,
+    pub firehose_stale_after_secs: Option<u64>,
+    pub firehose_max_lag_secs: Option<u64>,
+    pub event_source: Option<String>,
+    pub jetstream_url: Option<String>,
+    pub replay_file: Option<PathBuf>,
+    pub replay_speed: Option<f64>,
+    pub ingest_workers: Option<usize>,
+    pub ingest_queue_capacity: Option<usize>,
+    pub ingest_batch_size: Option<usize>,
+    pub ingest_queue_full: Option<String>,
+    pub retention_max_posts: Option<usize>,
+    pub retention_max_age_days: Option<u64>,
+    pub retention_archive: Option<String>,
+    pub retention_archive_dir: Option<PathBuf>,
+    pub retention_interval_secs: Option<u64>,
+    /// Retention limits of single feeds by feed name, only in the config file. The feeds share their posts so
+    /// only the loosest limit of all of them takes effect, a stricter one on a single feed archives nothing earlier
+    pub retention_feeds: Option<BTreeMap<String, FeedRetentionConfig>>,
+}
+
+/// A `[retention_feeds.<feed name>]` table, anything left out comes from the global limits. A post is
+/// archived only once it is past the limits of every feed
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct FeedRetentionConfig {
+    /// 0 keeps any number
+    pub max_posts: Option<usize>,
+    pub max_age_days: Option<u64>,
+}
+
+/// Where the feed binary reads events from
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventSourceConfig {
+    /// skyfeed's own connection, it always starts from live
+    Firehose,
+    Jetstream {
+        url: String,
+    },
+    Replay {
+        file: PathBuf,
+        speed: ReplaySpeed,
+    },
+}
+
+impl EventSourceConfig {
+    /// Also the name its cursor is saved under
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventSourceConfig::Firehose => "firehose",
+            EventSourceConfig::Jetstream { .. } => "jetstream",
+            EventSourceConfig::Replay { .. } => "replay",
+        }
+    }
+
+    /// Only Jetstream can be asked to start from a cursor, so it is the only source one is kept for
+    pub fn resumes_from_cursor(&self) -> bool {
+        matches!(self, EventSourceConfig::Jetstream { .. })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub database_path: PathBuf,
+    /// Postgres instead of the SQLite db at database_path when set
+    pub database_url: Option<String>,
+    pub sqlite: SqliteOptions,
+    pub appview_url: String,
+    pub plc_directory: String,
+    pub publisher_did: Option<String>,
+    pub feed_generator_hostname: Option<String>,
+    pub feed_names: Vec<String>,
+    pub bind_address: IpAddr,
+    pub port: u16,
+    pub xrpc_port: u16,
+    pub max_posts_per_author: usize,
+    pub hide_seen_posts: bool,
+    pub firehose_stale_after: Duration,
+    pub firehose_max_lag: Duration,
+    pub event_source: EventSourceConfig,
+    pub ingest: IngestOptions,
+    pub retention: RetentionPolicy,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read {
+        path: PathBuf,
+        message: String,
+    },
+    Parse {
+        path: PathBuf,
+        message: String,
+    },
+    Missing {
+        setting: &'static str,
+    },
+    Invalid {
+        setting: &'static str,
+        message: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read { path, message } => {
+                write!(
+                    f,
+                    "Could not read config file {}: {message}",
+                    path.display()
+                )
+            }
+            ConfigError::Parse { path, message } => {
+                write!(f, "Config file {} is not valid: {message}", path.display())
+            }
+            ConfigError::Missing { setting } => write!(
+                f,
+                "{setting} is not set. Set `{setting}` in the config file, the {} env var or --{}",
+                setting.to_uppercase(),
+                setting.replace('_', "-")
+            ),
+            ConfigError::Invalid { setting, message } => {
+                write!(f, "{setting} is not valid: {message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Reads the config file the args point at, then lays env vars and flags over it
+    pub fn load(args: ConfigArgs) -> Result<Config, ConfigError> {
+        let file = match &args.config {
+            Some(path) => Some(read_config_file(path)?),
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Some(read_config_file(Path::new(DEFAULT_CONFIG_PATH))?)
+            }
+            None => None,
+        };
+        Config::merge(args, file.unwrap_or_default())
+    }
+
+    /// Flags and env vars (already in `args`) win over the file, which wins over the defaults
+    pub fn merge(args: ConfigArgs, file: FileConfig) -> Result<Config, ConfigError> {
+        let bind_address = args
+            .bind_address
+            .or(file.bind_address)
+            .unwrap_or_else(|| DEFAULT_BIND_ADDRESS.to_string());
+        let bind_address = bind_address
+            .parse::<IpAddr>()
+            .map_err(|err| ConfigError::Invalid {
+                setting: "bind_address",
+                message: format!("{bind_address}: {err}"),
+            })?;
+
+        let event_source = match args
+            .event_source
+            .or(file.event_source)
+            .unwrap_or_else(|| DEFAULT_EVENT_SOURCE.to_string())
+            .as_str()
+        {
+            "firehose" => EventSourceConfig::Firehose,
+            "jetstream" => EventSourceConfig::Jetstream {
+                url: args
+                    .jetstream_url
+                    .or(file.jetstream_url)
+                    .unwrap_or_else(|| DEFAULT_JETSTREAM_URL.to_string()),
+            },
+            "replay" => EventSourceConfig::Replay {
+                file: args
+                    .replay_file
+                    .or(file.replay_file)
+                    .ok_or(ConfigError::Missing {
+                        setting: "replay_file",
+                    })?,
+                speed: args
+                    .replay_speed
+                    .or(file.replay_speed)
+                    .map_or(ReplaySpeed::Instant, ReplaySpeed::Scaled),
+            },
+            other => {
+                return Err(ConfigError::Invalid {
+                    setting: "event_source",
+                    message: format!("{other} is not one of firehose, jetstream or replay"),
+                })
+            }
+        };
+
+        let defaults = IngestOptions::default();
+        let when_full = match args.ingest_queue_full.or(file.ingest_queue_full).as_deref() {
+            None | Some("wait") => QueueFullPolicy::Wait,
+            Some("drop") => QueueFullPolicy::Drop,
+            Some(other) => {
+                return Err(ConfigError::Invalid {
+                    setting: "ingest_queue_full",
+                    message: format!("{other} is not one of wait or drop"),
+                })
+            }
+        };
+        let ingest = IngestOptions {
+            classifier_workers: args
+                .ingest_workers
+                .or(file.ingest_workers)
+                .unwrap_or(defaults.classifier_workers),
+            queue_capacity: args
+                .ingest_queue_capacity
+                .or(file.ingest_queue_capacity)
+                .unwrap_or(defaults.queue_capacity),
+            batch_size: args
+                .ingest_batch_size
+                .or(file.ingest_batch_size)
+                .unwrap_or(defaults.batch_size),
+            when_full,
+        };
+
+        let retention_defaults = RetentionPolicy::default();
+        let archive = match args.retention_archive.or(file.retention_archive).as_deref() {
+            None | Some("table") => ArchiveTarget::Table,
+            Some("jsonl") => ArchiveTarget::Jsonl(
+                args.retention_archive_dir
+                    .or(file.retention_archive_dir)
+                    .unwrap_or_else(|| PathBuf::from(DEFAULT_ARCHIVE_DIR)),
+            ),
+            Some(other) => {
+                return Err(ConfigError::Invalid {
+                    setting: "retention_archive",
+                    message: format!("{other} is not one of table or jsonl"),
+                })
+            }
+        };
+        let feed_names: Vec<String> = args
+            .feed_names
+            .or(file.feed_names)
+            .unwrap_or_else(|| vec![DEFAULT_FEED_NAME.to_string()])
+            .into_iter()
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .collect();
+        let max_posts = match args.retention_max_posts.or(file.retention_max_posts) {
+            Some(0) => None,
+            Some(max_posts) => Some(max_posts),
+            None => retention_defaults.max_posts,
+        };
+        let max_age = args
+            .retention_max_age_days
+            .or(file.retention_max_age_days)
+            .map(days)
+            .transpose()?;
+        let feeds = match file.retention_feeds {
+            None => BTreeMap::new(),
+            Some(mut feeds) => {
+                if let Some(name) = feeds.keys().find(|name| !feed_names.contains(name)) {
+                    return Err(ConfigError::Invalid {
+                        setting: "retention_feeds",
+                        message: format!("{name} is not one of feed_names"),
+                    });
+                }
+                feed_names
+                    .iter()
+                    .map(|name| {
+                        let feed = feeds.remove(name).unwrap_or_default();
+                        let limits = RetentionLimits {
+                            max_posts: match feed.max_posts {
+                                Some(0) => None,
+                                Some(max_posts) => Some(max_posts),
+                                None => max_posts,
+                            },
+                            max_age: feed.max_age_days.map(days).transpose()?.or(max_age),
+                        };
+                        Ok((name.clone(), limits))
+                    })
+                    .collect::<Result<_, ConfigError>>()?
+            }
+        };
+        let retention = RetentionPolicy {
+            max_posts,
+            max_age,
+            feeds,
+            archive,
+            interval: args
+                .retention_interval_secs
+                .or(file.retention_interval_secs)
+                .map(Duration::from_secs)
+                .unwrap_or(retention_defaults.interval),
+        };
+
+        let sqlite_defaults = SqliteOptions::default();
+        let sqlite = SqliteOptions {
+            read_connections: args
+                .sqlite_read_connections
+                .or(file.sqlite_read_connections)
+                .unwrap_or(sqlite_defaults.read_connections),
+            busy_timeout: args
+                .sqlite_busy_timeout_ms
+                .or(file.sqlite_busy_timeout_ms)
+                .map(Duration::from_millis)
+                .unwrap_or(sqlite_defaults.busy_timeout),
+        };
+
+        let config = Config {
+            database_path: args
+                .database_path
+                .or(file.database_path)
+                .unwrap_or_else(|| PathBuf::from(DEFAULT_DATABASE_PATH)),
+            database_url: args.database_url.or(file.database_url),
+            sqlite,
+            appview_url: args
+                .appview_url
+                .or(file.appview_url)
+                .unwrap_or_else(|| DEFAULT_APPVIEW_URL.to_string()),
+            plc_directory: args
+                .plc_directory
+                .or(file.plc_directory)
+                .unwrap_or_else(|| DEFAULT_PLC_DIRECTORY.to_string()),
+            publisher_did: args.publisher_did.or(file.publisher_did),
+            feed_generator_hostname: args
+                .feed_generator_hostname
+                .or(file.feed_generator_hostname),
+            feed_names,
+            bind_address,
+            port: args.port.or(file.port).unwrap_or(DEFAULT_PORT),
+            xrpc_port: args
+                .xrpc_port
+                .or(file.xrpc_port)
+                .unwrap_or(DEFAULT_XRPC_PORT),
+            max_posts_per_author: args
+                .max_posts_per_author
+                .or(file.max_posts_per_author)
+                .unwrap_or(DEFAULT_MAX_POSTS_PER_AUTHOR),
+            hide_seen_posts: args
+                .hide_seen_posts
+                .or(file.hide_seen_posts)
+                .unwrap_or(false),
+            firehose_stale_after: args
+                .firehose_stale_after_secs
+                .or(file.firehose_stale_after_secs)
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_STALE_AFTER),
+            firehose_max_lag: args
+                .firehose_max_lag_secs
+                .or(file.firehose_max_lag_secs)
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_MAX_LAG),
+            event_source,
+            ingest,
+            retention,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        for (setting, url) in [
+            ("appview_url", &self.appview_url),
+            ("plc_directory", &self.plc_directory),
+        ] {
+            url::Url::parse(url).map_err(|err| ConfigError::Invalid {
+                setting,
+                message: format!("{url}: {err}"),
+            })?;
+        }
+        if let Some(database_url) = &self.database_url {
+            if !database_url.starts_with("postgres://")
+                && !database_url.starts_with("postgresql://")
+            {
+                return Err(ConfigError::Invalid {
+                    setting: "database_url",
+                    //Left out of the message, it may have a password in it
+                    message: "has to be a postgres:// url".to_string(),
+                });
+            }
+            if cfg!(not(feature = "postgres")) {
+                return Err(ConfigError::Invalid {
+                    setting: "database_url",
+                    message:
+                        "this build has no Postgres support, build it with --features postgres"
+                            .to_string(),
+                });
+            }
+        }
+        if let EventSourceConfig::Jetstream { url } = &self.event_source {
+            match url::Url::parse(url) {
+                Ok(parsed) if matches!(parsed.scheme(), "ws" | "wss") => {}
+                Ok(_) => {
+                    return Err(ConfigError::Invalid {
+                        setting: "jetstream_url",
+                        message: format!("{url} is not a ws:// or wss:// url"),
+                    })
+                }
+                Err(err) => {
+                    return Err(ConfigError::Invalid {
+                        setting: "jetstream_url",
+                        message: format!("{url}: {err}"),
+                    })
+                }
+            }
+        }
+        if let EventSourceConfig::Replay {
+            speed: ReplaySpeed::Scaled(speed),
+            ..
+        } = &self.event_source
+        {
+            if *speed <= 0.0 {
+                return Err(ConfigError::Invalid {
+                    setting: "replay_speed",
+                    message: format!("{speed} has to be more than 0"),
+                });
+            }
+        }
+        for (setting, value) in [
+            ("ingest_workers", self.ingest.classifier_workers),
+            ("ingest_queue_capacity", self.ingest.queue_capacity),
+            ("ingest_batch_size", self.ingest.batch_size),
+            ("sqlite_read_connections", self.sqlite.read_connections),
+        ] {
+            if value == 0 {
+                return Err(ConfigError::Invalid {
+                    setting,
+                    message: "has to be at least 1".to_string(),
+                });
+            }
+        }
+        if self.retention.interval.is_zero() {
+            return Err(ConfigError::Invalid {
+                setting: "retention_interval_secs",
+                message: "has to be at least 1".to_string(),
+            });
+        }
+        //A feed's own max_age_days of 0 ends up here too
+        if self
+            .retention
+            .max_age
+            .into_iter()
+            .chain(
+                self.retention
+                    .feeds
+                    .values()
+                    .filter_map(|limits| limits.max_age),
+            )
+            .any(|max_age| max_age.is_zero())
+        {
+            return Err(ConfigError::Invalid {
+                setting: "retention_max_age_days",
+                message: "has to be at least 1".to_string(),
+            });
+        }
+        if let Some(publisher_did) = &self.publisher_did {
+            if !publisher_did.starts_with("did:") {
+                return Err(ConfigError::Invalid {
+                    setting: "publisher_did",
+                    message: format!("{publisher_did} is not a DID"),
+                });
+            }
+        }
+        if self.feed_names.is_empty() {
+            return Err(ConfigError::Invalid {
+                setting: "feed_names",
+                message: "at least one feed name is needed".to_string(),
+            });
+        }
+        if self.port == self.xrpc_port {
+            return Err(ConfigError::Invalid {
+                setting: "xrpc_port",
+                message: format!("{} is already used by port", self.xrpc_port),
+            });
+        }
+        Ok(())
+    }
+
+    /// Only the feed needs to know who publishes it
+    pub fn publisher_did(&self) -> Result<String, ConfigError> {
+        self.publisher_did.clone().ok_or(ConfigError::Missing {
+            setting: "publisher_did",
+        })
+    }
+
+    pub fn feed_generator_hostname(&self) -> Result<String, ConfigError> {
+        self.feed_generator_hostname
+            .clone()
+            .ok_or(ConfigError::Missing {
+                setting: "feed_generator_hostname",
+            })
+    }
+
+    /// The main feed, the one skyfeed serves
+    pub fn main_feed_name(&self) -> &str {
+        &self.feed_names[0]
+    }
+
+    /// 0 in the config means no cap
+    pub fn max_posts_per_author(&self) -> Option<usize> {
+        Some(self.max_posts_per_author).filter(|max| *max > 0)
+    }
+}
+
+/// Also keeps the seconds within what a unix timestamp can be compared to
+fn days(days: u64) -> Result<Duration, ConfigError> {
+    days.checked_mul(24 * 60 * 60)
+        .filter(|secs| i64::try_from(*secs).is_ok())
+        .map(Duration::from_secs)
+        .ok_or_else(|| ConfigError::Invalid {
+            setting: "retention_max_age_days",
+            message: format!("{days} days is too long"),
+        })
+}
+
+fn read_config_file(path: &Path) -> Result<FileConfig, ConfigError> {
+    let contents = std::fs::read_to_string(path).map_err(|err| ConfigError::Read {
+        path: path.to_path_buf(),
+        message: err.to_string(),
+    })?;
+    toml::from_str(&contents).map_err(|err| ConfigError::Parse {
+        path: path.to_path_buf(),
+        message: err.to_string(),
+    })
+}
@@ -0,0 +1,62 @@
+use crate::store::{FeedResult, FeedStore};
+use chrono::Utc;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+//** NOTICE **
+// Jetstream cursors are the time_us of an event. The ingest writer moves it up to the newest event
+// it has committed, and it is written to the db every few seconds, a restart then asks the relay to
+// replay from there. Anything committed after the last save is replayed again, which the handler has
+// to be fine with
+//
+
+pub struct CursorTracker {
+    source: String,
+    /// Newest event time_us committed, 0 until the first one
+    latest: AtomicI64,
+    /// What was last written to the db, so an idle stream does not write every tick
+    saved: AtomicI64,
+}
+
+impl CursorTracker {
+    pub fn new(source: impl Into<String>) -> Self {
+        Self {
+            source: source.into(),
+            latest: AtomicI64::new(0),
+            saved: AtomicI64::new(0),
+        }
+    }
+
+    /// Events are not always handled in order, so the cursor only ever moves forward
+    pub fn observe(&self, time_us: i64) {
+        self.latest.fetch_max(time_us, Ordering::Relaxed);
+    }
+
+    pub fn latest(&self) -> Option<i64> {
+        Some(self.latest.load(Ordering::Relaxed)).filter(|cursor| *cursor > 0)
+    }
+
+    /// The cursor a previous run left behind, if there was one
+    pub async fn load(&self, store: &dyn FeedStore) -> FeedResult<Option<i64>> {
+        let cursor = store.load_cursor(self.source.clone()).await?;
+        if let Some(cursor) = cursor {
+            self.latest.fetch_max(cursor, Ordering::Relaxed);
+            self.saved.store(cursor, Ordering::Relaxed);
+        }
+        Ok(cursor)
+    }
+
+    /// Writes the newest cursor if it moved since the last save. Returns if it wrote anything
+    pub async fn save(&self, store: &dyn FeedStore) -> FeedResult<bool> {
+        let Some(latest) = self.latest() else {
+            return Ok(false);
+        };
+        if latest == self.saved.load(Ordering::Relaxed) {
+            return Ok(false);
+        }
+        store
+            .save_cursor(self.source.clone(), latest, Utc::now().timestamp())
+            .await?;
+        self.saved.store(latest, Ordering::Relaxed);
+        Ok(true)
+    }
+}
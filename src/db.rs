@@ -1,52 +1,148 @@
-use crate::models::DbPost;
-use anyhow::Result;
+use crate::events::AccountStatus;
+use crate::ingest::{insert_post_row, store_scored_post, IngestOutcome};
+use crate::metrics::time_query;
+use crate::migrations::{migrate, MigrationError};
+use crate::models::{
+    AuthorList, AuthorLists, DbPost, FeedPosition, InteractionCounts, ListedAuthor, SearchResult,
+    Tombstone, ViewerFilter, REQUEST_LESS, REQUEST_MORE,
+};
+use crate::pipeline::FeedWrite;
+use crate::store::{BatchReport, FeedError, FeedResult};
 use crossterm::ExecutableCommand;
 use log::info;
-use tokio_rusqlite::Connection;
+use std::collections::HashMap;
+use tokio_rusqlite::{params, Connection, TransactionBehavior};
 
-pub async fn load_feed_from_db(db: &Connection, limit: u64, offset: u64) -> Vec<DbPost> {
+/// How much each extra share of a link adds to the engagement of the post that first shared it
+pub const SHARE_SCORE: u64 = 10;
+/// Once an author has this many more "show less" than "show more" their posts stop being served
+pub const AUTHOR_PENALTY_LIMIT: i64 = 10;
+/// Once a user has this many more "show less" than "show more" on an author, that author is hidden for them
+pub const USER_AUTHOR_MUTE_LIMIT: i64 = 2;
+
+pub async fn load_feed_from_db(
+    db: &Connection,
+    limit: u64,
+    offset: u64,
+) -> FeedResult<Vec<DbPost>> {
+    load_feed_for_viewer(db, limit, offset, None, None).await
+}
+
+/// Same as [load_feed_from_db] but only posts after `after`, and also hides what the viewer asked
+/// to see less of and posts they were already served before `hide_seen_before`
+pub async fn load_feed_for_viewer(
+    db: &Connection,
+    limit: u64,
+    offset: u64,
+    after: Option<FeedPosition>,
+    viewer: Option<ViewerFilter>,
+) -> FeedResult<Vec<DbPost>> {
+    let _timer = time_query("load_feed");
+    let (after_timestamp, after_uri) = after
+        .map(|after| (Some(after.timestamp), Some(after.uri)))
+        .unwrap_or_default();
+    let viewer_did = viewer.as_ref().map(|viewer| viewer.did.clone());
+    let hide_seen_before = viewer
+        .and_then(|viewer| viewer.hide_seen_before)
+        .unwrap_or(i64::MIN);
     //TODO just move to order by timestamp
     //BUT do a pull on pinned first or above x scoring and put them first?
     //May long get away with timestamp. Getting too wild
     db.call(move |db| {
         let mut stmt = db
-            .prepare(
+            .prepare(&format!(
                 "
                SELECT
                     posts.uri,
                     posts.text,
+                    posts.author_did,
                     posts.pinned,
                     main.posts.deleted,
-                    posts.priority
+                    posts.priority,
+                    posts.feed_context,
+                    -- Rows from before every post had one sort last
+                    COALESCE(posts.timestamp, 0)
 
                 FROM posts
                 where posts.deleted = 0
-                GROUP BY posts.uri, posts.text, posts.pinned, posts.deleted, posts.priority
-                ORDER BY  posts.timestamp desc
+                -- Users asked to see less of it enough times
+                AND posts.priority > 0
+                -- Only the best ranked post of everyone sharing the same link is served
+                AND (posts.link_url IS NULL OR posts.uri = (
+                    SELECT best.uri
+                    FROM posts AS best
+                    WHERE best.link_url = posts.link_url AND best.deleted = 0 AND best.priority > 0
+                    ORDER BY best.priority + best.shares * ?3 DESC, best.timestamp ASC
+                    LIMIT 1
+                ))
+                -- Deactivated and suspended accounts, by the repo in the uri too for rows without an author
+                AND NOT EXISTS (
+                    SELECT 1 FROM hidden_accounts AS hidden
+                    WHERE hidden.did = posts.author_did
+                    OR substr(posts.uri, 1, length('at://' || hidden.did || '/')) = 'at://' || hidden.did || '/'
+                )
+                AND (posts.author_did IS NULL OR posts.author_did NOT IN (
+                    SELECT less.author_did
+                    FROM author_interactions AS less
+                    LEFT JOIN author_interactions AS more
+                        ON more.author_did = less.author_did AND more.event = '{REQUEST_MORE}'
+                    WHERE less.event = '{REQUEST_LESS}'
+                        AND less.count - COALESCE(more.count, 0) >= {AUTHOR_PENALTY_LIMIT}
+                ))
+                -- What the viewer themselves asked to see less of
+                AND (?4 IS NULL OR posts.uri NOT IN (
+                    SELECT post_uri FROM user_interactions
+                    WHERE requester_did = ?4 AND event = '{REQUEST_LESS}'
+                ))
+                AND (?4 IS NULL OR posts.author_did IS NULL OR posts.author_did NOT IN (
+                    SELECT author_did FROM user_interactions
+                    WHERE requester_did = ?4 AND author_did IS NOT NULL
+                    GROUP BY author_did
+                    HAVING SUM(event = '{REQUEST_LESS}') - SUM(event = '{REQUEST_MORE}') >= {USER_AUTHOR_MUTE_LIMIT}
+                ))
+                AND (?4 IS NULL OR posts.uri NOT IN (
+                    SELECT post_uri FROM served_posts
+                    WHERE requester_did = ?4 AND served_at < ?5
+                ))
+                AND (?6 IS NULL OR (COALESCE(posts.timestamp, 0), posts.uri) < (?6, ?7))
+                GROUP BY posts.uri, posts.text, posts.author_did, posts.pinned, posts.deleted, posts.priority, posts.feed_context, posts.timestamp
+                ORDER BY  posts.timestamp desc, posts.uri desc
                LIMIT ?1 OFFSET ?2
-                 ",
-            )
-            .expect("Failed to prepare statement");
+                 "
+            ))?;
         let result = Ok(stmt
-            .query_map([&limit.clone(), &offset.clone()], |row| {
+            .query_map(
+                params![
+                    &limit,
+                    &offset,
+                    &SHARE_SCORE,
+                    &viewer_did,
+                    &hide_seen_before,
+                    &after_timestamp,
+                    &after_uri
+                ],
+                |row| {
                 Ok(DbPost {
                     uri: row.get(0)?,
                     text: row.get(1)?,
-                    pinned: row.get(2)?,
-                    deleted: row.get(3)?,
-                    priority: row.get(4)?,
-                    // timestamp: DateTime::<Utc>::now
-                    // timestamp: Utc.timestamp(row.get(5)?, 0),
+                    author_did: row.get(2)?,
+                    pinned: row.get(3)?,
+                    deleted: row.get(4)?,
+                    priority: row.get(5)?,
+                    feed_context: row.get(6)?,
+                    timestamp: row.get(7)?,
                 })
-            })?
+                },
+            )?
             .collect::<Result<Vec<DbPost>, _>>()?);
         result
     })
     .await
-    .unwrap()
+    .map_err(FeedError::from)
 }
 
-pub async fn get_posts_count(db: &Connection) -> u64 {
+pub async fn get_posts_count(db: &Connection) -> FeedResult<u64> {
+    let _timer = time_query("posts_count");
     let count = db
         .call(|db| {
             db.query_row("SELECT COUNT(uri) FROM posts", [], |row| {
@@ -54,57 +150,548 @@ pub async fn get_posts_count(db: &Connection) -> u64 {
             })
             .map_err(|err| err.into())
         })
+        .await?;
+    Ok(count)
+}
+
+pub async fn delete_post(db: &Connection, uri: String) -> FeedResult<()> {
+    let _timer = time_query("delete_post");
+    db.call(move |db| remove_post(db, &uri).map_err(|err| err.into()))
         .await
-        .expect("Failed to get posts count");
-    count
+        .map_err(FeedError::from)
 }
 
-pub async fn delete_post(db: &Connection, uri: String) {
-    let _ = db
-        .call(move |db| {
-            db.execute("DELETE FROM likes WHERE post_uri = ?1", &[&uri])
-                .unwrap();
+/// Deletes a post and its likes
+pub(crate) fn remove_post(db: &rusqlite::Connection, uri: &str) -> rusqlite::Result<()> {
+    db.execute("DELETE FROM likes WHERE post_uri = ?1", [uri])?;
+    db.execute("DELETE FROM posts WHERE uri = ?1", [uri])?;
+    Ok(())
+}
 
-            db.execute("DELETE FROM posts WHERE uri = ?1", &[&uri])
-                .map_err(|err| err.into())
-        })
-        .await
-        .expect("Failed to delete post");
+/// Searches the text, alt text and link text of stored posts, removed ones included. Every word
+/// has to match, FTS5 syntax in `query` is searched for as plain text
+pub async fn search_posts(
+    db: &Connection,
+    query: String,
+    limit: u64,
+    offset: u64,
+) -> FeedResult<Vec<SearchResult>> {
+    let _timer = time_query("search_posts");
+    let terms: Vec<String> = query
+        .split_whitespace()
+        .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+        .collect();
+    if terms.is_empty() {
+        return Ok(vec![]);
+    }
+    let fts_query = terms.join(" ");
+    db.call(move |db| {
+        let mut stmt = db.prepare(
+            "SELECT posts.uri, posts.author_did, snippet(posts_fts, -1, '[', ']', '…', 12), bm25(posts_fts)
+             FROM posts_fts
+             JOIN posts ON posts.rowid = posts_fts.rowid
+             WHERE posts_fts MATCH ?1
+             ORDER BY bm25(posts_fts)
+             LIMIT ?2 OFFSET ?3",
+        )?;
+        let results = stmt
+            .query_map(params![&fts_query, limit, offset], |row| {
+                Ok(SearchResult {
+                    uri: row.get(0)?,
+                    author_did: row.get(1)?,
+                    snippet: row.get(2)?,
+                    rank: row.get(3)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(results)
+    })
+    .await
+    .map_err(FeedError::from)
 }
 
-pub async fn initialize_db(db: &Connection) {
-    let _ = db
-        .call(|db| {
-            db.execute(
-                "CREATE TABLE IF NOT EXISTS posts (
-            uri TEXT PRIMARY KEY,
-            text TEXT,
-            pinned INTEGER,
-            deleted INTEGER,
-            priority INTEGER,
-            timestamp INTEGER
-        )",
-                [],
-            )
-            .expect("Failed to create posts table");
+/// Takes a post out of the feed and leaves a tombstone so no insert path can bring it back. The
+/// row is only marked deleted, its likes and scoring are kept in case it is restored
+pub async fn moderate_post(db: &Connection, uri: String, reason: String) -> FeedResult<()> {
+    let _timer = time_query("moderate_post");
+    db.call(move |db| {
+        let tx = db.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        tx.execute(
+            "INSERT INTO moderation_tombstones (uri, reason, removed_at) VALUES (?1, ?2, unixepoch())
+             ON CONFLICT(uri) DO UPDATE SET reason = ?2",
+            params![&uri, &reason],
+        )?;
+        tx.execute("UPDATE posts SET deleted = 1 WHERE uri = ?1", params![&uri])?;
+        tx.commit()?;
+        Ok(())
+    })
+    .await
+    .map_err(FeedError::from)
+}
 
-            db.execute(
-                "CREATE TABLE IF NOT EXISTS likes (
-            post_uri TEXT,
-            like_uri TEXT,
-            PRIMARY KEY (post_uri, like_uri),
-            FOREIGN KEY (post_uri) REFERENCES posts(uri) ON DELETE CASCADE
-        )",
-                [],
-            )
-            .expect("Failed to create likes table");
+/// Undoes [moderate_post]. Returns false when the post had no tombstone
+pub async fn restore_post(db: &Connection, uri: String) -> FeedResult<bool> {
+    let _timer = time_query("restore_post");
+    db.call(move |db| {
+        let tx = db.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let removed = tx.execute(
+            "DELETE FROM moderation_tombstones WHERE uri = ?1",
+            params![&uri],
+        )?;
+        tx.execute("UPDATE posts SET deleted = 0 WHERE uri = ?1", params![&uri])?;
+        tx.commit()?;
+        Ok(removed > 0)
+    })
+    .await
+    .map_err(FeedError::from)
+}
+
+/// Every tombstone, most recent first
+pub async fn list_tombstones(db: &Connection) -> FeedResult<Vec<Tombstone>> {
+    db.call(|db| {
+        let mut stmt = db.prepare(
+            "SELECT uri, reason, removed_at FROM moderation_tombstones ORDER BY removed_at DESC, uri",
+        )?;
+        let tombstones = stmt
+            .query_map([], |row| {
+                Ok(Tombstone {
+                    uri: row.get(0)?,
+                    reason: row.get(1)?,
+                    removed_at: row.get(2)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(tombstones)
+    })
+    .await
+    .map_err(FeedError::from)
+}
+
+pub(crate) fn is_tombstoned(db: &rusqlite::Connection, uri: &str) -> rusqlite::Result<bool> {
+    db.query_row(
+        "SELECT EXISTS (SELECT 1 FROM moderation_tombstones WHERE uri = ?1)",
+        [uri],
+        |row| row.get(0),
+    )
+}
+
+/// Puts an author on a list, or updates the reason and expiry if they are already on it
+pub async fn add_to_author_list(
+    db: &Connection,
+    list: AuthorList,
+    did: String,
+    reason: Option<String>,
+    expires_at: Option<i64>,
+) -> FeedResult<()> {
+    let _timer = time_query("add_to_author_list");
+    db.call(move |db| {
+        db.execute(
+            &format!(
+                "INSERT INTO {} (did, reason, added_at, expires_at) VALUES (?1, ?2, unixepoch(), ?3)
+                 ON CONFLICT(did) DO UPDATE SET reason = ?2, expires_at = ?3",
+                list.table()
+            ),
+            params![&did, &reason, expires_at],
+        )?;
+        Ok(())
+    })
+    .await
+    .map_err(FeedError::from)
+}
+
+/// Returns false when the author was not on the list
+pub async fn remove_from_author_list(
+    db: &Connection,
+    list: AuthorList,
+    did: String,
+) -> FeedResult<bool> {
+    let _timer = time_query("remove_from_author_list");
+    db.call(move |db| {
+        let removed = db.execute(
+            &format!("DELETE FROM {} WHERE did = ?1", list.table()),
+            params![&did],
+        )?;
+        Ok(removed > 0)
+    })
+    .await
+    .map_err(FeedError::from)
+}
+
+/// Everyone on the list, expired entries included so they can be seen and cleaned up
+pub async fn list_authors(db: &Connection, list: AuthorList) -> FeedResult<Vec<ListedAuthor>> {
+    db.call(move |db| {
+        let mut stmt = db.prepare(&format!(
+            "SELECT did, reason, added_at, expires_at FROM {} ORDER BY added_at DESC, did",
+            list.table()
+        ))?;
+        let authors = stmt
+            .query_map([], |row| {
+                Ok(ListedAuthor {
+                    did: row.get(0)?,
+                    reason: row.get(1)?,
+                    added_at: row.get(2)?,
+                    expires_at: row.get(3)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(authors)
+    })
+    .await
+    .map_err(FeedError::from)
+}
+
+/// Both lists for checking posts without going to the db for each one
+pub async fn load_author_lists(db: &Connection) -> FeedResult<AuthorLists> {
+    let _timer = time_query("load_author_lists");
+    db.call(|db| {
+        let mut lists = AuthorLists::default();
+        for (list, entries) in [
+            (AuthorList::Blocked, &mut lists.blocked),
+            (AuthorList::Trusted, &mut lists.trusted),
+        ] {
+            let mut stmt = db.prepare(&format!(
+                "SELECT did, expires_at FROM {} WHERE expires_at IS NULL OR expires_at > unixepoch()",
+                list.table()
+            ))?;
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            for row in rows {
+                let (did, expires_at) = row?;
+                entries.insert(did, expires_at);
+            }
+        }
+        Ok(lists)
+    })
+    .await
+    .map_err(FeedError::from)
+}
+
+/// [AuthorLists::standing] straight from the db
+pub(crate) fn author_standing(
+    db: &rusqlite::Connection,
+    did: &str,
+) -> rusqlite::Result<Option<AuthorList>> {
+    for list in [AuthorList::Blocked, AuthorList::Trusted] {
+        let listed: bool = db.query_row(
+            &format!(
+                "SELECT EXISTS (SELECT 1 FROM {} WHERE did = ?1 AND (expires_at IS NULL OR expires_at > unixepoch()))",
+                list.table()
+            ),
+            [did],
+            |row| row.get(0),
+        )?;
+        if listed {
+            return Ok(Some(list));
+        }
+    }
+    Ok(None)
+}
 
+/// Purges the posts, archived posts and likes of deleted and taken down accounts, hides deactivated
+/// and suspended ones until they are active again. Posts are matched by author and by the repo in
+/// their uri, rows stored before author_did was kept have no author. Returns how many posts were
+/// purged
+pub(crate) fn apply_account_status(
+    db: &rusqlite::Connection,
+    did: &str,
+    status: AccountStatus,
+) -> rusqlite::Result<usize> {
+    match status {
+        AccountStatus::Active => {
+            db.execute("DELETE FROM hidden_accounts WHERE did = ?1", [did])?;
+            Ok(0)
+        }
+        AccountStatus::Deactivated | AccountStatus::Suspended => {
             db.execute(
-                "CREATE INDEX IF NOT EXISTS idx_likes_post_uri ON likes(post_uri)",
-                [],
-            )
-            .map_err(|err| err.into())
-        })
+                "INSERT INTO hidden_accounts (did, status, hidden_at) VALUES (?1, ?2, unixepoch())
+                 ON CONFLICT(did) DO UPDATE SET status = ?2",
+                params![did, status.as_str()],
+            )?;
+            Ok(0)
+        }
+        AccountStatus::Deleted | AccountStatus::TakenDown => {
+            let repo = format!("at://{did}/");
+            db.execute(
+                "DELETE FROM likes WHERE post_uri IN (
+                    SELECT uri FROM posts WHERE author_did = ?1 OR substr(uri, 1, length(?2)) = ?2
+                )",
+                params![did, &repo],
+            )?;
+            //Likes they made on other posts, the like uri is in their repo
+            db.execute(
+                "DELETE FROM likes WHERE substr(like_uri, 1, length(?1)) = ?1",
+                [&repo],
+            )?;
+            let purged = db.execute(
+                "DELETE FROM posts WHERE author_did = ?1 OR substr(uri, 1, length(?2)) = ?2",
+                params![did, &repo],
+            )?;
+            db.execute(
+                "DELETE FROM archived_posts WHERE substr(uri, 1, length(?1)) = ?1",
+                [&repo],
+            )?;
+            db.execute(
+                "DELETE FROM link_shares WHERE substr(post_uri, 1, length(?1)) = ?1",
+                [&repo],
+            )?;
+            db.execute("DELETE FROM hidden_accounts WHERE did = ?1", [did])?;
+            Ok(purged)
+        }
+    }
+}
+
+/// Makes the writes in order in one transaction, see [crate::store::FeedStore::write_batch]
+pub async fn write_feed_batch(db: &Connection, writes: Vec<FeedWrite>) -> FeedResult<BatchReport> {
+    let _timer = time_query("write_batch");
+    db.call(move |db| {
+        let tx = db.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let mut report = BatchReport::default();
+        for write in writes {
+            match write {
+                FeedWrite::Post { post, scoring } => {
+                    if store_scored_post(&tx, &post, &scoring)? == IngestOutcome::Stored {
+                        report.stored += 1;
+                    }
+                }
+                FeedWrite::CuratedPost { post, scoring } => {
+                    insert_post_row(&tx, &post, &scoring, true)?;
+                    report.curated += 1;
+                }
+                FeedWrite::DeletePost { uri } => remove_post(&tx, &uri)?,
+                FeedWrite::Like {
+                    like_uri,
+                    liked_post_uri,
+                } => {
+                    tx.execute(
+                        "INSERT OR IGNORE INTO likes (post_uri, like_uri)
+                         SELECT ?1, ?2
+                         WHERE EXISTS (SELECT 1 FROM posts WHERE uri = ?1)",
+                        params![&liked_post_uri, &like_uri],
+                    )?;
+                }
+                FeedWrite::DeleteLike { like_uri } => {
+                    tx.execute("DELETE FROM likes WHERE like_uri = ?1", params![&like_uri])?;
+                }
+                FeedWrite::Account { did, status } => {
+                    let purged = apply_account_status(&tx, &did, status)?;
+                    if purged > 0 {
+                        info!(
+                            "Purged {purged} posts of {did}, the account is {}",
+                            status.as_str()
+                        );
+                    }
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(report)
+    })
+    .await
+    .map_err(FeedError::from)
+}
+
+/// If another post already shared this link, counts this one as a share of it instead of
+/// storing a copy. Returns true when the post is a share of an existing post, the same post seen
+/// again is still only counted once
+pub async fn add_share_to_existing_link(
+    db: &Connection,
+    link_url: String,
+    uri: String,
+) -> FeedResult<bool> {
+    let _timer = time_query("add_share_to_existing_link");
+    db.call(move |db| Ok(share_existing_link(db, &link_url, &uri)?))
+        .await
+        .map_err(FeedError::from)
+}
+
+/// [add_share_to_existing_link] for callers already on the db thread
+pub(crate) fn share_existing_link(
+    db: &rusqlite::Connection,
+    link_url: &str,
+    uri: &str,
+) -> rusqlite::Result<bool> {
+    let mut stmt = db.prepare(
+        "SELECT uri FROM posts
+         WHERE link_url = ?1 AND uri != ?2 AND deleted = 0
+         ORDER BY priority + shares * ?3 DESC, timestamp ASC
+         LIMIT 1",
+    )?;
+    let mut rows = stmt.query_map(params![link_url, uri, &SHARE_SCORE], |row| {
+        row.get::<_, String>(0)
+    })?;
+    let Some(shared) = rows.next().transpose()? else {
+        return Ok(false);
+    };
+    db.execute(
+        "INSERT INTO link_shares (post_uri, link_url) VALUES (?1, ?2)
+         ON CONFLICT(post_uri) DO NOTHING",
+        params![uri, link_url],
+    )?;
+    db.execute(
+        "UPDATE posts SET shares = (SELECT COUNT(*) FROM link_shares WHERE link_url = ?1)
+         WHERE uri = ?2",
+        params![link_url, &shared],
+    )?;
+    Ok(true)
+}
+
+/// Records feedback sent with app.bsky.feed.sendInteractions against the post, its author and
+/// the feedContext it was served with. "Show less" hides the post from that requester only, the
+/// global priority of a post is never changed by feedback so a few accounts cannot take it down.
+/// Each requester counts once per post and event, sending the same feedback again changes nothing
+pub async fn record_interactions(
+    db: &Connection,
+    requester_did: String,
+    interactions: Vec<(String, String, Option<String>)>,
+) -> FeedResult<()> {
+    let _timer = time_query("record_interactions");
+    db.call(move |db| {
+        let tx = db.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        for (post_uri, event, feed_context) in interactions {
+            //Nothing is kept for posts that are not in the feed
+            let added = tx.execute(
+                "INSERT INTO user_interactions (requester_did, post_uri, author_did, event, created_at)
+                 SELECT ?1, uri, author_did, ?3, unixepoch() FROM posts WHERE uri = ?2
+                 ON CONFLICT(requester_did, post_uri, event) DO NOTHING",
+                params![&requester_did, &post_uri, &event],
+            )?;
+            if added == 0 {
+                continue;
+            }
+            tx.execute(
+                "INSERT INTO post_interactions (post_uri, event, count) VALUES (?1, ?2, 1)
+                 ON CONFLICT(post_uri, event) DO UPDATE SET count = count + 1",
+                params![&post_uri, &event],
+            )?;
+            tx.execute(
+                "INSERT INTO author_interactions (author_did, event, count)
+                 SELECT author_did, ?2, 1 FROM posts WHERE uri = ?1 AND author_did IS NOT NULL
+                 ON CONFLICT(author_did, event) DO UPDATE SET count = count + 1",
+                params![&post_uri, &event],
+            )?;
+            if let Some(feed_context) = feed_context {
+                tx.execute(
+                    "INSERT INTO interaction_contexts (feed_context, event, count) VALUES (?1, ?2, 1)
+                     ON CONFLICT(feed_context, event) DO UPDATE SET count = count + 1",
+                    params![&feed_context, &event],
+                )?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    })
+    .await
+    .map_err(FeedError::from)
+}
+
+/// Remembers which posts a viewer has been served so later sessions can skip them
+pub async fn mark_posts_served(
+    db: &Connection,
+    requester_did: String,
+    post_uris: Vec<String>,
+    served_at: i64,
+) -> FeedResult<()> {
+    let _timer = time_query("mark_posts_served");
+    db.call(move |db| {
+        let tx = db.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        for post_uri in post_uris {
+            //Keeps the first time it was served so it stays visible for the rest of that session
+            tx.execute(
+                "INSERT OR IGNORE INTO served_posts (requester_did, post_uri, served_at) VALUES (?1, ?2, ?3)",
+                params![&requester_did, &post_uri, served_at],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    })
+    .await
+    .map_err(FeedError::from)
+}
+
+pub async fn forget_served_posts_before(db: &Connection, before: i64) -> FeedResult<usize> {
+    let _timer = time_query("forget_served_posts");
+    db.call(move |db| {
+        db.execute(
+            "DELETE FROM served_posts WHERE served_at < ?1",
+            params![before],
+        )
+        .map_err(|err| err.into())
+    })
+    .await
+    .map_err(FeedError::from)
+}
+
+/// "Show more" and "show less" counts for each of the posts asked for
+pub async fn get_interaction_counts(
+    db: &Connection,
+    post_uris: Vec<String>,
+) -> FeedResult<HashMap<String, InteractionCounts>> {
+    let _timer = time_query("interaction_counts");
+    db.call(move |db| {
+        let mut stmt = db.prepare(
+            "SELECT event, count FROM post_interactions
+             WHERE post_uri = ?1 AND event IN (?2, ?3)",
+        )?;
+        let mut counts = HashMap::new();
+        for post_uri in post_uris {
+            let mut post_counts = InteractionCounts::default();
+            let rows = stmt.query_map(params![&post_uri, REQUEST_MORE, REQUEST_LESS], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, u64>(1)?))
+            })?;
+            for row in rows {
+                let (event, count) = row?;
+                if event == REQUEST_MORE {
+                    post_counts.show_more = count;
+                } else {
+                    post_counts.show_less = count;
+                }
+            }
+            counts.insert(post_uri, post_counts);
+        }
+        Ok(counts)
+    })
+    .await
+    .map_err(FeedError::from)
+}
+
+/// Remembers how far into the event stream `source` got, so a restart can pick up from there
+pub async fn save_cursor(
+    db: &Connection,
+    source: String,
+    cursor: i64,
+    updated_at: i64,
+) -> FeedResult<()> {
+    let _timer = time_query("save_cursor");
+    db.call(move |db| {
+        db.execute(
+            "INSERT INTO firehose_cursor (source, cursor, updated_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT(source) DO UPDATE SET cursor = ?2, updated_at = ?3",
+            params![source, cursor, updated_at],
+        )?;
+        Ok(())
+    })
+    .await
+    .map_err(FeedError::from)
+}
+
+pub async fn load_cursor(db: &Connection, source: String) -> FeedResult<Option<i64>> {
+    db.call(move |db| {
+        let mut stmt = db.prepare("SELECT cursor FROM firehose_cursor WHERE source = ?1")?;
+        let mut rows = stmt.query_map(params![source], |row| row.get::<_, i64>(0))?;
+        Ok(rows.next().transpose()?)
+    })
+    .await
+    .map_err(FeedError::from)
+}
+
+/// [migrate_db] for tests and tools that do not care which version the db ends up at
+pub async fn initialize_db(db: &Connection) -> FeedResult<()> {
+    migrate_db(db).await?;
+    Ok(())
+}
+
+/// Brings the schema up to the version this binary knows, see [crate::migrations]
+pub async fn migrate_db(db: &Connection) -> std::result::Result<i64, MigrationError> {
+    db.call(|db| Ok(migrate(db)))
         .await
-        .expect("Failed to initialize database");
+        .map_err(MigrationError::Connection)?
 }
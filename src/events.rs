@@ -0,0 +1,235 @@
+use crate::ingest::{IncomingPost, PostRecord};
+use async_trait::async_trait;
+use serde::Deserialize;
+
+//** NOTICE **
+// Commit events for the collections the feed cares about, however they reached us. Recordings are
+// Jetstream's JSON, one event per line, so that is the format parsed here
+//
+
+pub const POST_COLLECTION: &str = "app.bsky.feed.post";
+pub const LIKE_COLLECTION: &str = "app.bsky.feed.like";
+
+#[derive(Clone, Debug)]
+pub enum FeedEvent {
+    Post {
+        time_us: i64,
+        uri: String,
+        author_did: String,
+        /// Not every source knows it
+        cid: Option<String>,
+        record: PostRecord,
+    },
+    DeletePost {
+        time_us: i64,
+        uri: String,
+    },
+    Like {
+        time_us: i64,
+        like_uri: String,
+        liked_post_uri: String,
+        liker_did: String,
+    },
+    DeleteLike {
+        time_us: i64,
+        like_uri: String,
+    },
+    /// An account was deleted, taken down, deactivated or came back
+    Account {
+        time_us: i64,
+        did: String,
+        status: AccountStatus,
+    },
+}
+
+/// What happened to an account, only the statuses the feed acts on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccountStatus {
+    /// Reactivated or reinstated, its posts are served again
+    Active,
+    /// Hidden until the account is active again
+    Deactivated,
+    Suspended,
+    /// Its posts are purged
+    Deleted,
+    TakenDown,
+}
+
+impl AccountStatus {
+    /// From the `active` and `status` of an account event, `None` for statuses like throttled
+    /// that say nothing about whether its posts should be served
+    pub fn from_account_event(active: bool, status: Option<&str>) -> Option<AccountStatus> {
+        if active {
+            return Some(AccountStatus::Active);
+        }
+        match status? {
+            "deactivated" => Some(AccountStatus::Deactivated),
+            "suspended" => Some(AccountStatus::Suspended),
+            "deleted" => Some(AccountStatus::Deleted),
+            "takendown" => Some(AccountStatus::TakenDown),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccountStatus::Active => "active",
+            AccountStatus::Deactivated => "deactivated",
+            AccountStatus::Suspended => "suspended",
+            AccountStatus::Deleted => "deleted",
+            AccountStatus::TakenDown => "takendown",
+        }
+    }
+}
+
+impl FeedEvent {
+    pub fn time_us(&self) -> i64 {
+        match self {
+            FeedEvent::Post { time_us, .. }
+            | FeedEvent::DeletePost { time_us, .. }
+            | FeedEvent::Like { time_us, .. }
+            | FeedEvent::DeleteLike { time_us, .. }
+            | FeedEvent::Account { time_us, .. } => *time_us,
+        }
+    }
+
+    /// Parses one Jetstream event. `Ok(None)` is an event the feed does not care about, like
+    /// identity events, other collections or record updates
+    pub fn from_jetstream_json(json: &str) -> Result<Option<FeedEvent>, serde_json::Error> {
+        let event: JetstreamEvent = serde_json::from_str(json)?;
+        if event.kind == "account" {
+            let Some(account) = event.account else {
+                return Ok(None);
+            };
+            return Ok(AccountStatus::from_account_event(
+                account.active,
+                account.status.as_deref(),
+            )
+            .map(|status| FeedEvent::Account {
+                time_us: event.time_us,
+                did: event.did,
+                status,
+            }));
+        }
+        let Some(commit) = event.commit.filter(|_| event.kind == "commit") else {
+            return Ok(None);
+        };
+        let uri = format!("at://{}/{}/{}", event.did, commit.collection, commit.rkey);
+        let feed_event = match (commit.operation.as_str(), commit.collection.as_str()) {
+            ("create", POST_COLLECTION) => match commit.record {
+                Some(record) => FeedEvent::Post {
+                    time_us: event.time_us,
+                    uri,
+                    author_did: event.did,
+                    cid: commit.cid,
+                    record: serde_json::from_value(record)?,
+                },
+                None => return Ok(None),
+            },
+            ("delete", POST_COLLECTION) => FeedEvent::DeletePost {
+                time_us: event.time_us,
+                uri,
+            },
+            ("create", LIKE_COLLECTION) => {
+                let Some(record) = commit.record else {
+                    return Ok(None);
+                };
+                let like: LikeRecord = serde_json::from_value(record)?;
+                FeedEvent::Like {
+                    time_us: event.time_us,
+                    like_uri: uri,
+                    liked_post_uri: like.subject.uri,
+                    liker_did: event.did,
+                }
+            }
+            ("delete", LIKE_COLLECTION) => FeedEvent::DeleteLike {
+                time_us: event.time_us,
+                like_uri: uri,
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(feed_event))
+    }
+}
+
+#[derive(Deserialize)]
+struct JetstreamEvent {
+    did: String,
+    time_us: i64,
+    kind: String,
+    commit: Option<JetstreamCommit>,
+    account: Option<JetstreamAccount>,
+}
+
+#[derive(Deserialize)]
+struct JetstreamAccount {
+    active: bool,
+    status: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct JetstreamCommit {
+    operation: String,
+    collection: String,
+    rkey: String,
+    record: Option<serde_json::Value>,
+    cid: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LikeRecord {
+    subject: LikeSubject,
+}
+
+#[derive(Deserialize)]
+struct LikeSubject {
+    uri: String,
+}
+
+/// What every event source calls, the same four calls skyfeed makes on a FeedHandler plus
+/// account changes, which skyfeed does not pass on
+#[async_trait]
+pub trait EventHandler: Send {
+    /// Called for every event before it is handled, with its time_us when the source has one
+    fn event_seen(&mut self, _time_us: Option<i64>) {}
+    async fn insert_post(&mut self, post: IncomingPost);
+    async fn delete_post(&mut self, uri: String);
+    async fn like_post(&mut self, like_uri: String, liked_post_uri: String, liker_did: String);
+    async fn delete_like(&mut self, like_uri: String);
+    async fn account_status(&mut self, _did: String, _status: AccountStatus) {}
+    /// Waits for everything handed over so far to be stored, for handlers that queue
+    async fn flush(&mut self) {}
+}
+
+/// Hands an event to the matching handler call
+pub async fn dispatch<H: EventHandler + ?Sized>(handler: &mut H, event: FeedEvent) {
+    handler.event_seen(Some(event.time_us()));
+    match event {
+        FeedEvent::Post {
+            time_us,
+            uri,
+            author_did,
+            cid,
+            record,
+        } => {
+            //createdAt is whatever the author's client wrote, a post from the future would stay on
+            //top of the feed and out of reach of retention until then
+            let received = time_us / 1_000_000;
+            let timestamp = record
+                .created_at_timestamp()
+                .map_or(received, |created_at| created_at.min(received));
+            handler
+                .insert_post(record.into_incoming_post(uri, author_did, cid, timestamp))
+                .await;
+        }
+        FeedEvent::DeletePost { uri, .. } => handler.delete_post(uri).await,
+        FeedEvent::Like {
+            like_uri,
+            liked_post_uri,
+            liker_did,
+            ..
+        } => handler.like_post(like_uri, liked_post_uri, liker_did).await,
+        FeedEvent::DeleteLike { like_uri, .. } => handler.delete_like(like_uri).await,
+        FeedEvent::Account { did, status, .. } => handler.account_status(did, status).await,
+    }
+}
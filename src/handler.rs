@@ -0,0 +1,112 @@
+use crate::appview::AppViewClient;
+use crate::events::{AccountStatus, EventHandler};
+use crate::health::FirehoseHealth;
+use crate::ingest::{classify_post, IncomingPost};
+use crate::metrics::count_event;
+use crate::models::TextInPost;
+use crate::pipeline::{FeedWrite, IngestPipeline};
+use async_trait::async_trait;
+use chrono::Utc;
+use log::{error, info};
+use std::sync::Arc;
+
+/// Queues what belongs in the feed from every event source. Replayed events go through here the
+/// same as live ones, so everything it does has to be fine with seeing an event twice
+#[derive(Clone)]
+pub struct FeedIngestHandler {
+    pub pipeline: IngestPipeline,
+    pub appview: Arc<AppViewClient>,
+    /// Posts this account likes are pulled into the feed if they fit
+    pub feed_author_did: String,
+    pub firehose: Arc<FirehoseHealth>,
+}
+
+/// A post the publisher liked goes in with a curated feed context, if the classifier agrees
+async fn curate_liked_post(appview: Arc<AppViewClient>, liked_post_uri: String) -> Vec<FeedWrite> {
+    let posts = match appview.get_posts(vec![liked_post_uri.clone()]).await {
+        Ok(posts) => posts,
+        Err(err) => {
+            error!("Failed to look up liked post {liked_post_uri}: {err}");
+            return vec![];
+        }
+    };
+    let mut writes = vec![];
+    for post in posts {
+        let text = post.record.text.clone();
+        let mut post = post.record.into_incoming_post(
+            post.uri,
+            post.author.did,
+            Some(post.cid),
+            Utc::now().timestamp(),
+        );
+        //Only the text of a liked post is classified, the like already says it fits
+        post.texts = vec![TextInPost::Post(text)];
+        let Some(mut scoring) = classify_post(&post) else {
+            continue;
+        };
+        //Tells feedback apart from posts that got in on their own
+        scoring.feed_context = format!("curated|{}", scoring.feed_context);
+        writes.push(FeedWrite::CuratedPost { post, scoring });
+    }
+    writes
+}
+
+#[async_trait]
+impl EventHandler for FeedIngestHandler {
+    fn event_seen(&mut self, time_us: Option<i64>) {
+        self.firehose
+            .record_event(time_us.map(|time_us| time_us / 1_000_000));
+        self.pipeline.event_seen(time_us);
+    }
+
+    async fn insert_post(&mut self, post: IncomingPost) {
+        count_event("post");
+        self.pipeline.classify(post).await;
+    }
+
+    async fn delete_post(&mut self, uri: String) {
+        count_event("delete_post");
+        self.pipeline.write(FeedWrite::DeletePost { uri }).await;
+    }
+
+    async fn like_post(&mut self, like_uri: String, liked_post_uri: String, liker_did: String) {
+        count_event("like");
+        let like = FeedWrite::Like {
+            like_uri,
+            liked_post_uri: liked_post_uri.clone(),
+        };
+        if liker_did != self.feed_author_did {
+            self.pipeline.write(like).await;
+            return;
+        }
+
+        info!("Hey you just liked something");
+        let appview = self.appview.clone();
+        //The like is written after the post it likes
+        self.pipeline
+            .spawn(async move {
+                let mut writes = curate_liked_post(appview, liked_post_uri).await;
+                writes.push(like);
+                writes
+            })
+            .await;
+    }
+
+    async fn delete_like(&mut self, like_uri: String) {
+        count_event("delete_like");
+        self.pipeline
+            .write(FeedWrite::DeleteLike { like_uri })
+            .await;
+    }
+
+    async fn account_status(&mut self, did: String, status: AccountStatus) {
+        count_event("account");
+        self.pipeline
+            .write(FeedWrite::Account { did, status })
+            .await;
+    }
+
+    async fn flush(&mut self) {
+        self.pipeline.flush().await;
+    }
+}
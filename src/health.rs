@@ -0,0 +1,151 @@
+use crate::metrics::FIREHOSE_LAG_SECONDS;
+use crate::store::FeedStore;
+use chrono::Utc;
+use serde::Serialize;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::Duration;
+
+//** NOTICE **
+// A stalled firehose does not error, the feed just stops getting new posts. The handler records
+// every event here and /readyz turns unhealthy once they stop coming or fall too far behind. The
+// lag gauge is set with every event and again on each scrape of /metrics
+//
+
+/// How long without a single event before the firehose counts as stalled
+pub const DEFAULT_STALE_AFTER: Duration = Duration::from_secs(2 * 60);
+/// How far behind the wall clock the latest event can be
+pub const DEFAULT_MAX_LAG: Duration = Duration::from_secs(5 * 60);
+/// How long the db gets to answer a readiness check
+const DB_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub struct FirehoseHealth {
+    stale_after: Duration,
+    max_lag: Duration,
+    /// Wall clock time the last event came in, starts as the time we started listening
+    last_event_at: AtomicI64,
+    /// Time of the latest event, 0 until one comes in. Sources stamp events with the relay's time
+    /// or when they got here, never what the author wrote
+    latest_event_time: AtomicI64,
+}
+
+impl Default for FirehoseHealth {
+    fn default() -> Self {
+        Self::new(DEFAULT_STALE_AFTER, DEFAULT_MAX_LAG)
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct CheckResult {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub problem: Option<String>,
+}
+
+impl CheckResult {
+    fn ok() -> Self {
+        Self {
+            ok: true,
+            problem: None,
+        }
+    }
+
+    fn problem(problem: String) -> Self {
+        Self {
+            ok: false,
+            problem: Some(problem),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    pub firehose: CheckResult,
+    pub lag: CheckResult,
+    pub db: CheckResult,
+}
+
+impl FirehoseHealth {
+    pub fn new(stale_after: Duration, max_lag: Duration) -> Self {
+        let now = Utc::now().timestamp();
+        Self {
+            stale_after,
+            max_lag,
+            last_event_at: AtomicI64::new(now),
+            latest_event_time: AtomicI64::new(0),
+        }
+    }
+
+    /// Call for every firehose event, with the time the event was made when it has one
+    pub fn record_event(&self, event_time: Option<i64>) {
+        self.record_event_at(Utc::now().timestamp(), event_time);
+    }
+
+    /// An event time ahead of when it came in is taken as when it came in, so a clock running fast
+    /// cannot hide lag
+    pub fn record_event_at(&self, received_at: i64, event_time: Option<i64>) {
+        self.last_event_at.store(received_at, Ordering::Relaxed);
+        if let Some(event_time) = event_time {
+            let event_time = event_time.min(received_at);
+            self.latest_event_time.store(event_time, Ordering::Relaxed);
+            FIREHOSE_LAG_SECONDS.set(received_at - event_time);
+        }
+    }
+
+    /// How far behind the latest event is, None until one came in
+    pub fn lag(&self, now: i64) -> Option<i64> {
+        let latest_event_time = self.latest_event_time.load(Ordering::Relaxed);
+        (latest_event_time != 0).then(|| now - latest_event_time)
+    }
+
+    /// Brings the lag gauge up to date, it keeps growing while no events come in
+    pub fn update_lag_metric(&self, now: i64) {
+        if let Some(lag) = self.lag(now) {
+            FIREHOSE_LAG_SECONDS.set(lag);
+        }
+    }
+
+    pub fn check_events(&self, now: i64) -> CheckResult {
+        let quiet_for = now - self.last_event_at.load(Ordering::Relaxed);
+        if quiet_for > self.stale_after.as_secs() as i64 {
+            CheckResult::problem(format!("No firehose events for {quiet_for}s"))
+        } else {
+            CheckResult::ok()
+        }
+    }
+
+    pub fn check_lag(&self, now: i64) -> CheckResult {
+        //Nothing to measure yet, a firehose that never sends anything is caught by check_events
+        let Some(lag) = self.lag(now) else {
+            return CheckResult::ok();
+        };
+        FIREHOSE_LAG_SECONDS.set(lag);
+        if lag > self.max_lag.as_secs() as i64 {
+            CheckResult::problem(format!("Firehose is {lag}s behind"))
+        } else {
+            CheckResult::ok()
+        }
+    }
+
+    /// Everything /readyz reports on
+    pub async fn readiness(&self, store: &dyn FeedStore) -> Readiness {
+        let now = Utc::now().timestamp();
+        let firehose = self.check_events(now);
+        let lag = self.check_lag(now);
+        let db = check_db(store).await;
+        Readiness {
+            ready: firehose.ok && lag.ok && db.ok,
+            firehose,
+            lag,
+            db,
+        }
+    }
+}
+
+async fn check_db(store: &dyn FeedStore) -> CheckResult {
+    match tokio::time::timeout(DB_CHECK_TIMEOUT, store.ping()).await {
+        Ok(Ok(_)) => CheckResult::ok(),
+        Ok(Err(err)) => CheckResult::problem(format!("Database error: {err}")),
+        Err(_) => CheckResult::problem("Database did not answer in time".to_string()),
+    }
+}
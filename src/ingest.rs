@@ -0,0 +1,336 @@
+use crate::db::{author_standing, is_tombstoned, share_existing_link};
+use crate::does_the_post_belong_to_the_feed;
+use crate::links::canonicalize_url;
+use crate::metrics::BLOCKED_AUTHOR_POSTS;
+use crate::models::{AuthorList, PostScoring, TextInPost};
+use crate::store::{FeedResult, FeedStore};
+use chrono::DateTime;
+use log::info;
+use serde::Deserialize;
+use tokio_rusqlite::params;
+
+//** NOTICE **
+// Every way a post reaches us (the firehose, backfill from the AppView, replayed recordings) ends
+// up as an IncomingPost and goes through the same classifier and insert, so they are all treated the same
+//
+
+/// Added to the priority of posts from trusted authors, worth a second blog/thread word
+pub const TRUSTED_AUTHOR_BOOST: i64 = 30;
+
+/// A post ready to go through the classifier, no matter where it came from
+#[derive(Clone, Debug)]
+pub struct IncomingPost {
+    pub uri: String,
+    pub author_did: String,
+    pub texts: Vec<TextInPost>,
+    pub link_url: Option<String>,
+    /// Unix seconds the post was made
+    pub timestamp: i64,
+    /// CID of the record, when the source hands it over
+    pub cid: Option<String>,
+    pub langs: Vec<String>,
+    /// What kind of embed the post has, see `RecordEmbed::kind`
+    pub embed_kind: Option<String>,
+    pub reply: Option<ReplyUris>,
+}
+
+/// The texts of a post the way the posts table keeps them, alt and link texts one per line
+pub(crate) struct StoredTexts {
+    pub text: String,
+    pub alt_text: String,
+    pub link_text: String,
+}
+
+impl IncomingPost {
+    pub(crate) fn stored_texts(&self) -> StoredTexts {
+        let mut text = String::new();
+        let mut alt_text: Vec<String> = vec![];
+        let mut link_text: Vec<String> = vec![];
+        for (index, found) in self.texts.iter().cloned().enumerate() {
+            match found {
+                TextInPost::Post(post_text) if index == 0 => text = post_text,
+                TextInPost::Picture(alt) | TextInPost::Video(alt) => alt_text.push(alt),
+                TextInPost::External(link) => link_text.push(link),
+                TextInPost::Post(_) => {}
+            }
+        }
+        StoredTexts {
+            text,
+            alt_text: alt_text.join("\n"),
+            link_text: link_text.join("\n"),
+        }
+    }
+}
+
+/// The post a reply answers and the top of its thread
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReplyUris {
+    pub parent: String,
+    pub root: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum IngestOutcome {
+    Stored,
+    /// Another post already shared the same link, this one was added to its shares
+    CountedAsShare,
+    Rejected,
+    /// Removed from the admin tool before, it stays out
+    Moderated,
+    /// The author is on the blocklist
+    Blocked,
+}
+
+/// An app.bsky.feed.post record as it is in a repo, only the parts we look at
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PostRecord {
+    #[serde(default)]
+    pub text: String,
+    pub created_at: Option<String>,
+    pub embed: Option<RecordEmbed>,
+    #[serde(default)]
+    pub langs: Vec<String>,
+    pub reply: Option<ReplyRef>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ReplyRef {
+    pub parent: StrongRef,
+    pub root: StrongRef,
+}
+
+/// com.atproto.repo.strongRef, the cid is left out since we only keep the uri
+#[derive(Clone, Debug, Deserialize)]
+pub struct StrongRef {
+    pub uri: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "$type")]
+pub enum RecordEmbed {
+    #[serde(rename = "app.bsky.embed.external")]
+    External { external: ExternalLink },
+    #[serde(rename = "app.bsky.embed.images")]
+    Images { images: Vec<EmbedImage> },
+    #[serde(rename = "app.bsky.embed.video")]
+    Video { alt: Option<String> },
+    #[serde(rename = "app.bsky.embed.record")]
+    Record,
+    #[serde(rename = "app.bsky.embed.recordWithMedia")]
+    RecordWithMedia { media: Box<RecordEmbed> },
+    #[serde(other)]
+    Unknown,
+}
+
+impl RecordEmbed {
+    /// The name stored in posts.embed_kind
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RecordEmbed::External { .. } => "external",
+            RecordEmbed::Images { .. } => "images",
+            RecordEmbed::Video { .. } => "video",
+            RecordEmbed::Record => "record",
+            RecordEmbed::RecordWithMedia { .. } => "record_with_media",
+            RecordEmbed::Unknown => "unknown",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ExternalLink {
+    pub uri: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct EmbedImage {
+    #[serde(default)]
+    pub alt: String,
+}
+
+impl PostRecord {
+    /// Pulls out the text and link the same way the firehose handler does for skyfeed posts
+    pub fn texts_and_link(&self) -> (Vec<TextInPost>, Option<String>) {
+        let mut text_types: Vec<TextInPost> = vec![TextInPost::Post(self.text.clone())];
+        let mut link_url: Option<String> = None;
+        match &self.embed {
+            None => {}
+            Some(RecordEmbed::Video { alt }) => {
+                text_types.push(TextInPost::Video(alt.clone().unwrap_or_default()));
+            }
+            Some(RecordEmbed::External { external }) => {
+                text_types.push(TextInPost::External(external.title.clone()));
+                text_types.push(TextInPost::External(external.description.clone()));
+                link_url = canonicalize_url(&external.uri);
+            }
+            Some(RecordEmbed::Record) | Some(RecordEmbed::Unknown) => {}
+            Some(RecordEmbed::RecordWithMedia { media }) => match media.as_ref() {
+                RecordEmbed::Images { images } => {
+                    for image in images {
+                        text_types.push(TextInPost::Picture(image.alt.clone()));
+                    }
+                }
+                RecordEmbed::External { external } => {
+                    link_url = canonicalize_url(&external.uri);
+                }
+                _ => {}
+            },
+            Some(RecordEmbed::Images { images }) => {
+                for image in images {
+                    text_types.push(TextInPost::Picture(image.alt.clone()));
+                }
+            }
+        }
+        (text_types, link_url)
+    }
+
+    /// Unix seconds from createdAt, `None` when it is missing or not a datetime
+    pub fn created_at_timestamp(&self) -> Option<i64> {
+        let created_at = self.created_at.as_ref()?;
+        DateTime::parse_from_rfc3339(created_at)
+            .ok()
+            .map(|created_at| created_at.timestamp())
+    }
+
+    pub fn into_incoming_post(
+        self,
+        uri: String,
+        author_did: String,
+        cid: Option<String>,
+        timestamp: i64,
+    ) -> IncomingPost {
+        let (texts, link_url) = self.texts_and_link();
+        IncomingPost {
+            uri,
+            author_did,
+            texts,
+            link_url,
+            timestamp,
+            cid,
+            langs: self.langs,
+            embed_kind: self.embed.as_ref().map(|embed| embed.kind().to_string()),
+            reply: self.reply.map(|reply| ReplyUris {
+                parent: reply.parent.uri,
+                root: reply.root.uri,
+            }),
+        }
+    }
+}
+
+/// Classifies a post and stores it when it belongs in the feed. Posts already stored are left
+/// alone so seeing the same post twice (replays, backfill, cursor rewinds) changes nothing
+pub async fn ingest_post(store: &dyn FeedStore, post: IncomingPost) -> FeedResult<IngestOutcome> {
+    let standing = store.author_standing(post.author_did.clone()).await?;
+    if standing == Some(AuthorList::Blocked) {
+        BLOCKED_AUTHOR_POSTS.inc();
+        return Ok(IngestOutcome::Blocked);
+    }
+    let Some(scoring) = classify_post_by(&post, standing) else {
+        return Ok(IngestOutcome::Rejected);
+    };
+    store.store_post(post, scoring).await
+}
+
+/// `None` when the post does not belong in the feed
+pub fn classify_post(post: &IncomingPost) -> Option<PostScoring> {
+    does_the_post_belong_to_the_feed(post.texts.clone())
+}
+
+/// [classify_post] for an author on one of the author lists
+pub fn classify_post_by(post: &IncomingPost, standing: Option<AuthorList>) -> Option<PostScoring> {
+    match standing {
+        Some(AuthorList::Blocked) => None,
+        Some(AuthorList::Trusted) => classify_post(post).map(|mut scoring| {
+            scoring.priority += TRUSTED_AUTHOR_BOOST;
+            scoring.matched_rules.push("trusted_author".to_string());
+            scoring
+        }),
+        None => classify_post(post),
+    }
+}
+
+/// Stores a post the classifier accepted, or counts it as a share when its link is already in the feed
+pub(crate) fn store_scored_post(
+    db: &rusqlite::Connection,
+    post: &IncomingPost,
+    scoring: &PostScoring,
+) -> rusqlite::Result<IngestOutcome> {
+    //The lists may have changed since the post was classified
+    if author_standing(db, &post.author_did)? == Some(AuthorList::Blocked) {
+        BLOCKED_AUTHOR_POSTS.inc();
+        return Ok(IngestOutcome::Blocked);
+    }
+    if is_tombstoned(db, &post.uri)? {
+        info!("Not storing {}, it was removed by a moderator", post.uri);
+        return Ok(IngestOutcome::Moderated);
+    }
+    if let Some(link_url) = &post.link_url {
+        if share_existing_link(db, link_url, &post.uri)? {
+            info!("Counted {} as another share of {link_url}", post.uri);
+            return Ok(IngestOutcome::CountedAsShare);
+        }
+    }
+
+    info!("Storing {}", post.uri);
+    insert_post_row(db, post, scoring, false)?;
+    Ok(IngestOutcome::Stored)
+}
+
+/// Writes every column we keep about a post. `replace` overwrites a row already there, which is
+/// what curated posts want, otherwise the first one stored wins. Posts with a tombstone or from a
+/// blocked author are never written. An upsert rather than INSERT OR REPLACE so the search index
+/// triggers see an update instead of a silent delete
+pub(crate) fn insert_post_row(
+    db: &rusqlite::Connection,
+    post: &IncomingPost,
+    scoring: &PostScoring,
+    replace: bool,
+) -> rusqlite::Result<()> {
+    let texts = post.stored_texts();
+    let langs = serde_json::to_string(&post.langs).unwrap_or_default();
+    let matched_rules = serde_json::to_string(&scoring.matched_rules).unwrap_or_default();
+    let on_conflict = if replace {
+        "DO UPDATE SET text = excluded.text, author_did = excluded.author_did, pinned = 0, deleted = 0,
+            priority = excluded.priority, timestamp = excluded.timestamp, link_url = excluded.link_url,
+            shares = 0, feed_context = excluded.feed_context, cid = excluded.cid, langs = excluded.langs,
+            embed_kind = excluded.embed_kind, reply_parent = excluded.reply_parent,
+            reply_root = excluded.reply_root, matched_rules = excluded.matched_rules,
+            alt_text = excluded.alt_text, link_text = excluded.link_text"
+    } else {
+        "DO NOTHING"
+    };
+    db.execute(
+        &format!(
+            "INSERT INTO posts (uri, text, author_did, pinned, deleted, priority, timestamp, link_url, feed_context, cid, langs, embed_kind, reply_parent, reply_root, matched_rules, alt_text, link_text)
+             SELECT ?1, ?2, ?3, 0, 0, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15
+             WHERE NOT EXISTS (SELECT 1 FROM moderation_tombstones WHERE uri = ?1)
+             AND NOT EXISTS (
+                SELECT 1 FROM blocked_authors
+                WHERE did = ?3 AND (expires_at IS NULL OR expires_at > unixepoch())
+             )
+             ON CONFLICT(uri) {on_conflict}"
+        ),
+        params![
+            &post.uri,
+            &texts.text,
+            &post.author_did,
+            scoring.priority,
+            post.timestamp,
+            &post.link_url,
+            &scoring.feed_context,
+            &post.cid,
+            &langs,
+            &post.embed_kind,
+            post.reply.as_ref().map(|reply| &reply.parent),
+            post.reply.as_ref().map(|reply| &reply.root),
+            &matched_rules,
+            &texts.alt_text,
+            &texts.link_text,
+        ],
+    )?;
+    Ok(())
+}
@@ -1,5 +1,27 @@
+pub mod appview;
+pub mod auth;
+pub mod backfill;
+pub mod config;
+pub mod cursor;
 pub mod db;
+pub mod events;
+pub mod handler;
+pub mod health;
+pub mod ingest;
+pub mod links;
+pub mod metrics;
+pub mod migrations;
 pub mod models;
+pub mod pipeline;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod replay;
+pub mod retention;
+pub mod server;
+pub mod skeleton;
+pub mod source;
+pub mod sqlite;
+pub mod store;
 use crate::models::{PostScoring, TextInPost};
 use log::info;
 use once_cell::sync::Lazy;
@@ -39,6 +61,11 @@ pub fn does_the_post_belong_to_the_feed(all_text_in_post: Vec<TextInPost>) -> Op
     let mut fits_topic = false;
     let mut post_text = String::new();
     let mut scoring = 0;
+    //The first topic and blog/thread words found, kept so we know why the post made it in
+    let mut topic: Option<String> = None;
+    let mut signal: Option<String> = None;
+    //Which rules matched, counted once the post is accepted or rejected
+    let mut matched_rules: Vec<&str> = vec![];
     for text in all_text_in_post {
         let string_of_text = text.clone().to_string();
         let should_it_be_censored = string_of_text.is_inappropriate();
@@ -47,10 +74,14 @@ pub fn does_the_post_belong_to_the_feed(all_text_in_post: Vec<TextInPost>) -> Op
             if PROGRAMMER_JARGON.is_match(string_of_text.as_str()) {
                 info!("False positive to check?: {string_of_text}");
             }
+            matched_rules.push("inappropriate");
+            metrics::count_rule_matches(&matched_rules, false);
             return None;
         }
 
         if DO_NOT_POST.is_match(string_of_text.as_str()) {
+            matched_rules.push("do_not_post");
+            metrics::count_rule_matches(&matched_rules, false);
             return None;
         }
 
@@ -58,47 +89,63 @@ pub fn does_the_post_belong_to_the_feed(all_text_in_post: Vec<TextInPost>) -> Op
         match text {
             TextInPost::Post(post) => {
                 post_text = post.clone();
-                if PROGRAMMER_JARGON.is_match(post.as_str()) {
+                if let Some(found) = PROGRAMMER_JARGON.find(post.as_str()) {
+                    topic.get_or_insert_with(|| found.as_str().to_lowercase());
                     scoring += 10;
                     fits_topic = true;
+                    matched_rules.push("programmer_jargon");
                 }
-                if BLOG_JARGON.is_match(post.as_str()) {
+                if let Some(found) = BLOG_JARGON.find(post.as_str()) {
+                    signal.get_or_insert_with(|| found.as_str().to_lowercase());
                     scoring += 30;
                     contains_identifier_its_a_blog_or_thread = true;
                     should_be_saved = true;
+                    matched_rules.push("blog_jargon");
                 }
             }
             TextInPost::Picture(picture) => {
-                if PROGRAMMER_JARGON.is_match(picture.as_str()) {
+                if let Some(found) = PROGRAMMER_JARGON.find(picture.as_str()) {
+                    topic.get_or_insert_with(|| found.as_str().to_lowercase());
                     scoring += 15;
                     fits_topic = true;
+                    matched_rules.push("programmer_jargon");
                 }
-                if BLOG_JARGON.is_match(picture.as_str()) {
+                if let Some(found) = BLOG_JARGON.find(picture.as_str()) {
+                    signal.get_or_insert_with(|| found.as_str().to_lowercase());
                     scoring += 30;
                     contains_identifier_its_a_blog_or_thread = true;
                     should_be_saved = true;
+                    matched_rules.push("blog_jargon");
                 }
             }
             TextInPost::Video(video) => {
-                if PROGRAMMER_JARGON.is_match(video.as_str()) {
+                if let Some(found) = PROGRAMMER_JARGON.find(video.as_str()) {
+                    topic.get_or_insert_with(|| found.as_str().to_lowercase());
                     scoring += 15;
                     fits_topic = true;
+                    matched_rules.push("programmer_jargon");
                 }
-                if BLOG_JARGON.is_match(video.as_str()) {
+                if let Some(found) = BLOG_JARGON.find(video.as_str()) {
+                    signal.get_or_insert_with(|| found.as_str().to_lowercase());
                     scoring += 15;
                     contains_identifier_its_a_blog_or_thread = true;
                     should_be_saved = true;
+                    matched_rules.push("blog_jargon");
                 }
             }
             TextInPost::External(external) => {
-                if PROGRAMMER_JARGON.is_match(external.as_str()) {
+                if let Some(found) = PROGRAMMER_JARGON.find(external.as_str()) {
+                    topic.get_or_insert_with(|| found.as_str().to_lowercase());
                     scoring += 15;
                     fits_topic = true;
+                    matched_rules.push("programmer_jargon");
                 }
-                if BLOG_JARGON.is_match(external.as_str()) {
+                if let Some(found) = BLOG_JARGON.find(external.as_str()) {
+                    signal.get_or_insert_with(|| found.as_str().to_lowercase());
                     scoring += 30;
                     contains_identifier_its_a_blog_or_thread = true;
                     should_be_saved = true;
+                    matched_rules.push("blog_jargon");
                 }
             }
         };
@@ -106,17 +153,36 @@ pub fn does_the_post_belong_to_the_feed(all_text_in_post: Vec<TextInPost>) -> Op
         //TODO later may check if its blog or thread and only save then
         //should_be_saved
         if fits_topic && should_be_saved {
+            metrics::count_rule_matches(&matched_rules, true);
             return Some(PostScoring {
                 pinned: false,
                 deleted: false,
                 priority: scoring,
+                feed_context: format!(
+                    "topic:{}|signal:{}",
+                    topic.unwrap_or_default(),
+                    signal.unwrap_or_default()
+                ),
+                matched_rules: unique_rules(&matched_rules),
             });
         }
     }
 
+    metrics::count_rule_matches(&matched_rules, false);
     None
 }
 
+/// Rules can match more than once, in the post and again in a link card
+fn unique_rules(matched_rules: &[&str]) -> Vec<String> {
+    let mut rules: Vec<String> = vec![];
+    for rule in matched_rules {
+        if !rules.iter().any(|kept| kept == rule) {
+            rules.push(rule.to_string());
+        }
+    }
+    rules
+}
+
 mod tests {
     use crate::does_the_post_belong_to_the_feed;
     use crate::models::{PostScoring, TextInPost};
@@ -131,7 +197,9 @@ mod tests {
                 pinned: false,
                 deleted: false,
                 //Lower scoring because no pictures or links
-                priority: 40
+                priority: 40,
+                feed_context: "topic:rust|signal:blog".to_string(),
+                matched_rules: vec!["programmer_jargon".to_string(), "blog_jargon".to_string()],
             })
         );
         print!("{:?}", score);
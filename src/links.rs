@@ -0,0 +1,94 @@
+use atrium_api::app::bsky::embed::record_with_media::ViewMediaRefs;
+use atrium_api::app::bsky::feed::defs::{PostView, PostViewEmbedRefs};
+use atrium_api::types::Union;
+use url::Url;
+
+/// Query parameters that only track where a click came from and never change what the page is
+const TRACKING_PARAMS: [&str; 11] = [
+    "fbclid", "gclid", "dclid", "msclkid", "igshid", "mc_cid", "mc_eid", "ref", "ref_src",
+    "ref_url", "si",
+];
+
+/// Turns an external link into one form so the same blog post shared many ways matches up.
+/// Drops tracking params, the fragment, `www.` and trailing slashes and always uses https
+pub fn canonicalize_url(link: &str) -> Option<String> {
+    let url = Url::parse(link.trim()).ok()?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return None;
+    }
+
+    let host = url.host_str()?;
+    let host = host.strip_prefix("www.").unwrap_or(host);
+
+    let mut canonical = format!("https://{host}");
+    if let Some(port) = url.port() {
+        canonical.push_str(&format!(":{port}"));
+    }
+    canonical.push_str(url.path().trim_end_matches('/'));
+
+    let query: Vec<String> = url
+        .query_pairs()
+        .filter(|(key, _)| {
+            let key = key.to_lowercase();
+            !key.starts_with("utm_") && !TRACKING_PARAMS.contains(&key.as_str())
+        })
+        .map(|(key, value)| {
+            if value.is_empty() {
+                key.to_string()
+            } else {
+                format!("{key}={value}")
+            }
+        })
+        .collect();
+    if !query.is_empty() {
+        canonical.push('?');
+        canonical.push_str(&query.join("&"));
+    }
+
+    Some(canonical)
+}
+
+/// Finds the external link of a post loaded from the AppView, either on its own or next to a quote
+pub fn external_link_in_post_view(post: &PostView) -> Option<String> {
+    match &post.embed {
+        Some(Union::Refs(PostViewEmbedRefs::AppBskyEmbedExternalView(view))) => {
+            canonicalize_url(&view.external.uri)
+        }
+        Some(Union::Refs(PostViewEmbedRefs::AppBskyEmbedRecordWithMediaView(view))) => {
+            match &view.media {
+                Union::Refs(ViewMediaRefs::AppBskyEmbedExternalView(media)) => {
+                    canonicalize_url(&media.external.uri)
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::links::canonicalize_url;
+
+    #[test]
+    fn test_canonicalize_url() {
+        let canonical = Some("https://example.com/blog/rust".to_string());
+        assert_eq!(
+            canonicalize_url("https://www.example.com/blog/rust/"),
+            canonical
+        );
+        assert_eq!(
+            canonicalize_url("http://example.com/blog/rust?utm_source=bsky&utm_medium=social"),
+            canonical
+        );
+        assert_eq!(
+            canonicalize_url("https://WWW.Example.com/blog/rust#comments"),
+            canonical
+        );
+        assert_eq!(
+            canonicalize_url("https://example.com/watch?v=abc&si=tracking"),
+            Some("https://example.com/watch?v=abc".to_string())
+        );
+        assert_eq!(canonicalize_url("not a link"), None);
+    }
+}
@@ -0,0 +1,191 @@
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+
+//** NOTICE **
+// Everything here is served on /metrics of the XRPC server. Posts seen per second is
+// rate(feed_firehose_events_total{kind="post"}[1m]) and the acceptance rate of a rule is its
+// accepted matches over all of its matches
+//
+
+pub static REGISTRY: Lazy<Registry> = Lazy::new(Registry::new);
+
+/// Events handed to the handler by the firehose, `kind` is post, delete_post, like, delete_like or account
+pub static FIREHOSE_EVENTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "feed_firehose_events_total",
+            "Firehose events processed by the feed",
+        ),
+        &["kind"],
+    ))
+});
+
+/// Each classifier rule that matched a post, and whether the post made it into the feed
+pub static CLASSIFIER_RULE_MATCHES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "feed_classifier_rule_matches_total",
+            "Posts a classifier rule matched, by outcome",
+        ),
+        &["rule", "outcome"],
+    ))
+});
+
+pub static POSTS_STORED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "feed_posts_stored_total",
+            "Posts stored in the feed, by how they got in",
+        ),
+        &["source"],
+    ))
+});
+
+/// Posts from blocked authors, dropped before the classifier
+pub static BLOCKED_AUTHOR_POSTS: Lazy<IntCounter> = Lazy::new(|| {
+    register(IntCounter::new(
+        "feed_blocked_author_posts_total",
+        "Posts rejected because their author is blocked",
+    ))
+});
+
+/// Posts moved out of the posts table by retention
+pub static POSTS_ARCHIVED: Lazy<IntCounter> = Lazy::new(|| {
+    register(IntCounter::new(
+        "feed_posts_archived_total",
+        "Posts archived by the retention policy",
+    ))
+});
+
+pub static SERVE_FEED_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register(HistogramVec::new(
+        HistogramOpts::new(
+            "feed_serve_feed_seconds",
+            "Time taken to build a getFeedSkeleton page",
+        ),
+        &["server"],
+    ))
+});
+
+pub static PAGE_SIZE: Lazy<HistogramVec> = Lazy::new(|| {
+    register(HistogramVec::new(
+        HistogramOpts::new("feed_page_size", "Posts served on a getFeedSkeleton page")
+            .buckets(vec![0.0, 1.0, 5.0, 10.0, 25.0, 50.0, 75.0, 100.0]),
+        &["server"],
+    ))
+});
+
+pub static DB_QUERY_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register(HistogramVec::new(
+        HistogramOpts::new("feed_db_query_seconds", "Time taken by database queries").buckets(
+            vec![0.0005, 0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0],
+        ),
+        &["query"],
+    ))
+});
+
+/// Refreshed every time /metrics is scraped
+pub static POSTS_ROWS: Lazy<IntGauge> =
+    Lazy::new(|| register(IntGauge::new("feed_posts_rows", "Rows in the posts table")));
+
+/// How far behind the wall clock the newest firehose event is, set by the readiness check
+pub static FIREHOSE_LAG_SECONDS: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new(
+        "feed_firehose_lag_seconds",
+        "Seconds between the newest firehose event and now",
+    ))
+});
+
+/// Events waiting to be classified and written
+pub static INGEST_QUEUE_DEPTH: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new(
+        "feed_ingest_queue_depth",
+        "Events waiting in the ingest queue",
+    ))
+});
+
+/// Times an event found the ingest queue full, `outcome` is waited or dropped
+pub static INGEST_QUEUE_FULL: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "feed_ingest_queue_full_total",
+            "Events that found the ingest queue full, by what happened to them",
+        ),
+        &["outcome"],
+    ))
+});
+
+/// Writes the db kept rejecting while it was up, given up on so the rest of the queue goes in
+pub static INGEST_WRITES_SKIPPED: Lazy<IntCounter> = Lazy::new(|| {
+    register(IntCounter::new(
+        "feed_ingest_writes_skipped_total",
+        "Writes skipped after the db rejected them every time",
+    ))
+});
+
+pub static INGEST_BATCH_SIZE: Lazy<Histogram> = Lazy::new(|| {
+    register(Histogram::with_opts(
+        HistogramOpts::new(
+            "feed_ingest_batch_size",
+            "Writes committed together in one transaction",
+        )
+        .buckets(vec![1.0, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0]),
+    ))
+});
+
+/// Database calls that failed and were logged instead of panicking, by what was being done
+pub static DB_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "feed_db_errors_total",
+            "Database calls that failed, by operation",
+        ),
+        &["operation"],
+    ))
+});
+
+fn register<T: prometheus::core::Collector + Clone + 'static>(metric: prometheus::Result<T>) -> T {
+    let metric = metric.expect("Metric options are valid");
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("Metric is only registered once");
+    metric
+}
+
+pub fn count_event(kind: &str) {
+    FIREHOSE_EVENTS.with_label_values(&[kind]).inc();
+}
+
+pub fn count_db_error(operation: &str) {
+    DB_ERRORS.with_label_values(&[operation]).inc();
+}
+
+/// A rule matching more than one part of the same post is still one match
+pub fn count_rule_matches(rules: &[&str], accepted: bool) {
+    let outcome = if accepted { "accepted" } else { "rejected" };
+    let mut rules = rules.to_vec();
+    rules.sort();
+    rules.dedup();
+    for rule in rules {
+        CLASSIFIER_RULE_MATCHES
+            .with_label_values(&[rule, outcome])
+            .inc();
+    }
+}
+
+/// Observes how long the query took once the returned timer is dropped
+pub fn time_query(query: &str) -> prometheus::HistogramTimer {
+    DB_QUERY_SECONDS.with_label_values(&[query]).start_timer()
+}
+
+/// Every metric in the Prometheus text format
+pub fn gather() -> String {
+    let mut buffer = vec![];
+    TextEncoder::new()
+        .encode(&REGISTRY.gather(), &mut buffer)
+        .expect("Metrics encode to text");
+    String::from_utf8(buffer).expect("Metrics text is utf8")
+}
@@ -0,0 +1,398 @@
+use log::info;
+use rusqlite::{Transaction, TransactionBehavior};
+use std::fmt;
+
+//** NOTICE **
+// The schema version lives in `PRAGMA user_version`. Each migration runs in its own transaction
+// together with the bump of user_version, so a failed one leaves the db at the version before it.
+// Each takes the write lock before checking the version, so binaries starting together apply it once.
+// Migrations 1 to 5 cover everything made before versioning, when every db said version 0, so
+// they only create what is missing. Anything after them can change the schema freely. Never edit
+// a migration that has shipped, add a new one to the end
+//
+
+pub struct Migration {
+    pub version: i64,
+    pub description: &'static str,
+    apply: fn(&Transaction) -> rusqlite::Result<()>,
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "posts and likes",
+        apply: baseline,
+    },
+    Migration {
+        version: 2,
+        description: "author, link and feed context of posts",
+        apply: post_columns,
+    },
+    Migration {
+        version: 3,
+        description: "interaction counts",
+        apply: interactions,
+    },
+    Migration {
+        version: 4,
+        description: "posts served to each viewer",
+        apply: served_posts,
+    },
+    Migration {
+        version: 5,
+        description: "firehose cursor",
+        apply: firehose_cursor,
+    },
+    Migration {
+        version: 6,
+        description: "post metadata",
+        apply: post_metadata,
+    },
+    Migration {
+        version: 7,
+        description: "moderation tombstones",
+        apply: moderation_tombstones,
+    },
+    Migration {
+        version: 8,
+        description: "blocked and trusted authors",
+        apply: author_lists,
+    },
+    Migration {
+        version: 9,
+        description: "hidden accounts",
+        apply: hidden_accounts,
+    },
+    Migration {
+        version: 10,
+        description: "full text search",
+        apply: full_text_search,
+    },
+    Migration {
+        version: 11,
+        description: "post retention",
+        apply: post_retention,
+    },
+    Migration {
+        version: 12,
+        description: "posts counted as shares of a link",
+        apply: link_shares,
+    },
+    Migration {
+        version: 13,
+        description: "one interaction per requester, post and event",
+        apply: unique_user_interactions,
+    },
+];
+
+/// The version this binary migrates up to
+pub fn schema_version() -> i64 {
+    MIGRATIONS.last().map_or(0, |migration| migration.version)
+}
+
+#[derive(Debug)]
+pub enum MigrationError {
+    /// Made by a newer binary, running against it could undo what it changed
+    NewerThanBinary {
+        found: i64,
+        supported: i64,
+    },
+    Failed {
+        version: i64,
+        description: &'static str,
+        source: rusqlite::Error,
+    },
+    Sqlite(rusqlite::Error),
+    Connection(tokio_rusqlite::Error),
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::NewerThanBinary { found, supported } => write!(
+                f,
+                "The database is at schema version {found} but this binary only knows up to {supported}. Update the binary before running it against this database"
+            ),
+            MigrationError::Failed {
+                version,
+                description,
+                source,
+            } => write!(f, "Migration {version} ({description}) failed: {source}"),
+            MigrationError::Sqlite(err) => write!(f, "Could not read the schema version: {err}"),
+            MigrationError::Connection(err) => write!(f, "Could not reach the database: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+impl From<rusqlite::Error> for MigrationError {
+    fn from(err: rusqlite::Error) -> Self {
+        MigrationError::Sqlite(err)
+    }
+}
+
+pub fn user_version(db: &rusqlite::Connection) -> rusqlite::Result<i64> {
+    db.query_row("PRAGMA user_version", [], |row| row.get(0))
+}
+
+/// Applies every migration newer than the db, returns the version it ends up at
+pub fn migrate(db: &mut rusqlite::Connection) -> Result<i64, MigrationError> {
+    let current = user_version(db)?;
+    let supported = schema_version();
+    if current > supported {
+        return Err(MigrationError::NewerThanBinary {
+            found: current,
+            supported,
+        });
+    }
+
+    for migration in MIGRATIONS
+        .iter()
+        .filter(|migration| migration.version > current)
+    {
+        let failed = |source| MigrationError::Failed {
+            version: migration.version,
+            description: migration.description,
+            source,
+        };
+        let tx = db
+            .transaction_with_behavior(TransactionBehavior::Immediate)
+            .map_err(failed)?;
+        //The other binary may have applied it while this one waited for the lock
+        if user_version(&tx).map_err(failed)? >= migration.version {
+            continue;
+        }
+        info!(
+            "Migrating the database to version {}: {}",
+            migration.version, migration.description
+        );
+        (migration.apply)(&tx).map_err(failed)?;
+        tx.pragma_update(None, "user_version", migration.version)
+            .map_err(failed)?;
+        tx.commit().map_err(failed)?;
+    }
+    Ok(supported)
+}
+
+fn baseline(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS posts (
+            uri TEXT PRIMARY KEY,
+            text TEXT,
+            pinned INTEGER,
+            deleted INTEGER,
+            priority INTEGER,
+            timestamp INTEGER
+        );
+        CREATE TABLE IF NOT EXISTS likes (
+            post_uri TEXT,
+            like_uri TEXT,
+            PRIMARY KEY (post_uri, like_uri),
+            FOREIGN KEY (post_uri) REFERENCES posts(uri) ON DELETE CASCADE
+        );
+        CREATE INDEX IF NOT EXISTS idx_likes_post_uri ON likes(post_uri);",
+    )
+}
+
+fn post_columns(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column_if_missing(tx, "posts", "author_did", "TEXT")?;
+    add_column_if_missing(tx, "posts", "link_url", "TEXT")?;
+    add_column_if_missing(tx, "posts", "shares", "INTEGER NOT NULL DEFAULT 0")?;
+    add_column_if_missing(tx, "posts", "feed_context", "TEXT")?;
+    tx.execute_batch("CREATE INDEX IF NOT EXISTS idx_posts_link_url ON posts(link_url);")
+}
+
+fn interactions(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS post_interactions (
+            post_uri TEXT,
+            event TEXT,
+            count INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY (post_uri, event)
+        );
+        CREATE TABLE IF NOT EXISTS author_interactions (
+            author_did TEXT,
+            event TEXT,
+            count INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY (author_did, event)
+        );
+        CREATE TABLE IF NOT EXISTS interaction_contexts (
+            feed_context TEXT,
+            event TEXT,
+            count INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY (feed_context, event)
+        );
+        CREATE TABLE IF NOT EXISTS user_interactions (
+            requester_did TEXT,
+            post_uri TEXT,
+            author_did TEXT,
+            event TEXT,
+            created_at INTEGER
+        );
+        CREATE INDEX IF NOT EXISTS idx_user_interactions_requester ON user_interactions(requester_did);",
+    )
+}
+
+fn served_posts(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS served_posts (
+            requester_did TEXT,
+            post_uri TEXT,
+            served_at INTEGER,
+            PRIMARY KEY (requester_did, post_uri)
+        );",
+    )
+}
+
+fn firehose_cursor(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS firehose_cursor (
+            source TEXT PRIMARY KEY,
+            cursor INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL
+        );",
+    )
+}
+
+fn post_metadata(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column_if_missing(tx, "posts", "cid", "TEXT")?;
+    //langs and matched_rules are JSON arrays
+    add_column_if_missing(tx, "posts", "langs", "TEXT")?;
+    add_column_if_missing(tx, "posts", "embed_kind", "TEXT")?;
+    add_column_if_missing(tx, "posts", "reply_parent", "TEXT")?;
+    add_column_if_missing(tx, "posts", "reply_root", "TEXT")?;
+    add_column_if_missing(tx, "posts", "matched_rules", "TEXT")?;
+    tx.execute_batch(
+        "CREATE INDEX IF NOT EXISTS idx_posts_author_did ON posts(author_did);
+        CREATE INDEX IF NOT EXISTS idx_posts_reply_root ON posts(reply_root);",
+    )
+}
+
+/// Outlives the post row, so a removed post stays removed even after cleanup drops it
+fn moderation_tombstones(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS moderation_tombstones (
+            uri TEXT PRIMARY KEY,
+            reason TEXT NOT NULL,
+            removed_at INTEGER NOT NULL
+        );",
+    )
+}
+
+fn author_lists(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS blocked_authors (
+            did TEXT PRIMARY KEY,
+            reason TEXT,
+            added_at INTEGER NOT NULL,
+            expires_at INTEGER
+        );
+        CREATE TABLE IF NOT EXISTS trusted_authors (
+            did TEXT PRIMARY KEY,
+            reason TEXT,
+            added_at INTEGER NOT NULL,
+            expires_at INTEGER
+        );",
+    )
+}
+
+/// Deactivated and suspended accounts, their posts are kept but not served
+fn hidden_accounts(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS hidden_accounts (
+            did TEXT PRIMARY KEY,
+            status TEXT NOT NULL,
+            hidden_at INTEGER NOT NULL
+        );",
+    )
+}
+
+/// posts_fts reads its content from posts, the triggers keep its index in step with every write.
+/// Posts stored before this only have their text indexed, their alt and link text was never kept
+fn full_text_search(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column_if_missing(tx, "posts", "alt_text", "TEXT")?;
+    add_column_if_missing(tx, "posts", "link_text", "TEXT")?;
+    tx.execute_batch(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS posts_fts USING fts5(
+            text,
+            alt_text,
+            link_text,
+            content = 'posts',
+            content_rowid = 'rowid',
+            tokenize = 'porter unicode61'
+        );
+        CREATE TRIGGER IF NOT EXISTS posts_fts_insert AFTER INSERT ON posts BEGIN
+            INSERT INTO posts_fts (rowid, text, alt_text, link_text)
+            VALUES (new.rowid, new.text, new.alt_text, new.link_text);
+        END;
+        CREATE TRIGGER IF NOT EXISTS posts_fts_delete AFTER DELETE ON posts BEGIN
+            INSERT INTO posts_fts (posts_fts, rowid, text, alt_text, link_text)
+            VALUES ('delete', old.rowid, old.text, old.alt_text, old.link_text);
+        END;
+        CREATE TRIGGER IF NOT EXISTS posts_fts_update AFTER UPDATE OF text, alt_text, link_text ON posts BEGIN
+            INSERT INTO posts_fts (posts_fts, rowid, text, alt_text, link_text)
+            VALUES ('delete', old.rowid, old.text, old.alt_text, old.link_text);
+            INSERT INTO posts_fts (rowid, text, alt_text, link_text)
+            VALUES (new.rowid, new.text, new.alt_text, new.link_text);
+        END;
+        INSERT INTO posts_fts (posts_fts) VALUES ('rebuild');",
+    )
+}
+
+/// Where retention moves posts, and the index it finds the oldest unpinned ones with
+fn post_retention(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS archived_posts (
+            uri TEXT PRIMARY KEY,
+            post TEXT NOT NULL,
+            archived_at INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS idx_posts_retention ON posts(timestamp, uri) WHERE pinned = 0;",
+    )
+}
+
+/// Each post that was counted as a share of a link, so it is never counted twice. Shares counted
+/// before this keep their count until the link is shared again
+fn link_shares(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS link_shares (
+            post_uri TEXT PRIMARY KEY,
+            link_url TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS idx_link_shares_link ON link_shares(link_url);",
+    )
+}
+
+/// Repeats were counted before, only the first of each is kept. The counts and priorities they
+/// already moved are left as they are
+fn unique_user_interactions(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "DELETE FROM user_interactions
+        WHERE requester_did IS NULL OR rowid NOT IN (
+            SELECT MIN(rowid) FROM user_interactions GROUP BY requester_did, post_uri, event
+        );
+        CREATE UNIQUE INDEX IF NOT EXISTS idx_user_interactions_once
+            ON user_interactions(requester_did, post_uri, event);",
+    )
+}
+
+/// Dbs made before versioning may or may not have these columns already
+fn add_column_if_missing(
+    db: &rusqlite::Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> rusqlite::Result<()> {
+    let mut stmt = db.prepare(&format!("PRAGMA table_info({table})"))?;
+    let columns = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<Result<Vec<String>, _>>()?;
+    if !columns.iter().any(|name| name == column) {
+        db.execute(
+            &format!("ALTER TABLE {table} ADD COLUMN {column} {definition}"),
+            [],
+        )?;
+    }
+    Ok(())
+}
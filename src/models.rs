@@ -1,4 +1,7 @@
-#[derive(Clone)]
+use serde::Serialize;
+use std::collections::HashMap;
+
+#[derive(Clone, Debug)]
 pub enum TextInPost {
     Post(String),
     Picture(String),
@@ -24,13 +27,144 @@ pub struct PostScoring {
     pub pinned: bool,
     pub deleted: bool,
     pub priority: i64,
+    /// Which topic and blog/thread words got the post into the feed, handed back to clients as feedContext
+    pub feed_context: String,
+    /// Classifier rules the post matched, each once in the order they first matched
+    pub matched_rules: Vec<String>,
 }
 
 pub struct DbPost {
     pub uri: String,
     pub text: String,
+    //Posts stored before we kept the author do not have one
+    pub author_did: Option<String>,
     pub pinned: bool,
     pub deleted: bool,
     pub priority: i64,
-    // pub timestamp: DateTime<Utc>,
+    pub feed_context: Option<String>,
+    pub timestamp: i64,
+}
+
+impl DbPost {
+    pub fn position(&self) -> FeedPosition {
+        FeedPosition {
+            timestamp: self.timestamp,
+            uri: self.uri.clone(),
+        }
+    }
+}
+
+/// Where a post sits in the feed, which is ordered newest first and then by uri
+#[derive(Clone, Debug, PartialEq)]
+pub struct FeedPosition {
+    pub timestamp: i64,
+    pub uri: String,
+}
+
+/// A post taken out of the feed from the admin tool, it is not stored again until restored
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tombstone {
+    pub uri: String,
+    pub reason: String,
+    /// Unix seconds
+    pub removed_at: i64,
+}
+
+/// Authors the admin tool handles differently from everyone else
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AuthorList {
+    /// Their posts are rejected before the classifier sees them
+    Blocked,
+    /// Their posts get [crate::ingest::TRUSTED_AUTHOR_BOOST] on top of what the classifier gave them
+    Trusted,
+}
+
+impl AuthorList {
+    pub fn table(&self) -> &'static str {
+        match self {
+            AuthorList::Blocked => "blocked_authors",
+            AuthorList::Trusted => "trusted_authors",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ListedAuthor {
+    pub did: String,
+    pub reason: Option<String>,
+    /// Unix seconds
+    pub added_at: i64,
+    /// Unix seconds, stays on the list for good when `None`
+    pub expires_at: Option<i64>,
+}
+
+/// Both author lists as they were last loaded, with the expiry of each entry
+#[derive(Clone, Debug, Default)]
+pub struct AuthorLists {
+    pub blocked: HashMap<String, Option<i64>>,
+    pub trusted: HashMap<String, Option<i64>>,
+}
+
+impl AuthorLists {
+    /// Which list the author is on at `now` (unix seconds), blocked wins if they are on both
+    pub fn standing(&self, did: &str, now: i64) -> Option<AuthorList> {
+        let listed = |list: &HashMap<String, Option<i64>>| {
+            list.get(did)
+                .is_some_and(|expires_at| expires_at.is_none_or(|expires_at| expires_at > now))
+        };
+        if listed(&self.blocked) {
+            Some(AuthorList::Blocked)
+        } else if listed(&self.trusted) {
+            Some(AuthorList::Trusted)
+        } else {
+            None
+        }
+    }
+}
+
+/// A post matching a search, best match first
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchResult {
+    pub uri: String,
+    pub author_did: Option<String>,
+    /// The matching part of the text, alt text or link text with the matches in [brackets]
+    pub snippet: String,
+    /// Lower is better, bm25 of the match on SQLite and its negated ts_rank on Postgres
+    pub rank: f64,
+}
+
+/// Who is asking for the feed, from the service auth JWT, and which of their filters apply
+#[derive(Clone, Debug)]
+pub struct ViewerFilter {
+    pub did: String,
+    /// Posts served to the viewer before this unix time are left out
+    pub hide_seen_before: Option<i64>,
+}
+
+/// Interaction events a client can send back with app.bsky.feed.sendInteractions
+pub const REQUEST_LESS: &str = "app.bsky.feed.defs#requestLess";
+pub const REQUEST_MORE: &str = "app.bsky.feed.defs#requestMore";
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InteractionCounts {
+    pub show_more: u64,
+    pub show_less: u64,
+}
+
+/// One item of a getFeedSkeleton response
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SkeletonItem {
+    pub post: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<SkeletonReason>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub feed_context: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "$type")]
+pub enum SkeletonReason {
+    #[serde(rename = "app.bsky.feed.defs#skeletonReasonRepost")]
+    Repost { repost: String },
 }
@@ -0,0 +1,407 @@
+use crate::cursor::CursorTracker;
+use crate::events::AccountStatus;
+use crate::ingest::{classify_post_by, IncomingPost};
+use crate::metrics::{
+    count_db_error, BLOCKED_AUTHOR_POSTS, INGEST_BATCH_SIZE, INGEST_QUEUE_DEPTH, INGEST_QUEUE_FULL,
+    INGEST_WRITES_SKIPPED, POSTS_STORED,
+};
+use crate::models::{AuthorList, AuthorLists, PostScoring};
+use crate::store::{FeedResult, FeedStore, SharedStore};
+use chrono::Utc;
+use log::{error, warn};
+use std::future::Future;
+use std::sync::{Arc, RwLock, Weak};
+use std::time::Duration;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{self, OwnedPermit};
+use tokio::sync::{oneshot, Semaphore};
+use tokio::task::JoinHandle;
+
+//** NOTICE **
+// Handlers only queue work so the event stream never waits on the db. Posts are classified on a
+// pool of blocking threads while they sit in the queue, then one writer takes the queue in the
+// order events arrived and commits them in batches. Keeping the order means a delete that came
+// after a post is always written after it, however long the post took to classify. The cursor only
+// moves once the writes of an event are committed. A batch that keeps failing is written one event
+// at a time, and only a write the db rejects while it is answering is skipped. While the db is down
+// the queue waits, so a restart never resumes past an event that was not stored
+//
+
+pub const DEFAULT_QUEUE_CAPACITY: usize = 10_000;
+pub const DEFAULT_BATCH_SIZE: usize = 500;
+/// How often the author lists are read again, the admin tool changes them from another process
+pub const AUTHOR_LISTS_REFRESH: Duration = Duration::from_secs(60);
+/// Wait before writing a failed batch again, doubled on each failure after
+const WRITE_RETRY_DELAY: Duration = Duration::from_millis(250);
+const MAX_WRITE_RETRY_DELAY: Duration = Duration::from_secs(30);
+/// Times a batch is tried before its writes are tried one at a time
+const MAX_BATCH_ATTEMPTS: u32 = 5;
+/// Times a single write is tried while the db answers before it is skipped
+const MAX_WRITE_ATTEMPTS: u32 = 3;
+
+/// What an event does when the queue is already full
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QueueFullPolicy {
+    /// Hold up the event source until there is room, nothing is lost
+    Wait,
+    /// Throw the event away so the source never falls behind. The cursor still moves past it with
+    /// the events after it, so a restart does not bring it back
+    Drop,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct IngestOptions {
+    /// Posts classified at the same time
+    pub classifier_workers: usize,
+    pub queue_capacity: usize,
+    /// Most writes committed in one transaction
+    pub batch_size: usize,
+    pub when_full: QueueFullPolicy,
+}
+
+impl Default for IngestOptions {
+    fn default() -> Self {
+        Self {
+            classifier_workers: std::thread::available_parallelism()
+                .map(|workers| workers.get())
+                .unwrap_or(4),
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            batch_size: DEFAULT_BATCH_SIZE,
+            when_full: QueueFullPolicy::Wait,
+        }
+    }
+}
+
+/// One change to the db, in the order it has to be made
+#[derive(Clone, Debug)]
+pub enum FeedWrite {
+    /// A post the classifier accepted
+    Post {
+        post: IncomingPost,
+        scoring: PostScoring,
+    },
+    /// A post the publisher liked, replaces whatever was stored for it
+    CuratedPost {
+        post: IncomingPost,
+        scoring: PostScoring,
+    },
+    DeletePost {
+        uri: String,
+    },
+    Like {
+        like_uri: String,
+        liked_post_uri: String,
+    },
+    DeleteLike {
+        like_uri: String,
+    },
+    Account {
+        did: String,
+        status: AccountStatus,
+    },
+}
+
+/// Writes carry the time_us of the event they came from, if it had one
+enum Queued {
+    Ready(Vec<FeedWrite>, Option<i64>),
+    /// Still being worked out, like a post being classified
+    Pending(JoinHandle<Vec<FeedWrite>>, Option<i64>),
+    /// Answered once everything queued before it is committed
+    Flush(oneshot::Sender<()>),
+}
+
+/// The sending side of the queue, cheap to clone. The writer stops once every clone is dropped
+#[derive(Clone)]
+pub struct IngestPipeline {
+    sender: mpsc::Sender<Queued>,
+    classifiers: Arc<Semaphore>,
+    when_full: QueueFullPolicy,
+    store: SharedStore,
+    authors: Arc<RwLock<AuthorLists>>,
+    cursor: Arc<CursorTracker>,
+    /// The event being handled, what is queued next is committed under it
+    event_time_us: Option<i64>,
+}
+
+impl IngestPipeline {
+    /// Starts the writer, `source` is the label stored posts are counted under and the name its
+    /// cursor is saved under
+    pub fn start(
+        store: SharedStore,
+        source: &'static str,
+        options: IngestOptions,
+    ) -> IngestPipeline {
+        let (sender, receiver) = mpsc::channel(options.queue_capacity.max(1));
+        let cursor = Arc::new(CursorTracker::new(source));
+        tokio::spawn(write_queued(
+            store.clone(),
+            source,
+            receiver,
+            options.batch_size.max(1),
+            cursor.clone(),
+        ));
+        let authors = Arc::new(RwLock::new(AuthorLists::default()));
+        tokio::spawn(keep_author_lists_fresh(
+            store.clone(),
+            Arc::downgrade(&authors),
+        ));
+        IngestPipeline {
+            sender,
+            classifiers: Arc::new(Semaphore::new(options.classifier_workers.max(1))),
+            when_full: options.when_full,
+            store,
+            authors,
+            cursor,
+            event_time_us: None,
+        }
+    }
+
+    /// Moved up to the time_us of each event once everything it queued is committed
+    pub fn cursor(&self) -> Arc<CursorTracker> {
+        self.cursor.clone()
+    }
+
+    /// Called before the writes of an event are queued
+    pub fn event_seen(&mut self, time_us: Option<i64>) {
+        self.event_time_us = time_us;
+    }
+
+    /// Reads the author lists again now instead of waiting for the next refresh
+    pub async fn refresh_author_lists(&self) {
+        refresh_author_lists(self.store.as_ref(), &self.authors).await;
+    }
+
+    /// Classifies the post off the event stream, it is stored if the classifier accepts it
+    pub async fn classify(&self, post: IncomingPost) {
+        let standing = self
+            .authors
+            .read()
+            .unwrap()
+            .standing(&post.author_did, Utc::now().timestamp());
+        if standing == Some(AuthorList::Blocked) {
+            BLOCKED_AUTHOR_POSTS.inc();
+            return;
+        }
+        let Some(slot) = self.reserve().await else {
+            return;
+        };
+        let classifiers = self.classifiers.clone();
+        let classifying = tokio::spawn(async move {
+            let Ok(_worker) = classifiers.acquire_owned().await else {
+                return vec![];
+            };
+            let classified = tokio::task::spawn_blocking(move || {
+                classify_post_by(&post, standing).map(|scoring| FeedWrite::Post { post, scoring })
+            })
+            .await;
+            match classified {
+                Ok(write) => write.into_iter().collect(),
+                Err(err) => {
+                    error!("Classifier failed: {err}");
+                    vec![]
+                }
+            }
+        });
+        self.queue(slot, Queued::Pending(classifying, self.event_time_us));
+    }
+
+    /// Writes that need some work first, like looking a post up. They still keep their place
+    pub async fn spawn<F>(&self, work: F)
+    where
+        F: Future<Output = Vec<FeedWrite>> + Send + 'static,
+    {
+        if let Some(slot) = self.reserve().await {
+            self.queue(
+                slot,
+                Queued::Pending(tokio::spawn(work), self.event_time_us),
+            );
+        }
+    }
+
+    pub async fn write(&self, write: FeedWrite) {
+        if let Some(slot) = self.reserve().await {
+            self.queue(slot, Queued::Ready(vec![write], self.event_time_us));
+        }
+    }
+
+    /// Waits until everything queued so far is committed
+    pub async fn flush(&self) {
+        let (done, committed) = oneshot::channel();
+        match self.sender.clone().reserve_owned().await {
+            Ok(slot) => self.queue(slot, Queued::Flush(done)),
+            Err(_) => return,
+        }
+        let _ = committed.await;
+    }
+
+    /// A place in the queue, or `None` when the event is dropped
+    async fn reserve(&self) -> Option<OwnedPermit<Queued>> {
+        match self.sender.clone().try_reserve_owned() {
+            Ok(slot) => Some(slot),
+            Err(TrySendError::Full(sender)) => match self.when_full {
+                QueueFullPolicy::Wait => {
+                    INGEST_QUEUE_FULL.with_label_values(&["waited"]).inc();
+                    sender.reserve_owned().await.ok()
+                }
+                QueueFullPolicy::Drop => {
+                    INGEST_QUEUE_FULL.with_label_values(&["dropped"]).inc();
+                    None
+                }
+            },
+            Err(TrySendError::Closed(_)) => {
+                error!("The ingest writer stopped, dropping an event");
+                None
+            }
+        }
+    }
+
+    fn queue(&self, slot: OwnedPermit<Queued>, queued: Queued) {
+        slot.send(queued);
+        INGEST_QUEUE_DEPTH.set(queue_depth(&self.sender));
+    }
+}
+
+async fn refresh_author_lists(store: &dyn FeedStore, authors: &RwLock<AuthorLists>) {
+    match store.load_author_lists().await {
+        Ok(lists) => *authors.write().unwrap() = lists,
+        Err(err) => {
+            error!("Failed to load the author lists: {err}");
+            count_db_error("load_author_lists");
+        }
+    }
+}
+
+/// Stops once the pipeline is gone
+async fn keep_author_lists_fresh(store: SharedStore, authors: Weak<RwLock<AuthorLists>>) {
+    let mut interval = tokio::time::interval(AUTHOR_LISTS_REFRESH);
+    loop {
+        interval.tick().await;
+        let Some(authors) = authors.upgrade() else {
+            return;
+        };
+        refresh_author_lists(store.as_ref(), &authors).await;
+    }
+}
+
+fn queue_depth(sender: &mpsc::Sender<Queued>) -> i64 {
+    (sender.max_capacity() - sender.capacity()) as i64
+}
+
+/// Takes whatever is waiting, up to a batch, in order and commits it in one transaction
+async fn write_queued(
+    store: SharedStore,
+    source: &'static str,
+    mut receiver: mpsc::Receiver<Queued>,
+    batch_size: usize,
+    cursor: Arc<CursorTracker>,
+) {
+    while let Some(first) = receiver.recv().await {
+        let mut writes = vec![];
+        let mut flushed = None;
+        let mut committed_time_us = None;
+        let mut taken = 0;
+        let mut next = Some(first);
+        while let Some(queued) = next.take() {
+            taken += 1;
+            match queued {
+                Queued::Ready(ready, time_us) => {
+                    writes.extend(ready);
+                    committed_time_us = committed_time_us.max(time_us);
+                }
+                Queued::Pending(pending, time_us) => {
+                    match pending.await {
+                        Ok(ready) => writes.extend(ready),
+                        Err(err) => error!("Lost an event while it was being worked on: {err}"),
+                    }
+                    committed_time_us = committed_time_us.max(time_us);
+                }
+                Queued::Flush(done) => {
+                    flushed = Some(done);
+                    break;
+                }
+            }
+            if taken < batch_size {
+                next = receiver.try_recv().ok();
+            }
+        }
+        INGEST_QUEUE_DEPTH.set(receiver.len() as i64);
+
+        if !writes.is_empty() {
+            write_until_committed(store.as_ref(), source, writes).await;
+        }
+        if let Some(time_us) = committed_time_us {
+            cursor.observe(time_us);
+        }
+        if let Some(done) = flushed {
+            let _ = done.send(());
+        }
+    }
+    warn!("Ingest queue closed, nothing more will be written");
+}
+
+/// Holds up the queue while the batch fails, then writes it one event at a time so one bad write
+/// does not hold up everything after it
+async fn write_until_committed(
+    store: &dyn FeedStore,
+    source: &'static str,
+    writes: Vec<FeedWrite>,
+) {
+    let mut delay = WRITE_RETRY_DELAY;
+    for attempt in 1..=MAX_BATCH_ATTEMPTS {
+        match write_batch(store, source, writes.clone()).await {
+            Ok(()) => return,
+            Err(err) => {
+                error!("Failed to write a batch of events, attempt {attempt}: {err}");
+                count_db_error("write_batch");
+            }
+        }
+        if attempt < MAX_BATCH_ATTEMPTS {
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(MAX_WRITE_RETRY_DELAY);
+        }
+    }
+    for write in writes {
+        write_one(store, source, write).await;
+    }
+}
+
+/// Waits for as long as the db does not answer, but skips the write once the db has turned it
+/// down a few times while answering
+async fn write_one(store: &dyn FeedStore, source: &'static str, write: FeedWrite) {
+    let mut delay = WRITE_RETRY_DELAY;
+    let mut rejected = 0;
+    loop {
+        let err = match write_batch(store, source, vec![write.clone()]).await {
+            Ok(()) => return,
+            Err(err) => err,
+        };
+        count_db_error("write_batch");
+        if store.ping().await.is_ok() {
+            rejected += 1;
+            if rejected >= MAX_WRITE_ATTEMPTS {
+                error!("Skipping a write the db keeps rejecting: {err}, {write:?}");
+                INGEST_WRITES_SKIPPED.inc();
+                return;
+            }
+        }
+        error!("Failed to write an event, trying again in {delay:?}: {err}");
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(MAX_WRITE_RETRY_DELAY);
+    }
+}
+
+async fn write_batch(
+    store: &dyn FeedStore,
+    source: &'static str,
+    writes: Vec<FeedWrite>,
+) -> FeedResult<()> {
+    INGEST_BATCH_SIZE.observe(writes.len() as f64);
+    let report = store.write_batch(writes).await?;
+    POSTS_STORED
+        .with_label_values(&[source])
+        .inc_by(report.stored);
+    POSTS_STORED
+        .with_label_values(&["curated"])
+        .inc_by(report.curated);
+    Ok(())
+}
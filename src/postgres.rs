@@ -0,0 +1,1038 @@
+use crate::db::{AUTHOR_PENALTY_LIMIT, SHARE_SCORE, USER_AUTHOR_MUTE_LIMIT};
+use crate::events::AccountStatus;
+use crate::ingest::{IncomingPost, IngestOutcome};
+use crate::metrics::{time_query, BLOCKED_AUTHOR_POSTS, POSTS_ARCHIVED};
+use crate::migrations::MigrationError;
+use crate::models::{
+    AuthorList, AuthorLists, DbPost, FeedPosition, InteractionCounts, ListedAuthor, PostScoring,
+    SearchResult, Tombstone, ViewerFilter, REQUEST_LESS, REQUEST_MORE,
+};
+use crate::pipeline::FeedWrite;
+use crate::retention::{
+    age_boundary, append_jsonl, shared_boundary, ArchiveTarget, RetentionPolicy, ARCHIVE_CHUNK_SIZE,
+};
+use crate::store::{BatchReport, FeedError, FeedResult, FeedStore};
+use async_trait::async_trait;
+use deadpool_postgres::{Manager, ManagerConfig, Pool, RecyclingMethod};
+use log::info;
+use std::collections::HashMap;
+use tokio_postgres::{NoTls, Transaction};
+
+//** NOTICE **
+// The same schema and queries as SQLite, written for Postgres so several feed instances and the
+// admin tool can share one db. Every write goes through a transaction on a pooled connection, and
+// instances migrating at the same time wait on an advisory lock instead of racing
+//
+
+/// Unix seconds in SQL, what unixepoch() is on SQLite
+const NOW: &str = "extract(epoch from now())::bigint";
+/// Key of the advisory lock held while migrating
+const MIGRATION_LOCK: i64 = 0x6665_6564;
+
+pub struct Migration {
+    pub version: i64,
+    pub description: &'static str,
+    pub sql: &'static str,
+}
+
+/// Append only, like the SQLite migrations. Version 1 is everything SQLite had at version 11
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "feed schema",
+        sql: "CREATE TABLE posts (
+            uri TEXT PRIMARY KEY,
+            text TEXT,
+            author_did TEXT,
+            pinned BOOLEAN NOT NULL DEFAULT false,
+            deleted BOOLEAN NOT NULL DEFAULT false,
+            priority BIGINT NOT NULL DEFAULT 0,
+            timestamp BIGINT NOT NULL,
+            link_url TEXT,
+            shares BIGINT NOT NULL DEFAULT 0,
+            feed_context TEXT,
+            cid TEXT,
+            langs TEXT,
+            embed_kind TEXT,
+            reply_parent TEXT,
+            reply_root TEXT,
+            matched_rules TEXT,
+            alt_text TEXT,
+            link_text TEXT,
+            search TSVECTOR GENERATED ALWAYS AS (
+                to_tsvector('english', coalesce(text, '') || ' ' || coalesce(alt_text, '') || ' ' || coalesce(link_text, ''))
+            ) STORED
+        );
+        CREATE INDEX idx_posts_timestamp ON posts(timestamp);
+        CREATE INDEX idx_posts_link_url ON posts(link_url);
+        CREATE INDEX idx_posts_author_did ON posts(author_did);
+        CREATE INDEX idx_posts_reply_root ON posts(reply_root);
+        CREATE INDEX idx_posts_retention ON posts(timestamp, uri COLLATE \"C\") WHERE NOT pinned;
+        CREATE INDEX idx_posts_search ON posts USING GIN (search);
+        CREATE TABLE likes (
+            post_uri TEXT,
+            like_uri TEXT,
+            PRIMARY KEY (post_uri, like_uri)
+        );
+        CREATE INDEX idx_likes_like_uri ON likes(like_uri);
+        CREATE TABLE post_interactions (
+            post_uri TEXT,
+            event TEXT,
+            count BIGINT NOT NULL DEFAULT 0,
+            PRIMARY KEY (post_uri, event)
+        );
+        CREATE TABLE author_interactions (
+            author_did TEXT,
+            event TEXT,
+            count BIGINT NOT NULL DEFAULT 0,
+            PRIMARY KEY (author_did, event)
+        );
+        CREATE TABLE interaction_contexts (
+            feed_context TEXT,
+            event TEXT,
+            count BIGINT NOT NULL DEFAULT 0,
+            PRIMARY KEY (feed_context, event)
+        );
+        CREATE TABLE user_interactions (
+            requester_did TEXT,
+            post_uri TEXT,
+            author_did TEXT,
+            event TEXT,
+            created_at BIGINT
+        );
+        CREATE INDEX idx_user_interactions_requester ON user_interactions(requester_did);
+        CREATE TABLE served_posts (
+            requester_did TEXT,
+            post_uri TEXT,
+            served_at BIGINT,
+            PRIMARY KEY (requester_did, post_uri)
+        );
+        CREATE TABLE firehose_cursor (
+            source TEXT PRIMARY KEY,
+            cursor BIGINT NOT NULL,
+            updated_at BIGINT NOT NULL
+        );
+        CREATE TABLE moderation_tombstones (
+            uri TEXT PRIMARY KEY,
+            reason TEXT NOT NULL,
+            removed_at BIGINT NOT NULL
+        );
+        CREATE TABLE blocked_authors (
+            did TEXT PRIMARY KEY,
+            reason TEXT,
+            added_at BIGINT NOT NULL,
+            expires_at BIGINT
+        );
+        CREATE TABLE trusted_authors (
+            did TEXT PRIMARY KEY,
+            reason TEXT,
+            added_at BIGINT NOT NULL,
+            expires_at BIGINT
+        );
+        CREATE TABLE hidden_accounts (
+            did TEXT PRIMARY KEY,
+            status TEXT NOT NULL,
+            hidden_at BIGINT NOT NULL
+        );
+        CREATE TABLE archived_posts (
+            uri TEXT PRIMARY KEY,
+            post TEXT NOT NULL,
+            archived_at BIGINT NOT NULL
+        );",
+    },
+    Migration {
+        version: 2,
+        description: "posts counted as shares of a link",
+        sql: "CREATE TABLE link_shares (
+                post_uri TEXT PRIMARY KEY,
+                link_url TEXT NOT NULL
+            );
+            CREATE INDEX idx_link_shares_link ON link_shares(link_url);",
+    },
+    Migration {
+        version: 3,
+        description: "one interaction per requester, post and event",
+        sql: "DELETE FROM user_interactions AS later
+            USING user_interactions AS first
+            WHERE later.requester_did = first.requester_did
+                AND later.post_uri = first.post_uri
+                AND later.event = first.event
+                AND later.ctid > first.ctid;
+            DELETE FROM user_interactions WHERE requester_did IS NULL;
+            CREATE UNIQUE INDEX idx_user_interactions_once
+                ON user_interactions(requester_did, post_uri, event);",
+    },
+];
+
+/// A posts row as JSON, the same keys and values the SQLite archive has
+const POST_AS_JSON: &str = "json_build_object(
+    'uri', uri, 'text', text, 'author_did', author_did, 'pinned', pinned::int, 'deleted', deleted::int,
+    'priority', priority, 'timestamp', timestamp, 'link_url', link_url, 'shares', shares,
+    'feed_context', feed_context, 'cid', cid, 'langs', langs::json, 'embed_kind', embed_kind,
+    'reply_parent', reply_parent, 'reply_root', reply_root, 'matched_rules', matched_rules::json,
+    'alt_text', alt_text, 'link_text', link_text
+)::text";
+
+/// A pool of connections to one Postgres db, cheap to clone
+#[derive(Clone)]
+pub struct PostgresStore {
+    pool: Pool,
+}
+
+impl PostgresStore {
+    /// Connections are only made once they are needed, a bad url fails here but a db that is
+    /// down fails on first use
+    pub fn connect(url: &str) -> FeedResult<PostgresStore> {
+        let config: tokio_postgres::Config = url.parse()?;
+        let manager = Manager::from_config(
+            config,
+            NoTls,
+            ManagerConfig {
+                recycling_method: RecyclingMethod::Fast,
+            },
+        );
+        //Only fails when timeouts are set without a runtime, and none are
+        let pool = Pool::builder(manager)
+            .build()
+            .map_err(|err| FeedError::Other(Box::new(err)))?;
+        Ok(PostgresStore { pool })
+    }
+}
+
+#[async_trait]
+impl FeedStore for PostgresStore {
+    async fn migrate(&self) -> FeedResult<i64> {
+        let mut client = self.pool.get().await?;
+        let tx = client.transaction().await?;
+        tx.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK])
+            .await?;
+        tx.batch_execute(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (
+                version BIGINT PRIMARY KEY,
+                description TEXT NOT NULL,
+                applied_at BIGINT NOT NULL
+            )",
+        )
+        .await?;
+        let current: i64 = tx
+            .query_one(
+                "SELECT COALESCE(MAX(version), 0) FROM schema_migrations",
+                &[],
+            )
+            .await?
+            .get(0);
+        let supported = MIGRATIONS.last().map_or(0, |migration| migration.version);
+        if current > supported {
+            return Err(MigrationError::NewerThanBinary {
+                found: current,
+                supported,
+            }
+            .into());
+        }
+        for migration in MIGRATIONS
+            .iter()
+            .filter(|migration| migration.version > current)
+        {
+            info!(
+                "Migrating the database to version {} ({})",
+                migration.version, migration.description
+            );
+            tx.batch_execute(migration.sql).await?;
+            tx.execute(
+                &format!(
+                    "INSERT INTO schema_migrations (version, description, applied_at) VALUES ($1, $2, {NOW})"
+                ),
+                &[&migration.version, &migration.description],
+            )
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(supported)
+    }
+
+    async fn ping(&self) -> FeedResult<()> {
+        self.pool.get().await?.query_one("SELECT 1", &[]).await?;
+        Ok(())
+    }
+
+    async fn store_post(
+        &self,
+        post: IncomingPost,
+        scoring: PostScoring,
+    ) -> FeedResult<IngestOutcome> {
+        let _timer = time_query("insert_post");
+        let mut client = self.pool.get().await?;
+        let tx = client.transaction().await?;
+        let outcome = store_scored_post(&tx, &post, &scoring).await?;
+        tx.commit().await?;
+        Ok(outcome)
+    }
+
+    async fn write_batch(&self, writes: Vec<FeedWrite>) -> FeedResult<BatchReport> {
+        let _timer = time_query("write_batch");
+        let mut client = self.pool.get().await?;
+        let tx = client.transaction().await?;
+        let mut report = BatchReport::default();
+        for write in writes {
+            match write {
+                FeedWrite::Post { post, scoring } => {
+                    if store_scored_post(&tx, &post, &scoring).await? == IngestOutcome::Stored {
+                        report.stored += 1;
+                    }
+                }
+                FeedWrite::CuratedPost { post, scoring } => {
+                    insert_post_row(&tx, &post, &scoring, true).await?;
+                    report.curated += 1;
+                }
+                FeedWrite::DeletePost { uri } => remove_post(&tx, &uri).await?,
+                FeedWrite::Like {
+                    like_uri,
+                    liked_post_uri,
+                } => {
+                    tx.execute(
+                        "INSERT INTO likes (post_uri, like_uri)
+                         SELECT $1::text, $2::text
+                         WHERE EXISTS (SELECT 1 FROM posts WHERE uri = $1)
+                         ON CONFLICT DO NOTHING",
+                        &[&liked_post_uri, &like_uri],
+                    )
+                    .await?;
+                }
+                FeedWrite::DeleteLike { like_uri } => {
+                    tx.execute("DELETE FROM likes WHERE like_uri = $1", &[&like_uri])
+                        .await?;
+                }
+                FeedWrite::Account { did, status } => {
+                    let purged = apply_account_status(&tx, &did, status).await?;
+                    if purged > 0 {
+                        info!(
+                            "Purged {purged} posts of {did}, the account is {}",
+                            status.as_str()
+                        );
+                    }
+                }
+            }
+        }
+        tx.commit().await?;
+        Ok(report)
+    }
+
+    async fn delete_post(&self, uri: String) -> FeedResult<()> {
+        let _timer = time_query("delete_post");
+        let mut client = self.pool.get().await?;
+        let tx = client.transaction().await?;
+        remove_post(&tx, &uri).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn load_feed(
+        &self,
+        limit: u64,
+        offset: u64,
+        after: Option<FeedPosition>,
+        viewer: Option<ViewerFilter>,
+    ) -> FeedResult<Vec<DbPost>> {
+        let _timer = time_query("load_feed");
+        let (after_timestamp, after_uri) = after
+            .map(|after| (Some(after.timestamp), Some(after.uri)))
+            .unwrap_or_default();
+        let viewer_did = viewer.as_ref().map(|viewer| viewer.did.clone());
+        let hide_seen_before = viewer
+            .and_then(|viewer| viewer.hide_seen_before)
+            .unwrap_or(i64::MIN);
+        let rows = self
+            .pool
+            .get()
+            .await?
+            .query(
+                &format!(
+                    "SELECT uri, text, author_did, pinned, deleted, priority, feed_context, timestamp
+                    FROM posts
+                    WHERE NOT deleted
+                    -- Users asked to see less of it enough times
+                    AND priority > 0
+                    -- Only the best ranked post of everyone sharing the same link is served
+                    AND (link_url IS NULL OR uri = (
+                        SELECT best.uri
+                        FROM posts AS best
+                        WHERE best.link_url = posts.link_url AND NOT best.deleted AND best.priority > 0
+                        ORDER BY best.priority + best.shares * $3 DESC, best.timestamp ASC
+                        LIMIT 1
+                    ))
+                    -- Deactivated and suspended accounts, by the repo in the uri too for rows without an author
+                    AND NOT EXISTS (
+                        SELECT 1 FROM hidden_accounts AS hidden
+                        WHERE hidden.did = posts.author_did
+                        OR left(posts.uri, length('at://' || hidden.did || '/')) = 'at://' || hidden.did || '/'
+                    )
+                    AND (author_did IS NULL OR author_did NOT IN (
+                        SELECT less.author_did
+                        FROM author_interactions AS less
+                        LEFT JOIN author_interactions AS more
+                            ON more.author_did = less.author_did AND more.event = '{REQUEST_MORE}'
+                        WHERE less.event = '{REQUEST_LESS}'
+                            AND less.count - COALESCE(more.count, 0) >= {AUTHOR_PENALTY_LIMIT}
+                    ))
+                    -- What the viewer themselves asked to see less of
+                    AND ($4::text IS NULL OR uri NOT IN (
+                        SELECT post_uri FROM user_interactions
+                        WHERE requester_did = $4 AND event = '{REQUEST_LESS}'
+                    ))
+                    AND ($4::text IS NULL OR author_did IS NULL OR author_did NOT IN (
+                        SELECT author_did FROM user_interactions
+                        WHERE requester_did = $4 AND author_did IS NOT NULL
+                        GROUP BY author_did
+                        HAVING COUNT(*) FILTER (WHERE event = '{REQUEST_LESS}')
+                            - COUNT(*) FILTER (WHERE event = '{REQUEST_MORE}') >= {USER_AUTHOR_MUTE_LIMIT}
+                    ))
+                    AND ($4::text IS NULL OR uri NOT IN (
+                        SELECT post_uri FROM served_posts
+                        WHERE requester_did = $4 AND served_at < $5
+                    ))
+                    AND ($6::bigint IS NULL OR (timestamp, uri COLLATE \"C\") < ($6, $7))
+                    ORDER BY timestamp DESC, uri COLLATE \"C\" DESC
+                    LIMIT $1 OFFSET $2"
+                ),
+                &[
+                    &(limit as i64),
+                    &(offset as i64),
+                    &(SHARE_SCORE as i64),
+                    &viewer_did,
+                    &hide_seen_before,
+                    &after_timestamp,
+                    &after_uri,
+                ],
+            )
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| DbPost {
+                uri: row.get(0),
+                text: row.get::<_, Option<String>>(1).unwrap_or_default(),
+                author_did: row.get(2),
+                pinned: row.get(3),
+                deleted: row.get(4),
+                priority: row.get(5),
+                feed_context: row.get(6),
+                timestamp: row.get(7),
+            })
+            .collect())
+    }
+
+    async fn posts_count(&self) -> FeedResult<u64> {
+        let _timer = time_query("posts_count");
+        let count: i64 = self
+            .pool
+            .get()
+            .await?
+            .query_one("SELECT COUNT(uri) FROM posts", &[])
+            .await?
+            .get(0);
+        Ok(count as u64)
+    }
+
+    async fn search_posts(
+        &self,
+        query: String,
+        limit: u64,
+        offset: u64,
+    ) -> FeedResult<Vec<SearchResult>> {
+        let _timer = time_query("search_posts");
+        if query.split_whitespace().next().is_none() {
+            return Ok(vec![]);
+        }
+        //plainto_tsquery ands every word together and takes no query syntax, like the quoted
+        //terms on SQLite
+        let rows = self
+            .pool
+            .get()
+            .await?
+            .query(
+                "SELECT uri, author_did,
+                    ts_headline('english', concat_ws(' ', text, alt_text, link_text), query,
+                        'StartSel=[, StopSel=], MinWords=6, MaxWords=12, MaxFragments=1'),
+                    -ts_rank(search, query)::float8 AS rank
+                FROM posts, plainto_tsquery('english', $1) AS query
+                WHERE search @@ query
+                ORDER BY rank, uri
+                LIMIT $2 OFFSET $3",
+                &[&query, &(limit as i64), &(offset as i64)],
+            )
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| SearchResult {
+                uri: row.get(0),
+                author_did: row.get(1),
+                snippet: row.get(2),
+                rank: row.get(3),
+            })
+            .collect())
+    }
+
+    async fn apply_retention(&self, policy: &RetentionPolicy, now: i64) -> FeedResult<usize> {
+        let _timer = time_query("apply_retention");
+        let mut client = self.pool.get().await?;
+        //Uris compare byte by byte like they do on SQLite and in the boundary worked out here
+        let mut boundaries = vec![];
+        for limits in policy.feed_limits() {
+            let by_count = match limits.max_posts {
+                Some(max_posts) => client
+                    .query_opt(
+                        "SELECT timestamp, uri FROM posts WHERE NOT pinned
+                         ORDER BY timestamp DESC, uri COLLATE \"C\" DESC
+                         LIMIT 1 OFFSET $1",
+                        &[&(max_posts as i64)],
+                    )
+                    .await?
+                    .map(|row| (row.get::<_, i64>(0), row.get::<_, String>(1))),
+                None => None,
+            };
+            boundaries.push(age_boundary(limits, now).into_iter().chain(by_count).max());
+        }
+        let Some((before_timestamp, before_uri)) = shared_boundary(boundaries) else {
+            return Ok(0);
+        };
+
+        let mut archived = 0;
+        loop {
+            let chunk: Vec<(String, String)> = client
+                .query(
+                    &format!(
+                        "SELECT uri, {POST_AS_JSON} FROM posts
+                         WHERE NOT pinned AND (timestamp, uri COLLATE \"C\") <= ($1, $2)
+                         ORDER BY timestamp, uri COLLATE \"C\"
+                         LIMIT $3"
+                    ),
+                    &[&before_timestamp, &before_uri, &(ARCHIVE_CHUNK_SIZE as i64)],
+                )
+                .await?
+                .iter()
+                .map(|row| (row.get(0), row.get(1)))
+                .collect();
+            if chunk.is_empty() {
+                break;
+            }
+
+            let to_table = match &policy.archive {
+                ArchiveTarget::Table => true,
+                ArchiveTarget::Jsonl(dir) => {
+                    let dir = dir.clone();
+                    let lines: Vec<String> = chunk.iter().map(|(_, post)| post.clone()).collect();
+                    tokio::task::spawn_blocking(move || append_jsonl(&dir, now, &lines)).await??;
+                    false
+                }
+            };
+            let moved = chunk.len();
+            let tx = client.transaction().await?;
+            for (uri, post) in chunk {
+                if to_table {
+                    tx.execute(
+                        "INSERT INTO archived_posts (uri, post, archived_at) VALUES ($1, $2, $3)
+                         ON CONFLICT (uri) DO UPDATE SET post = excluded.post, archived_at = excluded.archived_at",
+                        &[&uri, &post, &now],
+                    )
+                    .await?;
+                }
+                remove_post(&tx, &uri).await?;
+            }
+            tx.commit().await?;
+            archived += moved;
+            POSTS_ARCHIVED.inc_by(moved as u64);
+        }
+        if archived > 0 {
+            info!("Archived {archived} posts");
+        }
+        Ok(archived)
+    }
+
+    async fn moderate_post(&self, uri: String, reason: String) -> FeedResult<()> {
+        let _timer = time_query("moderate_post");
+        let mut client = self.pool.get().await?;
+        let tx = client.transaction().await?;
+        tx.execute(
+            &format!(
+                "INSERT INTO moderation_tombstones (uri, reason, removed_at) VALUES ($1, $2, {NOW})
+                 ON CONFLICT (uri) DO UPDATE SET reason = excluded.reason"
+            ),
+            &[&uri, &reason],
+        )
+        .await?;
+        tx.execute("UPDATE posts SET deleted = true WHERE uri = $1", &[&uri])
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn restore_post(&self, uri: String) -> FeedResult<bool> {
+        let _timer = time_query("restore_post");
+        let mut client = self.pool.get().await?;
+        let tx = client.transaction().await?;
+        let removed = tx
+            .execute("DELETE FROM moderation_tombstones WHERE uri = $1", &[&uri])
+            .await?;
+        tx.execute("UPDATE posts SET deleted = false WHERE uri = $1", &[&uri])
+            .await?;
+        tx.commit().await?;
+        Ok(removed > 0)
+    }
+
+    async fn list_tombstones(&self) -> FeedResult<Vec<Tombstone>> {
+        let rows = self
+            .pool
+            .get()
+            .await?
+            .query(
+                "SELECT uri, reason, removed_at FROM moderation_tombstones ORDER BY removed_at DESC, uri",
+                &[],
+            )
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| Tombstone {
+                uri: row.get(0),
+                reason: row.get(1),
+                removed_at: row.get(2),
+            })
+            .collect())
+    }
+
+    async fn add_to_author_list(
+        &self,
+        list: AuthorList,
+        did: String,
+        reason: Option<String>,
+        expires_at: Option<i64>,
+    ) -> FeedResult<()> {
+        let _timer = time_query("add_to_author_list");
+        self.pool
+            .get()
+            .await?
+            .execute(
+                &format!(
+                    "INSERT INTO {} (did, reason, added_at, expires_at) VALUES ($1, $2, {NOW}, $3)
+                     ON CONFLICT (did) DO UPDATE SET reason = excluded.reason, expires_at = excluded.expires_at",
+                    list.table()
+                ),
+                &[&did, &reason, &expires_at],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn remove_from_author_list(&self, list: AuthorList, did: String) -> FeedResult<bool> {
+        let _timer = time_query("remove_from_author_list");
+        let removed = self
+            .pool
+            .get()
+            .await?
+            .execute(
+                &format!("DELETE FROM {} WHERE did = $1", list.table()),
+                &[&did],
+            )
+            .await?;
+        Ok(removed > 0)
+    }
+
+    async fn list_authors(&self, list: AuthorList) -> FeedResult<Vec<ListedAuthor>> {
+        let rows = self
+            .pool
+            .get()
+            .await?
+            .query(
+                &format!(
+                    "SELECT did, reason, added_at, expires_at FROM {} ORDER BY added_at DESC, did",
+                    list.table()
+                ),
+                &[],
+            )
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| ListedAuthor {
+                did: row.get(0),
+                reason: row.get(1),
+                added_at: row.get(2),
+                expires_at: row.get(3),
+            })
+            .collect())
+    }
+
+    async fn load_author_lists(&self) -> FeedResult<AuthorLists> {
+        let _timer = time_query("load_author_lists");
+        let client = self.pool.get().await?;
+        let mut lists = AuthorLists::default();
+        for (list, entries) in [
+            (AuthorList::Blocked, &mut lists.blocked),
+            (AuthorList::Trusted, &mut lists.trusted),
+        ] {
+            let rows = client
+                .query(
+                    &format!(
+                        "SELECT did, expires_at FROM {} WHERE expires_at IS NULL OR expires_at > {NOW}",
+                        list.table()
+                    ),
+                    &[],
+                )
+                .await?;
+            for row in rows {
+                entries.insert(row.get(0), row.get(1));
+            }
+        }
+        Ok(lists)
+    }
+
+    async fn author_standing(&self, did: String) -> FeedResult<Option<AuthorList>> {
+        let mut client = self.pool.get().await?;
+        let tx = client.transaction().await?;
+        let standing = author_standing(&tx, &did).await?;
+        tx.commit().await?;
+        Ok(standing)
+    }
+
+    async fn apply_account_status(&self, did: String, status: AccountStatus) -> FeedResult<usize> {
+        let mut client = self.pool.get().await?;
+        let tx = client.transaction().await?;
+        let purged = apply_account_status(&tx, &did, status).await?;
+        tx.commit().await?;
+        Ok(purged)
+    }
+
+    async fn record_interactions(
+        &self,
+        requester_did: String,
+        interactions: Vec<(String, String, Option<String>)>,
+    ) -> FeedResult<()> {
+        let _timer = time_query("record_interactions");
+        let mut client = self.pool.get().await?;
+        let tx = client.transaction().await?;
+        for (post_uri, event, feed_context) in interactions {
+            let added = tx
+                .execute(
+                    &format!(
+                        "INSERT INTO user_interactions (requester_did, post_uri, author_did, event, created_at)
+                         SELECT $1, uri, author_did, $3, {NOW} FROM posts WHERE uri = $2
+                         ON CONFLICT (requester_did, post_uri, event) DO NOTHING"
+                    ),
+                    &[&requester_did, &post_uri, &event],
+                )
+                .await?;
+            if added == 0 {
+                continue;
+            }
+            tx.execute(
+                "INSERT INTO post_interactions (post_uri, event, count) VALUES ($1, $2, 1)
+                 ON CONFLICT (post_uri, event) DO UPDATE SET count = post_interactions.count + 1",
+                &[&post_uri, &event],
+            )
+            .await?;
+            tx.execute(
+                "INSERT INTO author_interactions (author_did, event, count)
+                 SELECT author_did, $2::text, 1 FROM posts WHERE uri = $1 AND author_did IS NOT NULL
+                 ON CONFLICT (author_did, event) DO UPDATE SET count = author_interactions.count + 1",
+                &[&post_uri, &event],
+            )
+            .await?;
+            if let Some(feed_context) = feed_context {
+                tx.execute(
+                    "INSERT INTO interaction_contexts (feed_context, event, count) VALUES ($1, $2, 1)
+                     ON CONFLICT (feed_context, event) DO UPDATE SET count = interaction_contexts.count + 1",
+                    &[&feed_context, &event],
+                )
+                .await?;
+            }
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn mark_posts_served(
+        &self,
+        requester_did: String,
+        post_uris: Vec<String>,
+        served_at: i64,
+    ) -> FeedResult<()> {
+        let _timer = time_query("mark_posts_served");
+        let mut client = self.pool.get().await?;
+        let tx = client.transaction().await?;
+        for post_uri in post_uris {
+            //Keeps the first time it was served so it stays visible for the rest of that session
+            tx.execute(
+                "INSERT INTO served_posts (requester_did, post_uri, served_at) VALUES ($1, $2, $3)
+                 ON CONFLICT DO NOTHING",
+                &[&requester_did, &post_uri, &served_at],
+            )
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn forget_served_posts_before(&self, before: i64) -> FeedResult<usize> {
+        let _timer = time_query("forget_served_posts");
+        let forgotten = self
+            .pool
+            .get()
+            .await?
+            .execute("DELETE FROM served_posts WHERE served_at < $1", &[&before])
+            .await?;
+        Ok(forgotten as usize)
+    }
+
+    async fn get_interaction_counts(
+        &self,
+        post_uris: Vec<String>,
+    ) -> FeedResult<HashMap<String, InteractionCounts>> {
+        let _timer = time_query("interaction_counts");
+        let client = self.pool.get().await?;
+        let mut counts = HashMap::new();
+        for post_uri in post_uris {
+            let mut post_counts = InteractionCounts::default();
+            let rows = client
+                .query(
+                    "SELECT event, count FROM post_interactions
+                     WHERE post_uri = $1 AND event IN ($2, $3)",
+                    &[&post_uri, &REQUEST_MORE, &REQUEST_LESS],
+                )
+                .await?;
+            for row in rows {
+                let event: String = row.get(0);
+                let count = row.get::<_, i64>(1) as u64;
+                if event == REQUEST_MORE {
+                    post_counts.show_more = count;
+                } else {
+                    post_counts.show_less = count;
+                }
+            }
+            counts.insert(post_uri, post_counts);
+        }
+        Ok(counts)
+    }
+
+    async fn save_cursor(&self, source: String, cursor: i64, updated_at: i64) -> FeedResult<()> {
+        let _timer = time_query("save_cursor");
+        self.pool
+            .get()
+            .await?
+            .execute(
+                "INSERT INTO firehose_cursor (source, cursor, updated_at) VALUES ($1, $2, $3)
+                 ON CONFLICT (source) DO UPDATE SET cursor = excluded.cursor, updated_at = excluded.updated_at",
+                &[&source, &cursor, &updated_at],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn load_cursor(&self, source: String) -> FeedResult<Option<i64>> {
+        let row = self
+            .pool
+            .get()
+            .await?
+            .query_opt(
+                "SELECT cursor FROM firehose_cursor WHERE source = $1",
+                &[&source],
+            )
+            .await?;
+        Ok(row.map(|row| row.get(0)))
+    }
+}
+
+/// [crate::ingest::store_scored_post] on Postgres
+async fn store_scored_post(
+    tx: &Transaction<'_>,
+    post: &IncomingPost,
+    scoring: &PostScoring,
+) -> FeedResult<IngestOutcome> {
+    //The lists may have changed since the post was classified
+    if author_standing(tx, &post.author_did).await? == Some(AuthorList::Blocked) {
+        BLOCKED_AUTHOR_POSTS.inc();
+        return Ok(IngestOutcome::Blocked);
+    }
+    let tombstoned: bool = tx
+        .query_one(
+            "SELECT EXISTS (SELECT 1 FROM moderation_tombstones WHERE uri = $1)",
+            &[&post.uri],
+        )
+        .await?
+        .get(0);
+    if tombstoned {
+        info!("Not storing {}, it was removed by a moderator", post.uri);
+        return Ok(IngestOutcome::Moderated);
+    }
+    if let Some(link_url) = &post.link_url {
+        let shared = tx
+            .query_opt(
+                "SELECT uri FROM posts
+                 WHERE link_url = $1 AND uri != $2 AND NOT deleted
+                 ORDER BY priority + shares * $3 DESC, timestamp ASC
+                 LIMIT 1",
+                &[link_url, &post.uri, &(SHARE_SCORE as i64)],
+            )
+            .await?;
+        if let Some(shared) = shared {
+            let shared: String = shared.get(0);
+            tx.execute(
+                "INSERT INTO link_shares (post_uri, link_url) VALUES ($1, $2)
+                 ON CONFLICT (post_uri) DO NOTHING",
+                &[&post.uri, link_url],
+            )
+            .await?;
+            tx.execute(
+                "UPDATE posts SET shares = (SELECT COUNT(*) FROM link_shares WHERE link_url = $1)
+                 WHERE uri = $2",
+                &[link_url, &shared],
+            )
+            .await?;
+            info!("Counted {} as another share of {link_url}", post.uri);
+            return Ok(IngestOutcome::CountedAsShare);
+        }
+    }
+
+    info!("Storing {}", post.uri);
+    insert_post_row(tx, post, scoring, false).await?;
+    Ok(IngestOutcome::Stored)
+}
+
+/// [crate::ingest::insert_post_row] on Postgres
+async fn insert_post_row(
+    tx: &Transaction<'_>,
+    post: &IncomingPost,
+    scoring: &PostScoring,
+    replace: bool,
+) -> FeedResult<()> {
+    let texts = post.stored_texts();
+    let langs = serde_json::to_string(&post.langs).unwrap_or_default();
+    let matched_rules = serde_json::to_string(&scoring.matched_rules).unwrap_or_default();
+    let on_conflict = if replace {
+        "DO UPDATE SET text = excluded.text, author_did = excluded.author_did, pinned = false,
+            deleted = false, priority = excluded.priority, timestamp = excluded.timestamp,
+            link_url = excluded.link_url, shares = 0, feed_context = excluded.feed_context,
+            cid = excluded.cid, langs = excluded.langs, embed_kind = excluded.embed_kind,
+            reply_parent = excluded.reply_parent, reply_root = excluded.reply_root,
+            matched_rules = excluded.matched_rules, alt_text = excluded.alt_text,
+            link_text = excluded.link_text"
+    } else {
+        "DO NOTHING"
+    };
+    tx.execute(
+        &format!(
+            "INSERT INTO posts (uri, text, author_did, pinned, deleted, priority, timestamp, link_url, feed_context, cid, langs, embed_kind, reply_parent, reply_root, matched_rules, alt_text, link_text)
+             SELECT $1::text, $2::text, $3::text, false, false, $4::bigint, $5::bigint, $6::text, $7::text,
+                $8::text, $9::text, $10::text, $11::text, $12::text, $13::text, $14::text, $15::text
+             WHERE NOT EXISTS (SELECT 1 FROM moderation_tombstones WHERE uri = $1)
+             AND NOT EXISTS (
+                SELECT 1 FROM blocked_authors
+                WHERE did = $3 AND (expires_at IS NULL OR expires_at > {NOW})
+             )
+             ON CONFLICT (uri) {on_conflict}"
+        ),
+        &[
+            &post.uri,
+            &texts.text,
+            &post.author_did,
+            &scoring.priority,
+            &post.timestamp,
+            &post.link_url,
+            &scoring.feed_context,
+            &post.cid,
+            &langs,
+            &post.embed_kind,
+            &post.reply.as_ref().map(|reply| reply.parent.as_str()),
+            &post.reply.as_ref().map(|reply| reply.root.as_str()),
+            &matched_rules,
+            &texts.alt_text,
+            &texts.link_text,
+        ],
+    )
+    .await?;
+    Ok(())
+}
+
+async fn remove_post(tx: &Transaction<'_>, uri: &str) -> FeedResult<()> {
+    tx.execute("DELETE FROM likes WHERE post_uri = $1", &[&uri])
+        .await?;
+    tx.execute("DELETE FROM posts WHERE uri = $1", &[&uri])
+        .await?;
+    Ok(())
+}
+
+async fn author_standing(tx: &Transaction<'_>, did: &str) -> FeedResult<Option<AuthorList>> {
+    for list in [AuthorList::Blocked, AuthorList::Trusted] {
+        let listed: bool = tx
+            .query_one(
+                &format!(
+                    "SELECT EXISTS (SELECT 1 FROM {} WHERE did = $1 AND (expires_at IS NULL OR expires_at > {NOW}))",
+                    list.table()
+                ),
+                &[&did],
+            )
+            .await?
+            .get(0);
+        if listed {
+            return Ok(Some(list));
+        }
+    }
+    Ok(None)
+}
+
+/// [crate::db::apply_account_status] on Postgres
+async fn apply_account_status(
+    tx: &Transaction<'_>,
+    did: &str,
+    status: AccountStatus,
+) -> FeedResult<usize> {
+    match status {
+        AccountStatus::Active => {
+            tx.execute("DELETE FROM hidden_accounts WHERE did = $1", &[&did])
+                .await?;
+            Ok(0)
+        }
+        AccountStatus::Deactivated | AccountStatus::Suspended => {
+            tx.execute(
+                &format!(
+                    "INSERT INTO hidden_accounts (did, status, hidden_at) VALUES ($1, $2, {NOW})
+                     ON CONFLICT (did) DO UPDATE SET status = excluded.status"
+                ),
+                &[&did, &status.as_str()],
+            )
+            .await?;
+            Ok(0)
+        }
+        AccountStatus::Deleted | AccountStatus::TakenDown => {
+            let repo = format!("at://{did}/");
+            tx.execute(
+                "DELETE FROM likes WHERE post_uri IN (
+                    SELECT uri FROM posts WHERE author_did = $1 OR left(uri, length($2)) = $2
+                )",
+                &[&did, &repo],
+            )
+            .await?;
+            //Likes they made on other posts, the like uri is in their repo
+            tx.execute(
+                "DELETE FROM likes WHERE left(like_uri, length($1)) = $1",
+                &[&repo],
+            )
+            .await?;
+            let purged = tx
+                .execute(
+                    "DELETE FROM posts WHERE author_did = $1 OR left(uri, length($2)) = $2",
+                    &[&did, &repo],
+                )
+                .await?;
+            tx.execute(
+                "DELETE FROM archived_posts WHERE left(uri, length($1)) = $1",
+                &[&repo],
+            )
+            .await?;
+            tx.execute(
+                "DELETE FROM link_shares WHERE left(post_uri, length($1)) = $1",
+                &[&repo],
+            )
+            .await?;
+            tx.execute("DELETE FROM hidden_accounts WHERE did = $1", &[&did])
+                .await?;
+            Ok(purged as usize)
+        }
+    }
+}
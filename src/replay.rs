@@ -0,0 +1,101 @@
+use crate::events::{dispatch, EventHandler, FeedEvent};
+use log::{error, info};
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, BufReader};
+
+//** NOTICE **
+// Plays back recorded Jetstream events through the same handler calls as the live firehose. A
+// recording is Jetstream's output saved one event per line, e.g.
+// websocat "wss://jetstream2.us-east.bsky.network/subscribe?wantedCollections=app.bsky.feed.post&wantedCollections=app.bsky.feed.like" > events.jsonl
+//
+
+/// How fast to play a recording back
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReplaySpeed {
+    /// One event after the other with no waiting, what tests want
+    Instant,
+    /// Keeps the gaps between events, divided by this. 1.0 is as recorded, 10.0 is ten times faster
+    Scaled(f64),
+}
+
+impl ReplaySpeed {
+    /// How long to wait between two events recorded at these times
+    pub fn delay(&self, previous_time_us: i64, time_us: i64) -> Duration {
+        match self {
+            ReplaySpeed::Instant => Duration::ZERO,
+            ReplaySpeed::Scaled(speed) if *speed > 0.0 => {
+                let gap_us = (time_us - previous_time_us).max(0) as f64;
+                Duration::from_micros((gap_us / speed) as u64)
+            }
+            ReplaySpeed::Scaled(_) => Duration::ZERO,
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ReplayReport {
+    /// Events handed to the handler
+    pub events: usize,
+    /// Lines that were not for the feed, like other collections or identity events
+    pub ignored: usize,
+    /// Lines that could not be parsed
+    pub malformed: usize,
+}
+
+pub async fn replay_file<H: EventHandler + ?Sized>(
+    path: &Path,
+    handler: &mut H,
+    speed: ReplaySpeed,
+) -> std::io::Result<ReplayReport> {
+    let file = tokio::fs::File::open(path).await?;
+    let report = replay_events(BufReader::new(file), handler, speed).await?;
+    info!("Replayed {}: {report:?}", path.display());
+    Ok(report)
+}
+
+/// Reads events line by line and hands each to the handler, waiting between them at `speed`
+pub async fn replay_events<R, H>(
+    reader: R,
+    handler: &mut H,
+    speed: ReplaySpeed,
+) -> std::io::Result<ReplayReport>
+where
+    R: AsyncBufRead + Unpin,
+    H: EventHandler + ?Sized,
+{
+    let mut report = ReplayReport::default();
+    let mut previous_time_us: Option<i64> = None;
+    let mut lines = reader.lines();
+    let mut line_number = 0;
+    while let Some(line) = lines.next_line().await? {
+        line_number += 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event = match FeedEvent::from_jetstream_json(&line) {
+            Ok(Some(event)) => event,
+            Ok(None) => {
+                report.ignored += 1;
+                continue;
+            }
+            Err(err) => {
+                error!("Skipping line {line_number} of the recording: {err}");
+                report.malformed += 1;
+                continue;
+            }
+        };
+
+        if let Some(previous_time_us) = previous_time_us {
+            let delay = speed.delay(previous_time_us, event.time_us());
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+        }
+        previous_time_us = Some(event.time_us());
+        dispatch(handler, event).await;
+        report.events += 1;
+    }
+    handler.flush().await;
+    Ok(report)
+}
@@ -0,0 +1,235 @@
+use crate::metrics::{time_query, POSTS_ARCHIVED};
+use crate::store::FeedResult;
+use chrono::DateTime;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::info;
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio_rusqlite::{params, Connection, TransactionBehavior};
+
+//** NOTICE **
+// Posts past the retention limits are moved out of `posts` a chunk at a time instead of being
+// deleted. They go to archived_posts or to one gzipped JSONL file per day, and are written there
+// before they are removed so a crash can at worst archive a post twice. Pinned posts never move.
+// Every feed serves the same posts, so with limits per feed a post is only archived once it is past
+// the limits of all of them
+//
+
+pub const DEFAULT_RETENTION_MAX_POSTS: usize = 10_000;
+pub const DEFAULT_RETENTION_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// Posts moved per transaction, so ingest is never held up for long
+pub(crate) const ARCHIVE_CHUNK_SIZE: usize = 500;
+
+/// A posts row as JSON, what both archives keep
+const POST_AS_JSON: &str = "json_object(
+    'uri', uri, 'text', text, 'author_did', author_did, 'pinned', pinned, 'deleted', deleted,
+    'priority', priority, 'timestamp', timestamp, 'link_url', link_url, 'shares', shares,
+    'feed_context', feed_context, 'cid', cid, 'langs', json(langs), 'embed_kind', embed_kind,
+    'reply_parent', reply_parent, 'reply_root', reply_root, 'matched_rules', json(matched_rules),
+    'alt_text', alt_text, 'link_text', link_text
+)";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ArchiveTarget {
+    /// The archived_posts table of the same db
+    Table,
+    /// posts-<day>.jsonl.gz files in this directory
+    Jsonl(PathBuf),
+}
+
+/// How much one feed keeps
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetentionLimits {
+    /// Newest unpinned posts kept, any number when `None`
+    pub max_posts: Option<usize>,
+    /// Unpinned posts made longer ago than this are archived
+    pub max_age: Option<Duration>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetentionPolicy {
+    /// Newest unpinned posts kept, any number when `None`
+    pub max_posts: Option<usize>,
+    /// Unpinned posts made longer ago than this are archived
+    pub max_age: Option<Duration>,
+    /// Limits by feed name. When set every feed is in here, the ones without their own limits
+    /// with the ones above
+    pub feeds: BTreeMap<String, RetentionLimits>,
+    pub archive: ArchiveTarget,
+    /// How often the feed applies the policy
+    pub interval: Duration,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            max_posts: Some(DEFAULT_RETENTION_MAX_POSTS),
+            max_age: None,
+            feeds: BTreeMap::new(),
+            archive: ArchiveTarget::Table,
+            interval: DEFAULT_RETENTION_INTERVAL,
+        }
+    }
+}
+
+impl RetentionPolicy {
+    /// The limits of each feed, or the global ones when no feed has its own
+    pub fn feed_limits(&self) -> Vec<RetentionLimits> {
+        if self.feeds.is_empty() {
+            vec![RetentionLimits {
+                max_posts: self.max_posts,
+                max_age: self.max_age,
+            }]
+        } else {
+            self.feeds.values().copied().collect()
+        }
+    }
+}
+
+/// Archives every unpinned post past the limits at `now` (unix seconds), returns how many moved
+pub async fn apply_retention(
+    db: &Connection,
+    policy: &RetentionPolicy,
+    now: i64,
+) -> FeedResult<usize> {
+    let _timer = time_query("apply_retention");
+    let Some((before_timestamp, before_uri)) = archive_boundary(db, policy, now).await? else {
+        return Ok(0);
+    };
+
+    let mut archived = 0;
+    loop {
+        let uri = before_uri.clone();
+        let chunk: Vec<(String, String)> = db
+            .call(move |db| {
+                let mut stmt = db.prepare(&format!(
+                    "SELECT uri, {POST_AS_JSON} FROM posts
+                     WHERE pinned = 0 AND (timestamp, uri) <= (?1, ?2)
+                     ORDER BY timestamp, uri
+                     LIMIT ?3"
+                ))?;
+                let rows = stmt
+                    .query_map(params![before_timestamp, &uri, ARCHIVE_CHUNK_SIZE], |row| {
+                        Ok((row.get(0)?, row.get(1)?))
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(rows)
+            })
+            .await?;
+        if chunk.is_empty() {
+            break;
+        }
+
+        let to_table = match &policy.archive {
+            ArchiveTarget::Table => true,
+            ArchiveTarget::Jsonl(dir) => {
+                let dir = dir.clone();
+                let lines: Vec<String> = chunk.iter().map(|(_, post)| post.clone()).collect();
+                tokio::task::spawn_blocking(move || append_jsonl(&dir, now, &lines)).await??;
+                false
+            }
+        };
+        let moved = chunk.len();
+        db.call(move |db| {
+            let tx = db.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            for (uri, post) in chunk {
+                if to_table {
+                    tx.execute(
+                        "INSERT OR REPLACE INTO archived_posts (uri, post, archived_at) VALUES (?1, ?2, ?3)",
+                        params![&uri, &post, now],
+                    )?;
+                }
+                tx.execute("DELETE FROM likes WHERE post_uri = ?1", params![&uri])?;
+                tx.execute("DELETE FROM posts WHERE uri = ?1", params![&uri])?;
+            }
+            tx.commit()?;
+            Ok(())
+        })
+        .await?;
+        archived += moved;
+        POSTS_ARCHIVED.inc_by(moved as u64);
+    }
+    if archived > 0 {
+        info!("Archived {archived} posts");
+    }
+    Ok(archived)
+}
+
+/// The newest (timestamp, uri) to archive, everything up to and including it goes. `None` when
+/// nothing is past the limits
+async fn archive_boundary(
+    db: &Connection,
+    policy: &RetentionPolicy,
+    now: i64,
+) -> FeedResult<Option<(i64, String)>> {
+    let mut boundaries = vec![];
+    for limits in policy.feed_limits() {
+        boundaries.push(feed_boundary(db, limits, now).await?);
+    }
+    Ok(shared_boundary(boundaries))
+}
+
+/// How far one feed would archive on its own
+async fn feed_boundary(
+    db: &Connection,
+    limits: RetentionLimits,
+    now: i64,
+) -> FeedResult<Option<(i64, String)>> {
+    let by_count = match limits.max_posts {
+        Some(max_posts) => {
+            db.call(move |db| {
+                let mut stmt = db.prepare(
+                    "SELECT timestamp, uri FROM posts WHERE pinned = 0
+                     ORDER BY timestamp DESC, uri DESC
+                     LIMIT 1 OFFSET ?1",
+                )?;
+                let mut rows = stmt.query_map(params![max_posts], |row| {
+                    Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+                })?;
+                Ok(rows.next().transpose()?)
+            })
+            .await?
+        }
+        None => None,
+    };
+    Ok(age_boundary(limits, now).into_iter().chain(by_count).max())
+}
+
+/// Only what every feed would archive goes, a feed that keeps everything keeps it for all
+pub(crate) fn shared_boundary(boundaries: Vec<Option<(i64, String)>>) -> Option<(i64, String)> {
+    boundaries
+        .into_iter()
+        .collect::<Option<Vec<_>>>()?
+        .into_iter()
+        .min()
+}
+
+/// The boundary of the age limit alone, anything made before the cutoff. Uris are compared byte by
+/// byte and no real one sorts after U+10FFFF. A limit reaching past the earliest timestamp has none
+pub(crate) fn age_boundary(limits: RetentionLimits, now: i64) -> Option<(i64, String)> {
+    let max_age = i64::try_from(limits.max_age?.as_secs()).ok()?;
+    let cutoff = now.checked_sub(max_age)?.checked_sub(1)?;
+    Some((cutoff, "\u{10FFFF}".to_string()))
+}
+
+/// Adds the lines to the day's file as another gzip member, gzip readers read them all in order
+pub(crate) fn append_jsonl(dir: &Path, now: i64, lines: &[String]) -> std::io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let day = DateTime::from_timestamp(now, 0)
+        .map(|now| now.format("%Y-%m-%d").to_string())
+        .unwrap_or_default();
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(format!("posts-{day}.jsonl.gz")))?;
+    let mut encoder = GzEncoder::new(file, Compression::default());
+    for line in lines {
+        encoder.write_all(line.as_bytes())?;
+        encoder.write_all(b"\n")?;
+    }
+    encoder.finish()?.sync_all()
+}
@@ -0,0 +1,386 @@
+use crate::auth::{verify_service_jwt, DidResolver};
+use crate::health::FirehoseHealth;
+use crate::metrics::{self, count_db_error, PAGE_SIZE, POSTS_ROWS, SERVE_FEED_SECONDS};
+use crate::models::SkeletonItem;
+use crate::skeleton::{load_skeleton_page_for_viewer, Viewer};
+use crate::store::SharedStore;
+use chrono::Utc;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use warp::http::StatusCode;
+use warp::{Filter, Rejection, Reply};
+
+//** NOTICE **
+// skyfeed serves a getFeedSkeleton for us on `port` but it can only hand back bare uris and never
+// sees the Authorization header. It goes through feed_skeleton like this server does, but only
+// this one sends feedContext and serves signed in viewers their own feed, so point the reverse
+// proxy at this server for all the XRPC endpoints and /.well-known/did.json. /metrics, /healthz
+// and /readyz are served here too but have no reason to go through the proxy
+//
+
+/// Largest sendInteractions body taken
+const MAX_INTERACTIONS_BODY_BYTES: u64 = 64 * 1024;
+/// Most interactions one sendInteractions request can carry
+pub const MAX_INTERACTIONS_PER_REQUEST: usize = 100;
+
+/// Who this feed generator is and which feeds it serves, everything did.json and
+/// describeFeedGenerator are built from
+#[derive(Clone, Debug)]
+pub struct FeedGeneratorIdentity {
+    /// Where the feed generator is reached, also makes up its did:web
+    pub hostname: String,
+    /// The account the feed records are published under
+    pub publisher_did: String,
+    pub feed_names: Vec<String>,
+}
+
+impl FeedGeneratorIdentity {
+    /// Service auth tokens have to be made out to this
+    pub fn service_did(&self) -> String {
+        format!("did:web:{}", self.hostname)
+    }
+
+    pub fn feed_uris(&self) -> Vec<String> {
+        self.feed_names
+            .iter()
+            .map(|name| format!("at://{}/app.bsky.feed.generator/{name}", self.publisher_did))
+            .collect()
+    }
+}
+
+#[derive(Clone)]
+pub struct ServerState {
+    pub store: SharedStore,
+    pub max_posts_per_author: Option<usize>,
+    pub identity: FeedGeneratorIdentity,
+    pub did_resolver: Arc<dyn DidResolver>,
+    /// Leave out posts a signed in viewer was served in an earlier session
+    pub hide_seen_posts: bool,
+    pub firehose: Arc<FirehoseHealth>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetFeedSkeletonParams {
+    pub feed: String,
+    pub limit: Option<u64>,
+    pub cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetFeedSkeletonOutput {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+    pub feed: Vec<SkeletonItem>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SendInteractionsInput {
+    pub interactions: Vec<Interaction>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Interaction {
+    pub item: Option<String>,
+    pub event: Option<String>,
+    pub feed_context: Option<String>,
+}
+
+/// The error body XRPC clients expect back
+#[derive(Debug, Serialize)]
+pub struct XrpcError {
+    pub error: String,
+    pub message: String,
+}
+
+pub fn xrpc_error(status: StatusCode, error: &str, message: &str) -> warp::reply::Response {
+    warp::reply::with_status(
+        warp::reply::json(&XrpcError {
+            error: error.to_string(),
+            message: message.to_string(),
+        }),
+        status,
+    )
+    .into_response()
+}
+
+pub fn routes(
+    state: ServerState,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let state = warp::any().map(move || state.clone());
+
+    let feed_skeleton_route = warp::path!("xrpc" / "app.bsky.feed.getFeedSkeleton")
+        .and(warp::get())
+        .and(warp::query::<GetFeedSkeletonParams>())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(state.clone())
+        .then(get_feed_skeleton);
+
+    let did_document_route = warp::path!(".well-known" / "did.json")
+        .and(warp::get())
+        .and(state.clone())
+        .map(|state: ServerState| warp::reply::json(&did_document(&state.identity)));
+
+    let describe_route = warp::path!("xrpc" / "app.bsky.feed.describeFeedGenerator")
+        .and(warp::get())
+        .and(state.clone())
+        .map(|state: ServerState| warp::reply::json(&describe_feed_generator(&state.identity)));
+
+    let interactions_route = warp::path!("xrpc" / "app.bsky.feed.sendInteractions")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(
+            MAX_INTERACTIONS_BODY_BYTES,
+        ))
+        .and(warp::body::json())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(state.clone())
+        .then(send_interactions);
+
+    let metrics_route = warp::path!("metrics")
+        .and(warp::get())
+        .and(state.clone())
+        .then(serve_metrics);
+
+    //Up as long as the process is, for restarting it when it is not
+    let health_route = warp::path!("healthz")
+        .and(warp::get())
+        .map(|| warp::reply::json(&json!({ "status": "ok" })));
+
+    let ready_route = warp::path!("readyz")
+        .and(warp::get())
+        .and(state)
+        .then(readiness);
+
+    feed_skeleton_route
+        .or(interactions_route)
+        .or(did_document_route)
+        .or(describe_route)
+        .or(metrics_route)
+        .or(health_route)
+        .or(ready_route)
+}
+
+pub async fn start_server(state: ServerState, address: impl Into<SocketAddr>) {
+    let address = address.into();
+    info!("Serving XRPC endpoints on {address}");
+    warp::serve(routes(state)).run(address).await;
+}
+
+/// Signed out clients do not send a token and get the same feed as everyone. A token that is sent
+/// has to check out though
+async fn requester_did(
+    authorization: Option<String>,
+    method: &str,
+    state: &ServerState,
+) -> Result<Option<String>, warp::reply::Response> {
+    let Some(authorization) = authorization else {
+        return Ok(None);
+    };
+    match verify_service_jwt(
+        &authorization,
+        &state.identity.service_did(),
+        method,
+        state.did_resolver.as_ref(),
+    )
+    .await
+    {
+        Ok(requester_did) => Ok(Some(requester_did)),
+        Err(err) => {
+            info!("Rejected service auth for {method}: {err}");
+            Err(xrpc_error(
+                StatusCode::UNAUTHORIZED,
+                "AuthenticationRequired",
+                &err.to_string(),
+            ))
+        }
+    }
+}
+
+/// The did:web document that points the Bluesky AppView at this server
+pub fn did_document(identity: &FeedGeneratorIdentity) -> serde_json::Value {
+    json!({
+        "@context": ["https://www.w3.org/ns/did/v1"],
+        "id": identity.service_did(),
+        "service": [{
+            "id": "#bsky_fg",
+            "type": "BskyFeedGenerator",
+            "serviceEndpoint": format!("https://{}", identity.hostname)
+        }]
+    })
+}
+
+pub fn describe_feed_generator(identity: &FeedGeneratorIdentity) -> serde_json::Value {
+    let feeds: Vec<serde_json::Value> = identity
+        .feed_uris()
+        .into_iter()
+        .map(|uri| json!({ "uri": uri }))
+        .collect();
+    json!({
+        "did": identity.service_did(),
+        "feeds": feeds
+    })
+}
+
+async fn get_feed_skeleton(
+    params: GetFeedSkeletonParams,
+    authorization: Option<String>,
+    state: ServerState,
+) -> warp::reply::Response {
+    match feed_skeleton(params, authorization, &state, "xrpc").await {
+        Ok(output) => warp::reply::json(&output).into_response(),
+        Err(response) => response,
+    }
+}
+
+/// getFeedSkeleton short of the reply, skyfeed's server on `port` answers through here too.
+/// `server` labels the metrics
+pub async fn feed_skeleton(
+    params: GetFeedSkeletonParams,
+    authorization: Option<String>,
+    state: &ServerState,
+    server: &str,
+) -> Result<GetFeedSkeletonOutput, warp::reply::Response> {
+    info!("Serving {params:?}");
+    let _timer = SERVE_FEED_SECONDS
+        .with_label_values(&[server])
+        .start_timer();
+    if !state.identity.feed_uris().contains(&params.feed) {
+        return Err(xrpc_error(
+            StatusCode::BAD_REQUEST,
+            "UnknownFeed",
+            &format!("Unknown feed {}", params.feed),
+        ));
+    }
+    let requester_did =
+        requester_did(authorization, "app.bsky.feed.getFeedSkeleton", state).await?;
+    //Anything over 255 is way past the max page size anyway
+    let limit = params.limit.map(|limit| limit.min(u8::MAX as u64) as u8);
+    let page = match load_skeleton_page_for_viewer(
+        state.store.as_ref(),
+        limit,
+        params.cursor.as_deref(),
+        state.max_posts_per_author,
+        requester_did.map(|did| Viewer {
+            did,
+            hide_seen_posts: state.hide_seen_posts,
+        }),
+    )
+    .await
+    {
+        Ok(page) => page,
+        Err(err) => {
+            error!("Failed to load the feed: {err}");
+            count_db_error("load_feed");
+            return Err(xrpc_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "InternalServerError",
+                "Failed to load the feed",
+            ));
+        }
+    };
+
+    let feed: Vec<SkeletonItem> = page
+        .posts
+        .into_iter()
+        .map(|post| SkeletonItem {
+            post: post.uri,
+            //We only store original posts so there is no repost to point to yet
+            reason: None,
+            feed_context: post.feed_context,
+        })
+        .collect();
+    info!("Served {} posts", feed.len());
+    PAGE_SIZE
+        .with_label_values(&[server])
+        .observe(feed.len() as f64);
+    Ok(GetFeedSkeletonOutput {
+        cursor: page.cursor,
+        feed,
+    })
+}
+
+async fn send_interactions(
+    input: SendInteractionsInput,
+    authorization: Option<String>,
+    state: ServerState,
+) -> warp::reply::Response {
+    //Feedback moves the ranking for every viewer, so it has to come from someone
+    let requester_did =
+        match requester_did(authorization, "app.bsky.feed.sendInteractions", &state).await {
+            Ok(Some(requester_did)) => requester_did,
+            Ok(None) => {
+                return xrpc_error(
+                    StatusCode::UNAUTHORIZED,
+                    "AuthenticationRequired",
+                    "sendInteractions needs a service auth token",
+                )
+            }
+            Err(response) => return response,
+        };
+    if input.interactions.len() > MAX_INTERACTIONS_PER_REQUEST {
+        return xrpc_error(
+            StatusCode::BAD_REQUEST,
+            "InvalidRequest",
+            &format!("At most {MAX_INTERACTIONS_PER_REQUEST} interactions can be sent at once"),
+        );
+    }
+    let interactions: Vec<(String, String, Option<String>)> = input
+        .interactions
+        .into_iter()
+        .filter_map(|interaction| match (interaction.item, interaction.event) {
+            (Some(item), Some(event)) => Some((item, event, interaction.feed_context)),
+            _ => None,
+        })
+        .collect();
+    info!("Received {} interactions", interactions.len());
+
+    match state
+        .store
+        .record_interactions(requester_did, interactions)
+        .await
+    {
+        Ok(_) => warp::reply::json(&json!({})).into_response(),
+        Err(err) => {
+            error!("Failed to record interactions: {err}");
+            count_db_error("record_interactions");
+            xrpc_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "InternalServerError",
+                "Failed to record interactions",
+            )
+        }
+    }
+}
+
+async fn serve_metrics(state: ServerState) -> warp::reply::Response {
+    state.firehose.update_lag_metric(Utc::now().timestamp());
+    //The rest of the metrics are still worth serving, the gauge keeps its last value
+    match state.store.posts_count().await {
+        Ok(count) => POSTS_ROWS.set(count as i64),
+        Err(err) => {
+            error!("Failed to count posts: {err}");
+            count_db_error("posts_count");
+        }
+    }
+    warp::reply::with_header(
+        metrics::gather(),
+        "content-type",
+        "text/plain; version=0.0.4",
+    )
+    .into_response()
+}
+
+async fn readiness(state: ServerState) -> warp::reply::Response {
+    let readiness = state.firehose.readiness(state.store.as_ref()).await;
+    let status = if readiness.ready {
+        StatusCode::OK
+    } else {
+        info!("Not ready: {readiness:?}");
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    warp::reply::with_status(warp::reply::json(&readiness), status).into_response()
+}
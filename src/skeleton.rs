@@ -0,0 +1,303 @@
+use crate::metrics::count_db_error;
+use crate::models::{DbPost, FeedPosition, ViewerFilter};
+use crate::store::{FeedResult, FeedStore};
+use chrono::Utc;
+use log::error;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Page size used when a getFeedSkeleton request does not send a `limit`
+pub const DEFAULT_PAGE_SIZE: u64 = 50;
+/// Largest page the lexicon allows for getFeedSkeleton
+pub const MAX_PAGE_SIZE: u64 = 100;
+/// How many rows are read from the db at a time while laying out pages
+const ROWS_PER_READ: u64 = MAX_PAGE_SIZE * 2;
+
+pub struct SkeletonPage {
+    pub posts: Vec<DbPost>,
+    pub cursor: Option<String>,
+}
+
+/// Works out how many posts to serve from the requested `limit`, falling back to the default
+/// and clamping to 1..=100 like the lexicon says
+pub fn page_size(limit: Option<u8>) -> u64 {
+    match limit {
+        None => DEFAULT_PAGE_SIZE,
+        Some(limit) => (limit as u64).clamp(1, MAX_PAGE_SIZE),
+    }
+}
+
+/// The requester from a verified service auth JWT
+#[derive(Clone, Debug)]
+pub struct Viewer {
+    pub did: String,
+    pub hide_seen_posts: bool,
+}
+
+/// Where in the feed a client is. Every post up to `after` was served, `served` are the ones past
+/// it that were too while the author cap held back an earlier one, and when hiding seen posts the
+/// time the viewer started scrolling so posts served earlier in the same session stay put
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SkeletonCursor {
+    pub after: Option<FeedPosition>,
+    pub served: Vec<String>,
+    pub session_start: Option<i64>,
+}
+
+impl SkeletonCursor {
+    /// `timestamp|uri|session_start|served,...`, at-uris never have a `|` or `,` in them
+    pub fn to_cursor_string(&self) -> String {
+        let (timestamp, uri) = match &self.after {
+            Some(after) => (after.timestamp.to_string(), after.uri.as_str()),
+            None => (String::new(), ""),
+        };
+        let session_start = self
+            .session_start
+            .map(|session_start| session_start.to_string())
+            .unwrap_or_default();
+        format!(
+            "{timestamp}|{uri}|{session_start}|{}",
+            self.served.join(",")
+        )
+    }
+}
+
+/// A missing cursor is the first page. A cursor we did not hand out returns `None`
+pub fn parse_cursor(cursor: Option<&str>) -> Option<SkeletonCursor> {
+    let Some(cursor) = cursor else {
+        return Some(SkeletonCursor::default());
+    };
+    let mut fields = cursor.split('|');
+    let (Some(timestamp), Some(uri), Some(session_start), Some(served), None) = (
+        fields.next(),
+        fields.next(),
+        fields.next(),
+        fields.next(),
+        fields.next(),
+    ) else {
+        return None;
+    };
+    let after = match (timestamp, uri) {
+        ("", "") => None,
+        (timestamp, uri) if uri.starts_with("at://") => Some(FeedPosition {
+            timestamp: timestamp.parse().ok()?,
+            uri: uri.to_string(),
+        }),
+        _ => return None,
+    };
+    let session_start = match session_start {
+        "" => None,
+        session_start => Some(session_start.parse().ok()?),
+    };
+    let served = match served {
+        "" => vec![],
+        served => served.split(',').map(str::to_string).collect(),
+    };
+    Some(SkeletonCursor {
+        after,
+        served,
+        session_start,
+    })
+}
+
+/// Lays posts out into pages so no author has more than `max_per_author` posts on one page.
+/// Posts over the cap are not dropped, they wait at the front of the line for the next page.
+pub struct AuthorSpread {
+    page_size: usize,
+    max_per_author: Option<usize>,
+    pages: Vec<Vec<DbPost>>,
+    page: Vec<DbPost>,
+    authors_on_page: HashMap<String, usize>,
+    overflow: VecDeque<DbPost>,
+}
+
+impl AuthorSpread {
+    pub fn new(page_size: usize, max_per_author: Option<usize>) -> Self {
+        Self {
+            page_size,
+            //A cap of 0 would never let anything through, so treat it as no cap
+            max_per_author: max_per_author.filter(|max| *max > 0),
+            pages: vec![],
+            page: vec![],
+            authors_on_page: HashMap::new(),
+            overflow: VecDeque::new(),
+        }
+    }
+
+    /// Pages that are full and will not change anymore
+    pub fn pages(&self) -> &[Vec<DbPost>] {
+        &self.pages
+    }
+
+    pub fn push(&mut self, post: DbPost) {
+        if self.fits_on_page(&post) {
+            self.add_to_page(post);
+            if self.page.len() >= self.page_size {
+                self.next_page();
+            }
+        } else {
+            self.overflow.push_back(post);
+        }
+    }
+
+    /// Call once there are no more posts, flushes the last page and anything still waiting
+    pub fn finish(mut self) -> Vec<Vec<DbPost>> {
+        while !self.overflow.is_empty() {
+            self.next_page();
+        }
+        if !self.page.is_empty() {
+            self.pages.push(self.page);
+        }
+        self.pages
+    }
+
+    fn fits_on_page(&self, post: &DbPost) -> bool {
+        match (&self.max_per_author, &post.author_did) {
+            (Some(max), Some(author_did)) => {
+                self.authors_on_page.get(author_did).copied().unwrap_or(0) < *max
+            }
+            _ => true,
+        }
+    }
+
+    fn add_to_page(&mut self, post: DbPost) {
+        if let Some(author_did) = &post.author_did {
+            *self.authors_on_page.entry(author_did.clone()).or_insert(0) += 1;
+        }
+        self.page.push(post);
+    }
+
+    fn next_page(&mut self) {
+        loop {
+            self.pages.push(std::mem::take(&mut self.page));
+            self.authors_on_page.clear();
+
+            let mut still_waiting = VecDeque::new();
+            while let Some(post) = self.overflow.pop_front() {
+                if self.page.len() < self.page_size && self.fits_on_page(&post) {
+                    self.add_to_page(post);
+                } else {
+                    still_waiting.push_back(post);
+                }
+            }
+            self.overflow = still_waiting;
+
+            if self.page.len() < self.page_size {
+                break;
+            }
+        }
+    }
+}
+
+/// Loads one page of the feed, reading on from where the cursor left off. The cursor points at the
+/// first post the author cap held back, so it comes first on the next page and new posts at the top
+/// or a different `limit` do not move what the next page holds.
+pub async fn load_skeleton_page(
+    store: &dyn FeedStore,
+    limit: Option<u8>,
+    cursor: Option<&str>,
+    max_per_author: Option<usize>,
+) -> FeedResult<SkeletonPage> {
+    load_skeleton_page_for_viewer(store, limit, cursor, max_per_author, None).await
+}
+
+/// Same as [load_skeleton_page] with the viewer's own filters on top
+pub async fn load_skeleton_page_for_viewer(
+    store: &dyn FeedStore,
+    limit: Option<u8>,
+    cursor: Option<&str>,
+    max_per_author: Option<usize>,
+    viewer: Option<Viewer>,
+) -> FeedResult<SkeletonPage> {
+    let page_size = page_size(limit);
+    let Some(mut cursor) = parse_cursor(cursor) else {
+        return Ok(SkeletonPage {
+            posts: vec![],
+            cursor: None,
+        });
+    };
+    let hide_seen_posts = viewer.as_ref().is_some_and(|viewer| viewer.hide_seen_posts);
+    if hide_seen_posts && cursor.session_start.is_none() {
+        cursor.session_start = Some(Utc::now().timestamp());
+    }
+    let viewer_filter = viewer.as_ref().map(|viewer| ViewerFilter {
+        did: viewer.did.clone(),
+        hide_seen_before: if viewer.hide_seen_posts {
+            cursor.session_start
+        } else {
+            None
+        },
+    });
+
+    let served: HashSet<&str> = cursor.served.iter().map(String::as_str).collect();
+    let mut spread = AuthorSpread::new(page_size as usize, max_per_author);
+    //Every post read past the cursor in feed order, and if it was served on an earlier page
+    let mut read: Vec<(FeedPosition, bool)> = vec![];
+    let mut read_until = cursor.after.clone();
+    let out_of_rows = loop {
+        let rows = store
+            .load_feed(ROWS_PER_READ, 0, read_until.clone(), viewer_filter.clone())
+            .await?;
+        let out_of_rows = (rows.len() as u64) < ROWS_PER_READ;
+        for post in rows {
+            let served_before = served.contains(post.uri.as_str());
+            read_until = Some(post.position());
+            read.push((post.position(), served_before));
+            if !served_before {
+                spread.push(post);
+            }
+        }
+        if out_of_rows || !spread.pages().is_empty() {
+            break out_of_rows;
+        }
+    };
+    let posts = spread.finish().into_iter().next().unwrap_or_default();
+
+    let on_page: HashSet<&str> = posts.iter().map(|post| post.uri.as_str()).collect();
+    let mut next = SkeletonCursor {
+        after: cursor.after.clone(),
+        served: vec![],
+        session_start: cursor.session_start,
+    };
+    let mut held_back = false;
+    for (position, served_before) in &read {
+        if !served_before && !on_page.contains(position.uri.as_str()) {
+            held_back = true;
+        } else if held_back {
+            next.served.push(position.uri.clone());
+        } else {
+            next.after = Some(position.clone());
+        }
+    }
+    if !out_of_rows {
+        //Served earlier and further down than this page read
+        let read_uris: HashSet<&str> = read
+            .iter()
+            .map(|(position, _)| position.uri.as_str())
+            .collect();
+        next.served.extend(
+            cursor
+                .served
+                .iter()
+                .filter(|uri| !read_uris.contains(uri.as_str()))
+                .cloned(),
+        );
+    }
+    let next_cursor = (held_back || !out_of_rows).then(|| next.to_cursor_string());
+
+    if let Some(viewer) = viewer.filter(|viewer| viewer.hide_seen_posts) {
+        let uris = posts.iter().map(|post| post.uri.clone()).collect();
+        if let Err(err) = store
+            .mark_posts_served(viewer.did, uris, Utc::now().timestamp())
+            .await
+        {
+            //The page can still be served, the viewer may just see some of it again
+            error!("Failed to remember served posts: {err}");
+            count_db_error("mark_posts_served");
+        }
+    }
+
+    Ok(SkeletonPage {
+        posts,
+        cursor: next_cursor,
+    })
+}
@@ -0,0 +1,141 @@
+use crate::cursor::CursorTracker;
+use crate::events::{dispatch, EventHandler, FeedEvent, LIKE_COLLECTION, POST_COLLECTION};
+use crate::replay::{replay_file, ReplaySpeed};
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use log::{error, info, warn};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+
+//** NOTICE **
+// Where events come from. Every source turns what it reads into FeedEvents and hands them to the
+// handler with `dispatch`, so the handler cannot tell a live Jetstream post from a replayed one
+//
+
+pub const DEFAULT_JETSTREAM_URL: &str = "wss://jetstream2.us-east.bsky.network/subscribe";
+/// Jetstream asks to resume a few seconds before the last event seen so none fall in the gap
+const JETSTREAM_CURSOR_REWIND: Duration = Duration::from_secs(5);
+const DEFAULT_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+#[async_trait]
+pub trait EventSource: Send {
+    /// Reads events and hands each one to the handler, until the source runs out
+    async fn run(&mut self, handler: &mut dyn EventHandler) -> anyhow::Result<()>;
+}
+
+/// Events something else already received, like skyfeed's firehose connection in the feed binary
+pub struct ChannelSource {
+    events: mpsc::Receiver<FeedEvent>,
+}
+
+impl ChannelSource {
+    pub fn new(events: mpsc::Receiver<FeedEvent>) -> Self {
+        Self { events }
+    }
+}
+
+#[async_trait]
+impl EventSource for ChannelSource {
+    async fn run(&mut self, handler: &mut dyn EventHandler) -> anyhow::Result<()> {
+        while let Some(event) = self.events.recv().await {
+            dispatch(handler, event).await;
+        }
+        handler.flush().await;
+        Ok(())
+    }
+}
+
+/// Jetstream's JSON websocket, only sent the post and like collections
+pub struct JetstreamSource {
+    pub url: String,
+    /// Where to resume from after a restart or a dropped connection
+    pub cursor: Arc<CursorTracker>,
+    /// Wait before the first reconnect, doubled on each failure after
+    pub reconnect_delay: Duration,
+}
+
+impl JetstreamSource {
+    pub fn new(url: impl Into<String>, cursor: Arc<CursorTracker>) -> Self {
+        Self {
+            url: url.into(),
+            cursor,
+            reconnect_delay: DEFAULT_RECONNECT_DELAY,
+        }
+    }
+
+    /// The subscribe url with the wanted collections and, once we have one, the cursor to resume at
+    pub fn subscribe_url(&self) -> anyhow::Result<String> {
+        let mut url = url::Url::parse(&self.url)?;
+        {
+            let mut query = url.query_pairs_mut();
+            query.append_pair("wantedCollections", POST_COLLECTION);
+            query.append_pair("wantedCollections", LIKE_COLLECTION);
+            if let Some(cursor) = self.cursor.latest() {
+                let rewind = JETSTREAM_CURSOR_REWIND.as_micros() as i64;
+                query.append_pair("cursor", &(cursor - rewind).max(0).to_string());
+            }
+        }
+        Ok(url.to_string())
+    }
+
+    /// One connection, returns when Jetstream closes it
+    async fn read_connection(&self, handler: &mut dyn EventHandler) -> anyhow::Result<()> {
+        let url = self.subscribe_url()?;
+        info!("Connecting to Jetstream at {url}");
+        let (mut stream, _) = tokio_tungstenite::connect_async(url.as_str()).await?;
+        while let Some(message) = stream.next().await {
+            let json = match message? {
+                Message::Text(json) => json,
+                Message::Close(_) => break,
+                _ => continue,
+            };
+            match FeedEvent::from_jetstream_json(&json) {
+                Ok(Some(event)) => dispatch(handler, event).await,
+                Ok(None) => {}
+                Err(err) => error!("Skipping a Jetstream event that could not be parsed: {err}"),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl EventSource for JetstreamSource {
+    /// Keeps reconnecting, there is no end to a live stream
+    async fn run(&mut self, handler: &mut dyn EventHandler) -> anyhow::Result<()> {
+        let mut delay = self.reconnect_delay;
+        loop {
+            let seen_before = self.cursor.latest();
+            match self.read_connection(handler).await {
+                Ok(()) => warn!("Jetstream closed the connection"),
+                Err(err) => error!("Jetstream connection failed: {err}"),
+            }
+            //The cursor only moves once events are stored, resume after all of them
+            handler.flush().await;
+            //Only back off while nothing is getting through
+            if self.cursor.latest() != seen_before {
+                delay = self.reconnect_delay;
+            }
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+        }
+    }
+}
+
+/// A recording of Jetstream events, see `replay`
+pub struct ReplaySource {
+    pub path: PathBuf,
+    pub speed: ReplaySpeed,
+}
+
+#[async_trait]
+impl EventSource for ReplaySource {
+    async fn run(&mut self, handler: &mut dyn EventHandler) -> anyhow::Result<()> {
+        replay_file(&self.path, handler, self.speed).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,233 @@
+use crate::events::AccountStatus;
+use crate::ingest::{IncomingPost, IngestOutcome};
+use crate::models::{
+    AuthorList, AuthorLists, DbPost, FeedPosition, InteractionCounts, ListedAuthor, PostScoring,
+    SearchResult, Tombstone, ViewerFilter,
+};
+use crate::pipeline::FeedWrite;
+use crate::retention::RetentionPolicy;
+use crate::store::{BatchReport, FeedResult, FeedStore};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio_rusqlite::{Connection, OpenFlags};
+
+//** NOTICE **
+// The feed and admin binaries open the same db file, so it runs in WAL mode where readers never
+// wait on the writer. Each binary writes through one connection and reads through a few read-only
+// ones. Only one connection of either binary can write at a time, the busy timeout is how long a
+// write waits for the other. Write transactions in db.rs are IMMEDIATE because a deferred one that
+// reads first fails straight away, busy timeout or not, when the other binary wrote in between
+//
+
+pub const DEFAULT_READ_CONNECTIONS: usize = 4;
+pub const DEFAULT_BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SqliteOptions {
+    /// Read-only connections serve_feed and the admin tool read through
+    pub read_connections: usize,
+    /// How long a connection waits for the db to be unlocked before the call fails
+    pub busy_timeout: Duration,
+}
+
+impl Default for SqliteOptions {
+    fn default() -> Self {
+        Self {
+            read_connections: DEFAULT_READ_CONNECTIONS,
+            busy_timeout: DEFAULT_BUSY_TIMEOUT,
+        }
+    }
+}
+
+/// The SQLite db at a path, writes go through `writer` and reads are spread over `readers`
+pub struct SqliteStore {
+    writer: Connection,
+    readers: Vec<Connection>,
+    next_reader: AtomicUsize,
+}
+
+impl SqliteStore {
+    /// Opens the db in WAL mode and migrates it before the readers open, they cannot create tables
+    pub async fn open(path: &Path, options: &SqliteOptions) -> FeedResult<Self> {
+        let busy_timeout = options.busy_timeout;
+        let writer = Connection::open(path).await?;
+        writer
+            .call(move |db| {
+                db.busy_timeout(busy_timeout)?;
+                db.pragma_update(None, "journal_mode", "WAL")?;
+                //Safe in WAL mode, a power loss can only lose the last few commits
+                db.pragma_update(None, "synchronous", "NORMAL")?;
+                Ok(())
+            })
+            .await?;
+        writer.migrate().await?;
+
+        let read_connections = options.read_connections.max(1);
+        let mut readers = Vec::with_capacity(read_connections);
+        for _ in 0..read_connections {
+            let reader = Connection::open_with_flags(
+                path,
+                OpenFlags::SQLITE_OPEN_READ_ONLY
+                    | OpenFlags::SQLITE_OPEN_NO_MUTEX
+                    | OpenFlags::SQLITE_OPEN_URI,
+            )
+            .await?;
+            reader
+                .call(move |db| Ok(db.busy_timeout(busy_timeout)?))
+                .await?;
+            readers.push(reader);
+        }
+        Ok(Self {
+            writer,
+            readers,
+            next_reader: AtomicUsize::new(0),
+        })
+    }
+
+    /// Each reader handles one call at a time, so they take turns
+    fn reader(&self) -> &Connection {
+        let next = self.next_reader.fetch_add(1, Ordering::Relaxed);
+        &self.readers[next % self.readers.len()]
+    }
+}
+
+#[async_trait]
+impl FeedStore for SqliteStore {
+    async fn migrate(&self) -> FeedResult<i64> {
+        self.writer.migrate().await
+    }
+
+    async fn ping(&self) -> FeedResult<()> {
+        self.reader().ping().await
+    }
+
+    async fn store_post(
+        &self,
+        post: IncomingPost,
+        scoring: PostScoring,
+    ) -> FeedResult<IngestOutcome> {
+        self.writer.store_post(post, scoring).await
+    }
+
+    async fn write_batch(&self, writes: Vec<FeedWrite>) -> FeedResult<BatchReport> {
+        self.writer.write_batch(writes).await
+    }
+
+    async fn delete_post(&self, uri: String) -> FeedResult<()> {
+        self.writer.delete_post(uri).await
+    }
+
+    async fn load_feed(
+        &self,
+        limit: u64,
+        offset: u64,
+        after: Option<FeedPosition>,
+        viewer: Option<ViewerFilter>,
+    ) -> FeedResult<Vec<DbPost>> {
+        self.reader().load_feed(limit, offset, after, viewer).await
+    }
+
+    async fn posts_count(&self) -> FeedResult<u64> {
+        self.reader().posts_count().await
+    }
+
+    async fn search_posts(
+        &self,
+        query: String,
+        limit: u64,
+        offset: u64,
+    ) -> FeedResult<Vec<SearchResult>> {
+        self.reader().search_posts(query, limit, offset).await
+    }
+
+    async fn apply_retention(&self, policy: &RetentionPolicy, now: i64) -> FeedResult<usize> {
+        self.writer.apply_retention(policy, now).await
+    }
+
+    async fn moderate_post(&self, uri: String, reason: String) -> FeedResult<()> {
+        self.writer.moderate_post(uri, reason).await
+    }
+
+    async fn restore_post(&self, uri: String) -> FeedResult<bool> {
+        self.writer.restore_post(uri).await
+    }
+
+    async fn list_tombstones(&self) -> FeedResult<Vec<Tombstone>> {
+        self.reader().list_tombstones().await
+    }
+
+    async fn add_to_author_list(
+        &self,
+        list: AuthorList,
+        did: String,
+        reason: Option<String>,
+        expires_at: Option<i64>,
+    ) -> FeedResult<()> {
+        self.writer
+            .add_to_author_list(list, did, reason, expires_at)
+            .await
+    }
+
+    async fn remove_from_author_list(&self, list: AuthorList, did: String) -> FeedResult<bool> {
+        self.writer.remove_from_author_list(list, did).await
+    }
+
+    async fn list_authors(&self, list: AuthorList) -> FeedResult<Vec<ListedAuthor>> {
+        self.reader().list_authors(list).await
+    }
+
+    async fn load_author_lists(&self) -> FeedResult<AuthorLists> {
+        self.reader().load_author_lists().await
+    }
+
+    async fn author_standing(&self, did: String) -> FeedResult<Option<AuthorList>> {
+        self.reader().author_standing(did).await
+    }
+
+    async fn apply_account_status(&self, did: String, status: AccountStatus) -> FeedResult<usize> {
+        self.writer.apply_account_status(did, status).await
+    }
+
+    async fn record_interactions(
+        &self,
+        requester_did: String,
+        interactions: Vec<(String, String, Option<String>)>,
+    ) -> FeedResult<()> {
+        self.writer
+            .record_interactions(requester_did, interactions)
+            .await
+    }
+
+    async fn mark_posts_served(
+        &self,
+        requester_did: String,
+        post_uris: Vec<String>,
+        served_at: i64,
+    ) -> FeedResult<()> {
+        self.writer
+            .mark_posts_served(requester_did, post_uris, served_at)
+            .await
+    }
+
+    async fn forget_served_posts_before(&self, before: i64) -> FeedResult<usize> {
+        self.writer.forget_served_posts_before(before).await
+    }
+
+    async fn get_interaction_counts(
+        &self,
+        post_uris: Vec<String>,
+    ) -> FeedResult<HashMap<String, InteractionCounts>> {
+        self.reader().get_interaction_counts(post_uris).await
+    }
+
+    async fn save_cursor(&self, source: String, cursor: i64, updated_at: i64) -> FeedResult<()> {
+        self.writer.save_cursor(source, cursor, updated_at).await
+    }
+
+    async fn load_cursor(&self, source: String) -> FeedResult<Option<i64>> {
+        self.reader().load_cursor(source).await
+    }
+}
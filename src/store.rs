@@ -0,0 +1,407 @@
+use crate::config::Config;
+use crate::db::{
+    add_to_author_list, apply_account_status, author_standing, delete_post,
+    forget_served_posts_before, get_interaction_counts, get_posts_count, list_authors,
+    list_tombstones, load_author_lists, load_cursor, load_feed_for_viewer, mark_posts_served,
+    migrate_db, moderate_post, record_interactions, remove_from_author_list, restore_post,
+    save_cursor, search_posts, write_feed_batch,
+};
+use crate::events::AccountStatus;
+use crate::ingest::{store_scored_post, IncomingPost, IngestOutcome};
+use crate::metrics::time_query;
+use crate::migrations::MigrationError;
+use crate::models::{
+    AuthorList, AuthorLists, DbPost, FeedPosition, InteractionCounts, ListedAuthor, PostScoring,
+    SearchResult, Tombstone, ViewerFilter,
+};
+use crate::pipeline::FeedWrite;
+use crate::retention::{apply_retention, RetentionPolicy};
+use crate::sqlite::SqliteStore;
+use async_trait::async_trait;
+use log::info;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use tokio_rusqlite::Connection;
+
+//** NOTICE **
+// Everything the feed and admin tool keep goes through FeedStore, so both binaries run the same on
+// SQLite or, built with the postgres feature, on one Postgres db shared by several feed instances.
+// SQLite is the store right on a tokio_rusqlite Connection, backed by the functions in db.rs, so
+// code that already holds a Connection can hand it to anything that wants a store. The binaries
+// open a SqliteStore instead, one writer Connection and a few read-only ones on the same file
+//
+
+/// A store every task of a binary can hold on to
+pub type SharedStore = Arc<dyn FeedStore>;
+
+pub type FeedResult<T> = std::result::Result<T, FeedError>;
+
+/// Why a store or db.rs call failed. None of them panic, callers decide whether to log, count or
+/// hand the error on
+#[derive(Debug)]
+pub enum FeedError {
+    /// The connection was closed and nothing more can be done with it
+    Closed,
+    /// Includes the db staying locked or busy, and rows that do not read as what they should be
+    Sqlite(rusqlite::Error),
+    #[cfg(feature = "postgres")]
+    Postgres(tokio_postgres::Error),
+    /// No pooled connection could be had
+    #[cfg(feature = "postgres")]
+    Pool(deadpool_postgres::PoolError),
+    Migration(MigrationError),
+    /// Writing an archive file
+    Io(std::io::Error),
+    /// A setting the store cannot work with
+    Config(String),
+    Other(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl fmt::Display for FeedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeedError::Closed => write!(f, "The database connection is closed"),
+            FeedError::Sqlite(err) => write!(f, "SQLite: {err}"),
+            #[cfg(feature = "postgres")]
+            FeedError::Postgres(err) => write!(f, "Postgres: {err}"),
+            #[cfg(feature = "postgres")]
+            FeedError::Pool(err) => write!(f, "Could not get a database connection: {err}"),
+            FeedError::Migration(err) => write!(f, "{err}"),
+            FeedError::Io(err) => write!(f, "Could not write the archive: {err}"),
+            FeedError::Config(message) => write!(f, "{message}"),
+            FeedError::Other(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for FeedError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FeedError::Sqlite(err) => Some(err),
+            #[cfg(feature = "postgres")]
+            FeedError::Postgres(err) => Some(err),
+            #[cfg(feature = "postgres")]
+            FeedError::Pool(err) => Some(err),
+            FeedError::Migration(err) => Some(err),
+            FeedError::Io(err) => Some(err),
+            FeedError::Other(err) => Some(err.as_ref()),
+            FeedError::Closed | FeedError::Config(_) => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for FeedError {
+    fn from(err: rusqlite::Error) -> Self {
+        FeedError::Sqlite(err)
+    }
+}
+
+impl From<tokio_rusqlite::Error> for FeedError {
+    fn from(err: tokio_rusqlite::Error) -> Self {
+        match err {
+            tokio_rusqlite::Error::ConnectionClosed | tokio_rusqlite::Error::Close(_) => {
+                FeedError::Closed
+            }
+            tokio_rusqlite::Error::Rusqlite(err) => FeedError::Sqlite(err),
+            tokio_rusqlite::Error::Other(err) => FeedError::Other(err),
+            err => FeedError::Other(Box::new(err)),
+        }
+    }
+}
+
+impl From<MigrationError> for FeedError {
+    fn from(err: MigrationError) -> Self {
+        FeedError::Migration(err)
+    }
+}
+
+impl From<std::io::Error> for FeedError {
+    fn from(err: std::io::Error) -> Self {
+        FeedError::Io(err)
+    }
+}
+
+impl From<tokio::task::JoinError> for FeedError {
+    fn from(err: tokio::task::JoinError) -> Self {
+        FeedError::Other(Box::new(err))
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl From<tokio_postgres::Error> for FeedError {
+    fn from(err: tokio_postgres::Error) -> Self {
+        FeedError::Postgres(err)
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl From<deadpool_postgres::PoolError> for FeedError {
+    fn from(err: deadpool_postgres::PoolError) -> Self {
+        FeedError::Pool(err)
+    }
+}
+
+/// What [FeedStore::write_batch] committed
+#[derive(Debug, Default, PartialEq)]
+pub struct BatchReport {
+    pub stored: u64,
+    pub curated: u64,
+}
+
+#[async_trait]
+pub trait FeedStore: Send + Sync {
+    /// Brings the schema up to the version this binary knows and returns it
+    async fn migrate(&self) -> FeedResult<i64>;
+    /// The cheapest query there is, for readiness checks
+    async fn ping(&self) -> FeedResult<()>;
+
+    /// Stores a post the classifier accepted unless its author is blocked, it has a tombstone or
+    /// its link is already in the feed, see [IngestOutcome]
+    async fn store_post(
+        &self,
+        post: IncomingPost,
+        scoring: PostScoring,
+    ) -> FeedResult<IngestOutcome>;
+    /// Makes every write in order, all of them or none
+    async fn write_batch(&self, writes: Vec<FeedWrite>) -> FeedResult<BatchReport>;
+    /// Deletes a post and its likes
+    async fn delete_post(&self, uri: String) -> FeedResult<()>;
+    /// Posts to serve, newest first and only the ones after `after` when it is set, with the
+    /// viewer's own filters when there is one
+    async fn load_feed(
+        &self,
+        limit: u64,
+        offset: u64,
+        after: Option<FeedPosition>,
+        viewer: Option<ViewerFilter>,
+    ) -> FeedResult<Vec<DbPost>>;
+    async fn posts_count(&self) -> FeedResult<u64>;
+    /// Every word of `query` has to match the text, alt text or link text, best match first
+    async fn search_posts(
+        &self,
+        query: String,
+        limit: u64,
+        offset: u64,
+    ) -> FeedResult<Vec<SearchResult>>;
+    /// Archives every unpinned post past the limits at `now`, returns how many moved
+    async fn apply_retention(&self, policy: &RetentionPolicy, now: i64) -> FeedResult<usize>;
+
+    /// Likes are only kept for posts in the feed
+    async fn like_post(&self, like_uri: String, liked_post_uri: String) -> FeedResult<()> {
+        self.write_batch(vec![FeedWrite::Like {
+            like_uri,
+            liked_post_uri,
+        }])
+        .await?;
+        Ok(())
+    }
+    async fn delete_like(&self, like_uri: String) -> FeedResult<()> {
+        self.write_batch(vec![FeedWrite::DeleteLike { like_uri }])
+            .await?;
+        Ok(())
+    }
+
+    /// Takes a post out of the feed and leaves a tombstone so it is never stored again
+    async fn moderate_post(&self, uri: String, reason: String) -> FeedResult<()>;
+    /// Returns false when the post had no tombstone
+    async fn restore_post(&self, uri: String) -> FeedResult<bool>;
+    /// Most recent first
+    async fn list_tombstones(&self) -> FeedResult<Vec<Tombstone>>;
+    async fn add_to_author_list(
+        &self,
+        list: AuthorList,
+        did: String,
+        reason: Option<String>,
+        expires_at: Option<i64>,
+    ) -> FeedResult<()>;
+    /// Returns false when the author was not on the list
+    async fn remove_from_author_list(&self, list: AuthorList, did: String) -> FeedResult<bool>;
+    /// Expired entries included
+    async fn list_authors(&self, list: AuthorList) -> FeedResult<Vec<ListedAuthor>>;
+    /// Unexpired entries of both lists
+    async fn load_author_lists(&self) -> FeedResult<AuthorLists>;
+    async fn author_standing(&self, did: String) -> FeedResult<Option<AuthorList>>;
+    /// Returns how many posts were purged
+    async fn apply_account_status(&self, did: String, status: AccountStatus) -> FeedResult<usize>;
+
+    /// Feedback from app.bsky.feed.sendInteractions, as (post uri, event, feed context). Only the
+    /// first of each from a requester counts, and only for posts in the feed
+    async fn record_interactions(
+        &self,
+        requester_did: String,
+        interactions: Vec<(String, String, Option<String>)>,
+    ) -> FeedResult<()>;
+    async fn mark_posts_served(
+        &self,
+        requester_did: String,
+        post_uris: Vec<String>,
+        served_at: i64,
+    ) -> FeedResult<()>;
+    async fn forget_served_posts_before(&self, before: i64) -> FeedResult<usize>;
+    async fn get_interaction_counts(
+        &self,
+        post_uris: Vec<String>,
+    ) -> FeedResult<HashMap<String, InteractionCounts>>;
+
+    /// Remembers how far into the event stream `source` got
+    async fn save_cursor(&self, source: String, cursor: i64, updated_at: i64) -> FeedResult<()>;
+    async fn load_cursor(&self, source: String) -> FeedResult<Option<i64>>;
+}
+
+/// Opens and migrates the db the config points at, Postgres when database_url is set
+pub async fn open_store(config: &Config) -> FeedResult<SharedStore> {
+    let store: SharedStore = match &config.database_url {
+        #[cfg(feature = "postgres")]
+        Some(url) => Arc::new(crate::postgres::PostgresStore::connect(url)?),
+        #[cfg(not(feature = "postgres"))]
+        Some(_) => {
+            return Err(FeedError::Config(
+                "database_url needs a build with the postgres feature".to_string(),
+            ))
+        }
+        None => Arc::new(SqliteStore::open(&config.database_path, &config.sqlite).await?),
+    };
+    let version = store.migrate().await?;
+    info!("Database is at schema version {version}");
+    Ok(store)
+}
+
+#[async_trait]
+impl FeedStore for Connection {
+    async fn migrate(&self) -> FeedResult<i64> {
+        Ok(migrate_db(self).await?)
+    }
+
+    async fn ping(&self) -> FeedResult<()> {
+        self.call(|db| Ok(db.query_row("SELECT 1", [], |row| row.get::<_, i64>(0))?))
+            .await?;
+        Ok(())
+    }
+
+    async fn store_post(
+        &self,
+        post: IncomingPost,
+        scoring: PostScoring,
+    ) -> FeedResult<IngestOutcome> {
+        let _timer = time_query("insert_post");
+        Ok(self
+            .call(move |db| Ok(store_scored_post(db, &post, &scoring)?))
+            .await?)
+    }
+
+    async fn write_batch(&self, writes: Vec<FeedWrite>) -> FeedResult<BatchReport> {
+        write_feed_batch(self, writes).await
+    }
+
+    async fn delete_post(&self, uri: String) -> FeedResult<()> {
+        delete_post(self, uri).await
+    }
+
+    async fn load_feed(
+        &self,
+        limit: u64,
+        offset: u64,
+        after: Option<FeedPosition>,
+        viewer: Option<ViewerFilter>,
+    ) -> FeedResult<Vec<DbPost>> {
+        load_feed_for_viewer(self, limit, offset, after, viewer).await
+    }
+
+    async fn posts_count(&self) -> FeedResult<u64> {
+        get_posts_count(self).await
+    }
+
+    async fn search_posts(
+        &self,
+        query: String,
+        limit: u64,
+        offset: u64,
+    ) -> FeedResult<Vec<SearchResult>> {
+        search_posts(self, query, limit, offset).await
+    }
+
+    async fn apply_retention(&self, policy: &RetentionPolicy, now: i64) -> FeedResult<usize> {
+        apply_retention(self, policy, now).await
+    }
+
+    async fn moderate_post(&self, uri: String, reason: String) -> FeedResult<()> {
+        moderate_post(self, uri, reason).await
+    }
+
+    async fn restore_post(&self, uri: String) -> FeedResult<bool> {
+        restore_post(self, uri).await
+    }
+
+    async fn list_tombstones(&self) -> FeedResult<Vec<Tombstone>> {
+        list_tombstones(self).await
+    }
+
+    async fn add_to_author_list(
+        &self,
+        list: AuthorList,
+        did: String,
+        reason: Option<String>,
+        expires_at: Option<i64>,
+    ) -> FeedResult<()> {
+        add_to_author_list(self, list, did, reason, expires_at).await
+    }
+
+    async fn remove_from_author_list(&self, list: AuthorList, did: String) -> FeedResult<bool> {
+        remove_from_author_list(self, list, did).await
+    }
+
+    async fn list_authors(&self, list: AuthorList) -> FeedResult<Vec<ListedAuthor>> {
+        list_authors(self, list).await
+    }
+
+    async fn load_author_lists(&self) -> FeedResult<AuthorLists> {
+        load_author_lists(self).await
+    }
+
+    async fn author_standing(&self, did: String) -> FeedResult<Option<AuthorList>> {
+        Ok(self.call(move |db| Ok(author_standing(db, &did)?)).await?)
+    }
+
+    async fn apply_account_status(&self, did: String, status: AccountStatus) -> FeedResult<usize> {
+        Ok(self
+            .call(move |db| Ok(apply_account_status(db, &did, status)?))
+            .await?)
+    }
+
+    async fn record_interactions(
+        &self,
+        requester_did: String,
+        interactions: Vec<(String, String, Option<String>)>,
+    ) -> FeedResult<()> {
+        record_interactions(self, requester_did, interactions).await
+    }
+
+    async fn mark_posts_served(
+        &self,
+        requester_did: String,
+        post_uris: Vec<String>,
+        served_at: i64,
+    ) -> FeedResult<()> {
+        mark_posts_served(self, requester_did, post_uris, served_at).await
+    }
+
+    async fn forget_served_posts_before(&self, before: i64) -> FeedResult<usize> {
+        forget_served_posts_before(self, before).await
+    }
+
+    async fn get_interaction_counts(
+        &self,
+        post_uris: Vec<String>,
+    ) -> FeedResult<HashMap<String, InteractionCounts>> {
+        get_interaction_counts(self, post_uris).await
+    }
+
+    async fn save_cursor(&self, source: String, cursor: i64, updated_at: i64) -> FeedResult<()> {
+        save_cursor(self, source, cursor, updated_at).await
+    }
+
+    async fn load_cursor(&self, source: String) -> FeedResult<Option<i64>> {
+        load_cursor(self, source).await
+    }
+}
@@ -0,0 +1,153 @@
+use bsky_thread_and_blog_feed::appview::AppViewClient;
+use bsky_thread_and_blog_feed::db::{initialize_db, load_feed_from_db};
+use bsky_thread_and_blog_feed::events::{AccountStatus, FeedEvent};
+use bsky_thread_and_blog_feed::handler::FeedIngestHandler;
+use bsky_thread_and_blog_feed::health::FirehoseHealth;
+use bsky_thread_and_blog_feed::pipeline::{IngestOptions, IngestPipeline};
+use bsky_thread_and_blog_feed::replay::{replay_events, ReplaySpeed};
+use std::sync::Arc;
+use tokio_rusqlite::Connection;
+
+/// Alice and Bob each post something for the feed and Bob likes Alice's post
+const POSTS: &str = r#"{"did":"did:plc:alice","time_us":1736510400000000,"kind":"commit","commit":{"rev":"a","operation":"create","collection":"app.bsky.feed.post","rkey":"1","record":{"text":"New rust blog post is up","createdAt":"2025-01-10T12:00:00.000Z"},"cid":"bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm"}}
+{"did":"did:plc:bob","time_us":1736510401000000,"kind":"commit","commit":{"rev":"b","operation":"create","collection":"app.bsky.feed.post","rkey":"1","record":{"text":"My embedded rust thread","createdAt":"2025-01-10T12:00:01.000Z"},"cid":"bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpn"}}
+{"did":"did:plc:bob","time_us":1736510402000000,"kind":"commit","commit":{"rev":"c","operation":"create","collection":"app.bsky.feed.like","rkey":"l1","record":{"subject":{"uri":"at://did:plc:alice/app.bsky.feed.post/1","cid":"bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm"},"createdAt":"2025-01-10T12:00:02.000Z"},"cid":"bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpo"}}
+"#;
+
+fn account(did: &str, active: bool, status: Option<&str>) -> String {
+    let status = status.map_or(String::new(), |status| format!(r#","status":"{status}""#));
+    format!(
+        r#"{{"did":"{did}","time_us":1736510410000000,"kind":"account","account":{{"active":{active},"did":"{did}","seq":1,"time":"2025-01-10T12:00:10.000Z"{status}}}}}"#
+    )
+}
+
+async fn handler() -> (FeedIngestHandler, Connection) {
+    let db = Connection::open_in_memory().await.unwrap();
+    initialize_db(&db).await.unwrap();
+    let handler = FeedIngestHandler {
+        pipeline: IngestPipeline::start(Arc::new(db.clone()), "test", IngestOptions::default()),
+        //Nothing here is liked by the publisher, so the AppView is never asked
+        appview: Arc::new(AppViewClient::new("http://127.0.0.1:9")),
+        feed_author_did: "did:plc:publisher".to_string(),
+        firehose: Arc::new(FirehoseHealth::default()),
+    };
+    (handler, db)
+}
+
+async fn replay(handler: &mut FeedIngestHandler, events: &str) {
+    replay_events(events.as_bytes(), handler, ReplaySpeed::Instant)
+        .await
+        .unwrap();
+}
+
+async fn feed_authors(db: &Connection) -> Vec<String> {
+    let mut authors: Vec<String> = load_feed_from_db(db, 10, 0)
+        .await
+        .unwrap()
+        .into_iter()
+        .filter_map(|post| post.author_did)
+        .collect();
+    authors.sort();
+    authors
+}
+
+async fn count(db: &Connection, query: &'static str) -> u64 {
+    db.call(move |db| Ok(db.query_row(query, [], |row| row.get(0))?))
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn deactivated_accounts_are_hidden_until_they_come_back() {
+    let (mut handler, db) = handler().await;
+    replay(&mut handler, POSTS).await;
+    assert_eq!(
+        feed_authors(&db).await,
+        vec!["did:plc:alice", "did:plc:bob"]
+    );
+
+    replay(
+        &mut handler,
+        &account("did:plc:alice", false, Some("deactivated")),
+    )
+    .await;
+    assert_eq!(feed_authors(&db).await, vec!["did:plc:bob"]);
+    //Kept for when they come back
+    assert_eq!(count(&db, "SELECT COUNT(*) FROM posts").await, 2);
+
+    replay(&mut handler, &account("did:plc:alice", true, None)).await;
+    assert_eq!(
+        feed_authors(&db).await,
+        vec!["did:plc:alice", "did:plc:bob"]
+    );
+}
+
+#[tokio::test]
+async fn deleted_and_taken_down_accounts_are_purged() {
+    let (mut handler, db) = handler().await;
+    replay(&mut handler, POSTS).await;
+    assert_eq!(count(&db, "SELECT COUNT(*) FROM likes").await, 1);
+
+    replay(
+        &mut handler,
+        &account("did:plc:bob", false, Some("deleted")),
+    )
+    .await;
+    assert_eq!(feed_authors(&db).await, vec!["did:plc:alice"]);
+    //Bob's like on Alice's post went with him
+    assert_eq!(count(&db, "SELECT COUNT(*) FROM likes").await, 0);
+
+    replay(
+        &mut handler,
+        &account("did:plc:alice", false, Some("takendown")),
+    )
+    .await;
+    assert_eq!(count(&db, "SELECT COUNT(*) FROM posts").await, 0);
+}
+
+#[tokio::test]
+async fn posts_without_an_author_and_archived_posts_go_too() {
+    let (mut handler, db) = handler().await;
+    //Stored before author_did was kept, and one archived by retention
+    db.call(|db| {
+        db.execute_batch(
+            "INSERT INTO posts (uri, text, pinned, deleted, priority, timestamp)
+             VALUES ('at://did:plc:carol/app.bsky.feed.post/1', 'rust blog', 0, 0, 40, 1);
+             INSERT INTO archived_posts (uri, post, archived_at)
+             VALUES ('at://did:plc:carol/app.bsky.feed.post/0', '{}', 1);",
+        )?;
+        Ok(())
+    })
+    .await
+    .unwrap();
+    assert_eq!(load_feed_from_db(&db, 10, 0).await.unwrap().len(), 1);
+
+    replay(
+        &mut handler,
+        &account("did:plc:carol", false, Some("suspended")),
+    )
+    .await;
+    assert!(load_feed_from_db(&db, 10, 0).await.unwrap().is_empty());
+
+    replay(
+        &mut handler,
+        &account("did:plc:carol", false, Some("takendown")),
+    )
+    .await;
+    assert_eq!(count(&db, "SELECT COUNT(*) FROM posts").await, 0);
+    assert_eq!(count(&db, "SELECT COUNT(*) FROM archived_posts").await, 0);
+}
+
+#[test]
+fn account_events_the_feed_does_not_act_on_are_ignored() {
+    let event = FeedEvent::from_jetstream_json(&account("did:plc:alice", false, Some("throttled")));
+    assert!(event.unwrap().is_none());
+
+    match FeedEvent::from_jetstream_json(&account("did:plc:alice", false, Some("suspended"))) {
+        Ok(Some(FeedEvent::Account { did, status, .. })) => {
+            assert_eq!(did, "did:plc:alice");
+            assert_eq!(status, AccountStatus::Suspended);
+        }
+        other => panic!("Expected an account event, got {other:?}"),
+    }
+}
@@ -0,0 +1,441 @@
+use async_trait::async_trait;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use bsky_thread_and_blog_feed::auth::{
+    is_public_hostname, signing_key_from_did_document, verify_service_jwt, AuthError, DidResolver,
+    HttpDidResolver, PublicKey, StaticDidResolver,
+};
+use bsky_thread_and_blog_feed::db::initialize_db;
+use bsky_thread_and_blog_feed::health::FirehoseHealth;
+use bsky_thread_and_blog_feed::models::REQUEST_LESS;
+use bsky_thread_and_blog_feed::server::{
+    feed_skeleton, routes, FeedGeneratorIdentity, GetFeedSkeletonParams, ServerState,
+};
+use chrono::Utc;
+use k256::ecdsa::signature::Signer;
+use k256::ecdsa::{Signature, SigningKey};
+use serde_json::json;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio_rusqlite::{params, Connection};
+
+const SERVICE_DID: &str = "did:web:feed.example.com";
+const VIEWER_DID: &str = "did:plc:viewer";
+const GET_FEED_SKELETON: &str = "app.bsky.feed.getFeedSkeleton";
+
+fn viewer_key() -> SigningKey {
+    SigningKey::from_slice(&[7u8; 32]).unwrap()
+}
+
+fn resolver() -> StaticDidResolver {
+    let mut resolver = StaticDidResolver::default();
+    resolver.keys.insert(
+        VIEWER_DID.to_string(),
+        PublicKey::K256(*viewer_key().verifying_key()),
+    );
+    resolver
+}
+
+fn service_jwt(key: &SigningKey, aud: &str, lxm: &str, exp: i64) -> String {
+    signed_jwt(
+        key,
+        json!({ "iss": VIEWER_DID, "aud": aud, "lxm": lxm, "iat": Utc::now().timestamp(), "exp": exp }),
+    )
+}
+
+fn signed_jwt(key: &SigningKey, claims: serde_json::Value) -> String {
+    let header = URL_SAFE_NO_PAD.encode(json!({ "alg": "ES256K", "typ": "JWT" }).to_string());
+    let claims = URL_SAFE_NO_PAD.encode(claims.to_string());
+    let signed_part = format!("{header}.{claims}");
+    let signature: Signature = key.sign(signed_part.as_bytes());
+    format!(
+        "{signed_part}.{}",
+        URL_SAFE_NO_PAD.encode(signature.to_bytes())
+    )
+}
+
+fn a_minute_from_now() -> i64 {
+    Utc::now().timestamp() + 60
+}
+
+#[tokio::test]
+async fn valid_token_returns_the_requester_did() {
+    let token = service_jwt(
+        &viewer_key(),
+        SERVICE_DID,
+        GET_FEED_SKELETON,
+        a_minute_from_now(),
+    );
+    let did = verify_service_jwt(
+        &format!("Bearer {token}"),
+        SERVICE_DID,
+        GET_FEED_SKELETON,
+        &resolver(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(did, VIEWER_DID);
+}
+
+#[tokio::test]
+async fn bad_tokens_are_rejected() {
+    let resolver = resolver();
+
+    let wrong_audience = service_jwt(
+        &viewer_key(),
+        "did:web:someone.else",
+        GET_FEED_SKELETON,
+        a_minute_from_now(),
+    );
+    let result =
+        verify_service_jwt(&wrong_audience, SERVICE_DID, GET_FEED_SKELETON, &resolver).await;
+    assert!(matches!(result, Err(AuthError::WrongAudience(_))));
+
+    let expired = service_jwt(&viewer_key(), SERVICE_DID, GET_FEED_SKELETON, 0);
+    let result = verify_service_jwt(&expired, SERVICE_DID, GET_FEED_SKELETON, &resolver).await;
+    assert!(matches!(result, Err(AuthError::Expired)));
+
+    let wrong_method = service_jwt(
+        &viewer_key(),
+        SERVICE_DID,
+        "com.atproto.repo.createRecord",
+        a_minute_from_now(),
+    );
+    let result = verify_service_jwt(&wrong_method, SERVICE_DID, GET_FEED_SKELETON, &resolver).await;
+    assert!(matches!(result, Err(AuthError::WrongMethod(_))));
+
+    let someone_elses_key = SigningKey::from_slice(&[9u8; 32]).unwrap();
+    let forged = service_jwt(
+        &someone_elses_key,
+        SERVICE_DID,
+        GET_FEED_SKELETON,
+        a_minute_from_now(),
+    );
+    let result = verify_service_jwt(&forged, SERVICE_DID, GET_FEED_SKELETON, &resolver).await;
+    assert!(matches!(result, Err(AuthError::BadSignature)));
+}
+
+#[tokio::test]
+async fn tokens_have_to_be_short_lived_and_for_a_method() {
+    let resolver = resolver();
+    let now = Utc::now().timestamp();
+    let claims = json!({
+        "iss": VIEWER_DID,
+        "aud": SERVICE_DID,
+        "lxm": GET_FEED_SKELETON,
+        "iat": now,
+        "exp": now + 60
+    });
+    let without = |claim: &str| {
+        let mut claims = claims.clone();
+        claims.as_object_mut().unwrap().remove(claim);
+        signed_jwt(&viewer_key(), claims)
+    };
+    let with = |claim: &str, value: i64| {
+        let mut claims = claims.clone();
+        claims[claim] = json!(value);
+        signed_jwt(&viewer_key(), claims)
+    };
+
+    for token in [without("lxm"), without("iat")] {
+        let result = verify_service_jwt(&token, SERVICE_DID, GET_FEED_SKELETON, &resolver).await;
+        assert!(matches!(result, Err(AuthError::Malformed(_))));
+    }
+    for token in [with("iat", now + 600), with("exp", now + 24 * 60 * 60)] {
+        let result = verify_service_jwt(&token, SERVICE_DID, GET_FEED_SKELETON, &resolver).await;
+        assert!(matches!(result, Err(AuthError::BadLifetime(_))));
+    }
+
+    //Service tokens name the key they were signed with, users have just the one
+    let mut claims = claims.clone();
+    claims["iss"] = json!(format!("{VIEWER_DID}#atproto_labeler"));
+    let result = verify_service_jwt(
+        &signed_jwt(&viewer_key(), claims),
+        SERVICE_DID,
+        GET_FEED_SKELETON,
+        &resolver,
+    )
+    .await;
+    assert!(matches!(result, Err(AuthError::Malformed(_))));
+}
+
+/// Has the viewer's key from before they rotated it cached
+struct RotatedKeyResolver {
+    cached: Mutex<Option<PublicKey>>,
+    lookups: AtomicUsize,
+}
+
+#[async_trait]
+impl DidResolver for RotatedKeyResolver {
+    async fn resolve_signing_key(&self, _did: &str) -> Result<PublicKey, AuthError> {
+        if let Some(key) = self.cached.lock().unwrap().clone() {
+            return Ok(key);
+        }
+        self.lookups.fetch_add(1, Ordering::SeqCst);
+        let key = PublicKey::K256(*viewer_key().verifying_key());
+        *self.cached.lock().unwrap() = Some(key.clone());
+        Ok(key)
+    }
+
+    async fn forget_signing_key(&self, _did: &str) -> bool {
+        self.cached.lock().unwrap().take().is_some()
+    }
+}
+
+#[tokio::test]
+async fn a_cached_key_that_fails_is_looked_up_again_once() {
+    let old_key = SigningKey::from_slice(&[9u8; 32]).unwrap();
+    let resolver = RotatedKeyResolver {
+        cached: Mutex::new(Some(PublicKey::K256(*old_key.verifying_key()))),
+        lookups: AtomicUsize::new(0),
+    };
+    let token = service_jwt(
+        &viewer_key(),
+        SERVICE_DID,
+        GET_FEED_SKELETON,
+        a_minute_from_now(),
+    );
+    let did = verify_service_jwt(&token, SERVICE_DID, GET_FEED_SKELETON, &resolver)
+        .await
+        .unwrap();
+    assert_eq!(did, VIEWER_DID);
+    assert_eq!(resolver.lookups.load(Ordering::SeqCst), 1);
+
+    //A forged token gets the key looked up once more, not over and over
+    let forged = service_jwt(
+        &old_key,
+        SERVICE_DID,
+        GET_FEED_SKELETON,
+        a_minute_from_now(),
+    );
+    let result = verify_service_jwt(&forged, SERVICE_DID, GET_FEED_SKELETON, &resolver).await;
+    assert!(matches!(result, Err(AuthError::BadSignature)));
+    assert_eq!(resolver.lookups.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn only_public_did_web_hosts_are_resolved() {
+    for host in ["feed.example.com", "bsky.social", "xn--bcher-kva.example"] {
+        assert!(is_public_hostname(host), "{host}");
+    }
+    for host in [
+        "localhost",
+        "169.254.169.254",
+        "10.0.0.1",
+        "localhost%3A8080",
+        "example.com%3A8080",
+        "example.com:path",
+        "[::1]",
+        "printer.local",
+        "metadata.google.internal",
+        "Example.com",
+        "-bad.example.com",
+        "example..com",
+        "",
+    ] {
+        assert!(!is_public_hostname(host), "{host}");
+    }
+
+    let resolver = HttpDidResolver::new("https://plc.directory");
+    assert_eq!(
+        resolver
+            .did_document_url("did:web:feed.example.com")
+            .unwrap(),
+        "https://feed.example.com/.well-known/did.json"
+    );
+    assert_eq!(
+        resolver
+            .did_document_url("did:plc:rnpkyqnmsw4ipey6eotbdnnf")
+            .unwrap(),
+        "https://plc.directory/did:plc:rnpkyqnmsw4ipey6eotbdnnf"
+    );
+    for did in [
+        "did:web:169.254.169.254",
+        "did:web:localhost%3A8080",
+        "did:plc:../../admin",
+        "did:key:z6Mk",
+    ] {
+        assert!(
+            matches!(resolver.did_document_url(did), Err(AuthError::Resolve(_))),
+            "{did}"
+        );
+    }
+}
+
+#[test]
+fn signing_key_is_read_from_the_did_document() {
+    let mut key_bytes = vec![0xe7, 0x01];
+    key_bytes.extend_from_slice(
+        viewer_key()
+            .verifying_key()
+            .to_encoded_point(true)
+            .as_bytes(),
+    );
+    let document = json!({
+        "id": VIEWER_DID,
+        "verificationMethod": [{
+            "id": format!("{VIEWER_DID}#atproto"),
+            "type": "Multikey",
+            "controller": VIEWER_DID,
+            "publicKeyMultibase": multibase::encode(multibase::Base::Base58Btc, key_bytes)
+        }]
+    });
+
+    let key = signing_key_from_did_document(&document.to_string()).unwrap();
+    assert!(matches!(key, PublicKey::K256(key) if key == *viewer_key().verifying_key()));
+}
+
+async fn feed_with_two_authors() -> Connection {
+    let db = Connection::open_in_memory().await.unwrap();
+    initialize_db(&db).await.unwrap();
+    db.call(|db| {
+        for (i, author) in ["did:plc:noisy", "did:plc:quiet"].iter().enumerate() {
+            db.execute(
+                "INSERT INTO posts (uri, text, author_did, pinned, deleted, priority, timestamp) VALUES (?1, 'rust blog', ?2, 0, 0, 40, ?3)",
+                params![format!("at://{author}/app.bsky.feed.post/{i}"), author, i as i64],
+            )?;
+        }
+        Ok(())
+    })
+    .await
+    .unwrap();
+    db
+}
+
+fn server_state(db: &Connection, hide_seen_posts: bool) -> ServerState {
+    ServerState {
+        store: Arc::new(db.clone()),
+        max_posts_per_author: None,
+        identity: FeedGeneratorIdentity {
+            hostname: "feed.example.com".to_string(),
+            publisher_did: "did:plc:publisher".to_string(),
+            feed_names: vec!["TechThreadsAndMore".to_string()],
+        },
+        did_resolver: Arc::new(resolver()),
+        hide_seen_posts,
+        firehose: Arc::new(FirehoseHealth::default()),
+    }
+}
+
+async fn get_feed(state: &ServerState, token: Option<&str>) -> (u16, Vec<String>) {
+    let mut request = warp::test::request()
+        .method("GET")
+        .path("/xrpc/app.bsky.feed.getFeedSkeleton?feed=at://did:plc:publisher/app.bsky.feed.generator/TechThreadsAndMore");
+    if let Some(token) = token {
+        request = request.header("authorization", format!("Bearer {token}"));
+    }
+    let response = request.reply(&routes(state.clone())).await;
+    let body: serde_json::Value =
+        serde_json::from_slice(response.body()).unwrap_or(serde_json::Value::Null);
+    let posts = body["feed"]
+        .as_array()
+        .map(|feed| {
+            feed.iter()
+                .map(|item| item["post"].as_str().unwrap().to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    (response.status().as_u16(), posts)
+}
+
+#[tokio::test]
+async fn viewer_muting_an_author_only_hides_them_for_that_viewer() {
+    let db = feed_with_two_authors().await;
+    let state = server_state(&db, false);
+    let feed_token = service_jwt(
+        &viewer_key(),
+        SERVICE_DID,
+        GET_FEED_SKELETON,
+        a_minute_from_now(),
+    );
+    let interactions_token = service_jwt(
+        &viewer_key(),
+        SERVICE_DID,
+        "app.bsky.feed.sendInteractions",
+        a_minute_from_now(),
+    );
+
+    let response = warp::test::request()
+        .method("POST")
+        .path("/xrpc/app.bsky.feed.sendInteractions")
+        .header("authorization", format!("Bearer {interactions_token}"))
+        .json(&json!({
+            "interactions": [
+                { "item": "at://did:plc:noisy/app.bsky.feed.post/0", "event": REQUEST_LESS },
+                { "item": "at://did:plc:noisy/app.bsky.feed.post/0", "event": REQUEST_LESS }
+            ]
+        }))
+        .reply(&routes(state.clone()))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let (status, posts) = get_feed(&state, Some(&feed_token)).await;
+    assert_eq!(status, 200);
+    assert_eq!(posts, vec!["at://did:plc:quiet/app.bsky.feed.post/1"]);
+
+    //Everyone else still sees the noisy author
+    let (_, posts) = get_feed(&state, None).await;
+    assert_eq!(posts.len(), 2);
+}
+
+#[tokio::test]
+async fn skyfeed_serves_what_signed_out_viewers_get() {
+    let db = feed_with_two_authors().await;
+    let state = server_state(&db, false);
+    let params = |feed: &str| GetFeedSkeletonParams {
+        feed: feed.to_string(),
+        limit: None,
+        cursor: None,
+    };
+
+    let output = feed_skeleton(
+        params("at://did:plc:publisher/app.bsky.feed.generator/TechThreadsAndMore"),
+        None,
+        &state,
+        "skyfeed",
+    )
+    .await
+    .unwrap();
+    let posts: Vec<String> = output.feed.into_iter().map(|item| item.post).collect();
+    assert_eq!(posts, get_feed(&state, None).await.1);
+
+    let unknown = feed_skeleton(
+        params("at://did:plc:publisher/app.bsky.feed.generator/Other"),
+        None,
+        &state,
+        "skyfeed",
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(unknown.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn seen_posts_are_hidden_in_the_next_session() {
+    let db = feed_with_two_authors().await;
+    let state = server_state(&db, true);
+    let token = service_jwt(
+        &viewer_key(),
+        SERVICE_DID,
+        GET_FEED_SKELETON,
+        a_minute_from_now(),
+    );
+
+    let (_, posts) = get_feed(&state, Some(&token)).await;
+    assert_eq!(posts.len(), 2);
+
+    //Served posts are kept for the session they were served in, so age them into a past session
+    db.call(|db| Ok(db.execute("UPDATE served_posts SET served_at = served_at - 60", [])?))
+        .await
+        .unwrap();
+    let (_, posts) = get_feed(&state, Some(&token)).await;
+    assert!(posts.is_empty());
+}
+
+#[tokio::test]
+async fn invalid_token_is_unauthorized() {
+    let db = feed_with_two_authors().await;
+    let state = server_state(&db, false);
+    let (status, _) = get_feed(&state, Some("not.a.jwt")).await;
+    assert_eq!(status, 401);
+}
@@ -0,0 +1,170 @@
+use bsky_thread_and_blog_feed::db::{
+    add_to_author_list, initialize_db, list_authors, remove_from_author_list,
+};
+use bsky_thread_and_blog_feed::ingest::{
+    ingest_post, IncomingPost, IngestOutcome, TRUSTED_AUTHOR_BOOST,
+};
+use bsky_thread_and_blog_feed::metrics::BLOCKED_AUTHOR_POSTS;
+use bsky_thread_and_blog_feed::models::{AuthorList, TextInPost};
+use bsky_thread_and_blog_feed::pipeline::{IngestOptions, IngestPipeline};
+use chrono::Utc;
+use std::sync::Arc;
+use tokio_rusqlite::Connection;
+
+async fn db() -> Connection {
+    let db = Connection::open_in_memory().await.unwrap();
+    initialize_db(&db).await.unwrap();
+    db
+}
+
+fn post(author_did: &str, rkey: &str) -> IncomingPost {
+    IncomingPost {
+        uri: format!("at://{author_did}/app.bsky.feed.post/{rkey}"),
+        author_did: author_did.to_string(),
+        texts: vec![TextInPost::Post("New rust blog post is up".to_string())],
+        link_url: None,
+        timestamp: 1736510400,
+        cid: None,
+        langs: vec![],
+        embed_kind: None,
+        reply: None,
+    }
+}
+
+async fn priorities(db: &Connection) -> Vec<(String, i64)> {
+    db.call(|db| {
+        let mut stmt = db.prepare("SELECT author_did, priority FROM posts ORDER BY author_did")?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    })
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn blocked_authors_are_rejected_until_the_block_expires() {
+    let db = db().await;
+    add_to_author_list(
+        &db,
+        AuthorList::Blocked,
+        "did:plc:spammer".to_string(),
+        Some("Spam".to_string()),
+        None,
+    )
+    .await
+    .unwrap();
+    add_to_author_list(
+        &db,
+        AuthorList::Blocked,
+        "did:plc:forgiven".to_string(),
+        None,
+        Some(Utc::now().timestamp() - 60),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        ingest_post(&db, post("did:plc:spammer", "1"))
+            .await
+            .unwrap(),
+        IngestOutcome::Blocked
+    );
+    assert_eq!(
+        ingest_post(&db, post("did:plc:forgiven", "1"))
+            .await
+            .unwrap(),
+        IngestOutcome::Stored
+    );
+
+    //The firehose goes through the pipeline's copy of the lists
+    let pipeline = IngestPipeline::start(Arc::new(db.clone()), "test", IngestOptions::default());
+    pipeline.refresh_author_lists().await;
+    let blocked_before = BLOCKED_AUTHOR_POSTS.get();
+    pipeline.classify(post("did:plc:spammer", "2")).await;
+    pipeline.classify(post("did:plc:forgiven", "2")).await;
+    pipeline.flush().await;
+    assert!(BLOCKED_AUTHOR_POSTS.get() > blocked_before);
+
+    let authors: Vec<String> = priorities(&db)
+        .await
+        .into_iter()
+        .map(|(author_did, _)| author_did)
+        .collect();
+    assert_eq!(authors, vec!["did:plc:forgiven", "did:plc:forgiven"]);
+}
+
+#[tokio::test]
+async fn trusted_authors_get_a_boost() {
+    let db = db().await;
+    add_to_author_list(
+        &db,
+        AuthorList::Trusted,
+        "did:plc:regular".to_string(),
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+
+    ingest_post(&db, post("did:plc:regular", "1"))
+        .await
+        .unwrap();
+    ingest_post(&db, post("did:plc:stranger", "1"))
+        .await
+        .unwrap();
+
+    let priorities = priorities(&db).await;
+    assert_eq!(priorities[0].0, "did:plc:regular");
+    assert_eq!(priorities[0].1, priorities[1].1 + TRUSTED_AUTHOR_BOOST);
+}
+
+#[tokio::test]
+async fn authors_can_be_taken_off_a_list() {
+    let db = db().await;
+    add_to_author_list(
+        &db,
+        AuthorList::Blocked,
+        "did:plc:spammer".to_string(),
+        Some("Spam".to_string()),
+        None,
+    )
+    .await
+    .unwrap();
+    //Adding again only updates the reason
+    add_to_author_list(
+        &db,
+        AuthorList::Blocked,
+        "did:plc:spammer".to_string(),
+        Some("Still spam".to_string()),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let blocked = list_authors(&db, AuthorList::Blocked).await.unwrap();
+    assert_eq!(blocked.len(), 1);
+    assert_eq!(blocked[0].reason.as_deref(), Some("Still spam"));
+    assert!(list_authors(&db, AuthorList::Trusted)
+        .await
+        .unwrap()
+        .is_empty());
+
+    assert!(
+        remove_from_author_list(&db, AuthorList::Blocked, "did:plc:spammer".to_string())
+            .await
+            .unwrap()
+    );
+    assert!(
+        !remove_from_author_list(&db, AuthorList::Blocked, "did:plc:spammer".to_string())
+            .await
+            .unwrap()
+    );
+    assert_eq!(
+        ingest_post(&db, post("did:plc:spammer", "1"))
+            .await
+            .unwrap(),
+        IngestOutcome::Stored
+    );
+}
@@ -0,0 +1,154 @@
+use bsky_thread_and_blog_feed::appview::AppViewClient;
+use bsky_thread_and_blog_feed::backfill::{backfill_author, BackfillReport};
+use bsky_thread_and_blog_feed::db::initialize_db;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use tokio_rusqlite::Connection;
+use warp::Filter;
+
+const AUTHOR: &str = "did:plc:author";
+
+fn post(rkey: &str, text: &str, created_at: &str, embed: Option<Value>) -> Value {
+    let mut record = json!({
+        "$type": "app.bsky.feed.post",
+        "text": text,
+        "createdAt": created_at
+    });
+    if let Some(embed) = embed {
+        record["embed"] = embed;
+    }
+    json!({
+        "post": {
+            "uri": format!("at://{AUTHOR}/app.bsky.feed.post/{rkey}"),
+            "cid": "bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm",
+            "author": { "did": AUTHOR, "handle": "author.test" },
+            "record": record,
+            "indexedAt": created_at
+        }
+    })
+}
+
+/// Stands in for the AppView, serving the author's feed as two pages
+async fn mock_appview() -> SocketAddr {
+    let mut repost = post(
+        "repost",
+        "Someone else's rust blog",
+        "2025-01-20T12:00:00.000Z",
+        None,
+    );
+    repost["post"]["author"]["did"] = json!("did:plc:someone");
+    repost["reason"] = json!({ "$type": "app.bsky.feed.defs#reasonRepost" });
+
+    let first_page = json!({
+        "cursor": "page2",
+        "feed": [
+            repost,
+            post("1", "New rust blog post is up", "2025-01-10T12:00:00.000Z", None),
+            post("2", "Lunch was good", "2025-01-09T12:00:00.000Z", None),
+        ]
+    });
+    let second_page = json!({
+        "cursor": "page3",
+        "feed": [
+            post(
+                "3",
+                "Wrote this up",
+                "2025-01-05T12:00:00.000Z",
+                Some(json!({
+                    "$type": "app.bsky.embed.external",
+                    "external": {
+                        "uri": "https://www.example.com/embedded-rust/?utm_source=bsky",
+                        "title": "Embedded Rust on the Pico",
+                        "description": "A deep dive"
+                    }
+                })),
+            ),
+            post("4", "Old rust thread 🧵", "2024-06-01T12:00:00.000Z", None),
+        ]
+    });
+
+    let route = warp::path!("xrpc" / "app.bsky.feed.getAuthorFeed")
+        .and(warp::query::<HashMap<String, String>>())
+        .map(move |query: HashMap<String, String>| {
+            assert_eq!(query.get("actor").map(String::as_str), Some(AUTHOR));
+            match query.get("cursor").map(String::as_str) {
+                None => warp::reply::json(&first_page),
+                Some("page2") => warp::reply::json(&second_page),
+                Some(other) => panic!("Backfill went past the date limit to {other}"),
+            }
+        });
+    let (address, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    address
+}
+
+#[tokio::test]
+async fn backfill_stores_matching_posts_until_the_date_limit() {
+    let db = Connection::open_in_memory().await.unwrap();
+    initialize_db(&db).await.unwrap();
+    let address = mock_appview().await;
+    let appview = AppViewClient::new(format!("http://{address}"));
+    let since = chrono::DateTime::parse_from_rfc3339("2025-01-01T00:00:00Z")
+        .unwrap()
+        .timestamp();
+
+    let report = backfill_author(&db, &appview, AUTHOR, since).await.unwrap();
+    assert_eq!(
+        report,
+        BackfillReport {
+            seen: 5,
+            stored: 2,
+            shares: 0,
+            rejected: 1,
+            skipped: 2,
+        }
+    );
+
+    let stored: Vec<(String, i64, Option<String>)> = db
+        .call(|db| {
+            let mut stmt =
+                db.prepare("SELECT uri, timestamp, link_url FROM posts ORDER BY timestamp DESC")?;
+            let rows = stmt
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(rows)
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        stored,
+        vec![
+            (
+                format!("at://{AUTHOR}/app.bsky.feed.post/1"),
+                1736510400,
+                None
+            ),
+            (
+                format!("at://{AUTHOR}/app.bsky.feed.post/3"),
+                1736078400,
+                Some("https://example.com/embedded-rust".to_string())
+            ),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn backfilling_twice_stores_nothing_new() {
+    let db = Connection::open_in_memory().await.unwrap();
+    initialize_db(&db).await.unwrap();
+    let address = mock_appview().await;
+    let appview = AppViewClient::new(format!("http://{address}"));
+
+    backfill_author(&db, &appview, AUTHOR, 1735689600)
+        .await
+        .unwrap();
+    backfill_author(&db, &appview, AUTHOR, 1735689600)
+        .await
+        .unwrap();
+    let count: u64 = db
+        .call(|db| Ok(db.query_row("SELECT COUNT(*) FROM posts", [], |row| row.get(0))?))
+        .await
+        .unwrap();
+    assert_eq!(count, 2);
+}
@@ -0,0 +1,58 @@
+use bsky_thread_and_blog_feed::cursor::CursorTracker;
+use bsky_thread_and_blog_feed::db::{initialize_db, load_cursor};
+use tokio_rusqlite::Connection;
+
+async fn empty_db() -> Connection {
+    let db = Connection::open_in_memory().await.unwrap();
+    initialize_db(&db).await.unwrap();
+    db
+}
+
+#[tokio::test]
+async fn cursor_survives_a_restart() {
+    let db = empty_db().await;
+    let tracker = CursorTracker::new("firehose");
+    tracker.observe(1_700_000_000_000_002);
+    //Handled out of order, the cursor stays on the newest
+    tracker.observe(1_700_000_000_000_001);
+    assert!(tracker.save(&db).await.unwrap());
+
+    let restarted = CursorTracker::new("firehose");
+    assert_eq!(
+        restarted.load(&db).await.unwrap(),
+        Some(1_700_000_000_000_002)
+    );
+    assert_eq!(restarted.latest(), Some(1_700_000_000_000_002));
+}
+
+#[tokio::test]
+async fn only_writes_when_the_cursor_moved() {
+    let db = empty_db().await;
+    let tracker = CursorTracker::new("firehose");
+    assert!(!tracker.save(&db).await.unwrap());
+    assert_eq!(
+        load_cursor(&db, "firehose".to_string()).await.unwrap(),
+        None
+    );
+
+    tracker.observe(10);
+    assert!(tracker.save(&db).await.unwrap());
+    assert!(!tracker.save(&db).await.unwrap());
+    tracker.observe(20);
+    assert!(tracker.save(&db).await.unwrap());
+    assert_eq!(
+        load_cursor(&db, "firehose".to_string()).await.unwrap(),
+        Some(20)
+    );
+}
+
+#[tokio::test]
+async fn cursors_are_kept_per_source() {
+    let db = empty_db().await;
+    let firehose = CursorTracker::new("firehose");
+    firehose.observe(10);
+    firehose.save(&db).await.unwrap();
+
+    let other = CursorTracker::new("jetstream");
+    assert_eq!(other.load(&db).await.unwrap(), None);
+}
@@ -0,0 +1,80 @@
+use bsky_thread_and_blog_feed::auth::StaticDidResolver;
+use bsky_thread_and_blog_feed::db::initialize_db;
+use bsky_thread_and_blog_feed::health::FirehoseHealth;
+use bsky_thread_and_blog_feed::server::{routes, FeedGeneratorIdentity, ServerState};
+use serde_json::json;
+use std::sync::Arc;
+use tokio_rusqlite::Connection;
+
+async fn server_state() -> ServerState {
+    let db = Connection::open_in_memory().await.unwrap();
+    initialize_db(&db).await.unwrap();
+    ServerState {
+        store: Arc::new(db),
+        max_posts_per_author: None,
+        identity: FeedGeneratorIdentity {
+            hostname: "feed.example.com".to_string(),
+            publisher_did: "did:plc:publisher".to_string(),
+            feed_names: vec!["TechThreadsAndMore".to_string(), "RustOnly".to_string()],
+        },
+        did_resolver: Arc::new(StaticDidResolver::default()),
+        hide_seen_posts: false,
+        firehose: Arc::new(FirehoseHealth::default()),
+    }
+}
+
+async fn get_json(path: &str) -> (u16, serde_json::Value) {
+    let response = warp::test::request()
+        .method("GET")
+        .path(path)
+        .reply(&routes(server_state().await))
+        .await;
+    (
+        response.status().as_u16(),
+        serde_json::from_slice(response.body()).unwrap(),
+    )
+}
+
+#[tokio::test]
+async fn did_document_points_at_the_feed_generator() {
+    let (status, body) = get_json("/.well-known/did.json").await;
+    assert_eq!(status, 200);
+    assert_eq!(
+        body,
+        json!({
+            "@context": ["https://www.w3.org/ns/did/v1"],
+            "id": "did:web:feed.example.com",
+            "service": [{
+                "id": "#bsky_fg",
+                "type": "BskyFeedGenerator",
+                "serviceEndpoint": "https://feed.example.com"
+            }]
+        })
+    );
+}
+
+#[tokio::test]
+async fn describe_feed_generator_lists_every_feed() {
+    let (status, body) = get_json("/xrpc/app.bsky.feed.describeFeedGenerator").await;
+    assert_eq!(status, 200);
+    assert_eq!(
+        body,
+        json!({
+            "did": "did:web:feed.example.com",
+            "feeds": [
+                { "uri": "at://did:plc:publisher/app.bsky.feed.generator/TechThreadsAndMore" },
+                { "uri": "at://did:plc:publisher/app.bsky.feed.generator/RustOnly" }
+            ]
+        })
+    );
+}
+
+#[tokio::test]
+async fn unknown_feed_is_rejected() {
+    let (status, body) = get_json(
+        "/xrpc/app.bsky.feed.getFeedSkeleton?feed=at://did:plc:publisher/app.bsky.feed.generator/NotOurs",
+    )
+    .await;
+    assert_eq!(status, 400);
+    assert_eq!(body["error"], "UnknownFeed");
+}
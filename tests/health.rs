@@ -0,0 +1,120 @@
+use bsky_thread_and_blog_feed::auth::StaticDidResolver;
+use bsky_thread_and_blog_feed::db::initialize_db;
+use bsky_thread_and_blog_feed::health::FirehoseHealth;
+use bsky_thread_and_blog_feed::server::{routes, FeedGeneratorIdentity, ServerState};
+use chrono::Utc;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_rusqlite::Connection;
+
+const STALE_AFTER: Duration = Duration::from_secs(60);
+const MAX_LAG: Duration = Duration::from_secs(300);
+
+async fn server_state(firehose: FirehoseHealth) -> ServerState {
+    let db = Connection::open_in_memory().await.unwrap();
+    initialize_db(&db).await.unwrap();
+    ServerState {
+        store: Arc::new(db),
+        max_posts_per_author: None,
+        identity: FeedGeneratorIdentity {
+            hostname: "feed.example.com".to_string(),
+            publisher_did: "did:plc:publisher".to_string(),
+            feed_names: vec!["TechThreadsAndMore".to_string()],
+        },
+        did_resolver: Arc::new(StaticDidResolver::default()),
+        hide_seen_posts: false,
+        firehose: Arc::new(firehose),
+    }
+}
+
+async fn get(state: &ServerState, path: &str) -> (u16, serde_json::Value) {
+    let response = warp::test::request()
+        .method("GET")
+        .path(path)
+        .reply(&routes(state.clone()))
+        .await;
+    (
+        response.status().as_u16(),
+        serde_json::from_slice(response.body()).unwrap(),
+    )
+}
+
+#[tokio::test]
+async fn ready_while_events_keep_coming() {
+    let firehose = FirehoseHealth::new(STALE_AFTER, MAX_LAG);
+    firehose.record_event(Some(Utc::now().timestamp() - 5));
+    let state = server_state(firehose).await;
+
+    let (status, _) = get(&state, "/healthz").await;
+    assert_eq!(status, 200);
+    let (status, body) = get(&state, "/readyz").await;
+    assert_eq!(status, 200);
+    assert_eq!(body["ready"], true);
+}
+
+#[tokio::test]
+async fn not_ready_once_the_firehose_goes_quiet() {
+    let firehose = FirehoseHealth::new(STALE_AFTER, MAX_LAG);
+    firehose.record_event_at(Utc::now().timestamp() - 120, None);
+    let state = server_state(firehose).await;
+
+    let (status, body) = get(&state, "/readyz").await;
+    assert_eq!(status, 503);
+    assert_eq!(body["firehose"]["ok"], false);
+    //Still alive, just not getting anything
+    let (status, _) = get(&state, "/healthz").await;
+    assert_eq!(status, 200);
+}
+
+#[tokio::test]
+async fn not_ready_when_events_fall_behind() {
+    let now = Utc::now().timestamp();
+    let firehose = FirehoseHealth::new(STALE_AFTER, MAX_LAG);
+    firehose.record_event_at(now, Some(now - 10));
+    //The latest event is what counts, not the newest one ever seen
+    firehose.record_event_at(now, Some(now - 600));
+    let state = server_state(firehose).await;
+
+    let (status, body) = get(&state, "/readyz").await;
+    assert_eq!(status, 503);
+    assert_eq!(body["firehose"]["ok"], true);
+    assert_eq!(body["lag"]["ok"], false);
+    assert!(body["lag"]["problem"].as_str().unwrap().contains("behind"));
+
+    state.firehose.record_event_at(now, Some(now));
+    let (status, _) = get(&state, "/readyz").await;
+    assert_eq!(status, 200);
+}
+
+#[tokio::test]
+async fn future_dated_events_do_not_hide_lag() {
+    let now = Utc::now().timestamp();
+    let firehose = FirehoseHealth::new(STALE_AFTER, MAX_LAG);
+    //Counted as when it came in, ten minutes ago
+    firehose.record_event_at(now - 600, Some(now + 86_400));
+    let state = server_state(firehose).await;
+
+    let (status, body) = get(&state, "/readyz").await;
+    assert_eq!(status, 503);
+    assert_eq!(body["lag"]["ok"], false);
+
+    //Nor does one stick around once older events come in after it
+    state.firehose.record_event_at(now, Some(now + 86_400));
+    state.firehose.record_event_at(now, Some(now - 600));
+    let (_, body) = get(&state, "/readyz").await;
+    assert_eq!(body["lag"]["ok"], false);
+}
+
+#[tokio::test]
+async fn not_ready_when_the_db_is_unreachable() {
+    let db = Connection::open_in_memory().await.unwrap();
+    db.clone().close().await.unwrap();
+    let state = ServerState {
+        store: Arc::new(db),
+        ..server_state(FirehoseHealth::default()).await
+    };
+
+    let (status, body) = get(&state, "/readyz").await;
+    assert_eq!(status, 503);
+    assert_eq!(body["db"]["ok"], false);
+}
@@ -0,0 +1,251 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use bsky_thread_and_blog_feed::auth::{PublicKey, StaticDidResolver};
+use bsky_thread_and_blog_feed::db::{
+    get_interaction_counts, initialize_db, load_feed_for_viewer, load_feed_from_db,
+};
+use bsky_thread_and_blog_feed::health::FirehoseHealth;
+use bsky_thread_and_blog_feed::models::{ViewerFilter, REQUEST_LESS, REQUEST_MORE};
+use bsky_thread_and_blog_feed::server::{
+    routes, FeedGeneratorIdentity, ServerState, MAX_INTERACTIONS_PER_REQUEST,
+};
+use chrono::Utc;
+use k256::ecdsa::signature::Signer;
+use k256::ecdsa::{Signature, SigningKey};
+use serde_json::json;
+use std::sync::Arc;
+use tokio_rusqlite::{params, Connection};
+
+const POST_URI: &str = "at://did:plc:author/app.bsky.feed.post/1";
+const VIEWERS: [&str; 3] = ["did:plc:viewer0", "did:plc:viewer1", "did:plc:viewer2"];
+
+async fn feed_with_post() -> Connection {
+    let db = Connection::open_in_memory().await.unwrap();
+    initialize_db(&db).await.unwrap();
+    db.call(|db| {
+        db.execute(
+            "INSERT INTO posts (uri, text, author_did, pinned, deleted, priority, timestamp, feed_context) VALUES (?1, 'rust blog', 'did:plc:author', 0, 0, 20, 1, 'topic:rust|signal:blog')",
+            params![POST_URI],
+        )?;
+        Ok(())
+    })
+    .await
+    .unwrap();
+    db
+}
+
+fn viewer_key(viewer: usize) -> SigningKey {
+    SigningKey::from_slice(&[viewer as u8 + 1; 32]).unwrap()
+}
+
+fn server_state(db: &Connection) -> ServerState {
+    let mut resolver = StaticDidResolver::default();
+    for (viewer, did) in VIEWERS.iter().enumerate() {
+        resolver.keys.insert(
+            did.to_string(),
+            PublicKey::K256(*viewer_key(viewer).verifying_key()),
+        );
+    }
+    ServerState {
+        store: Arc::new(db.clone()),
+        max_posts_per_author: None,
+        identity: FeedGeneratorIdentity {
+            hostname: "feed.example.com".to_string(),
+            publisher_did: "did:plc:publisher".to_string(),
+            feed_names: vec!["TechThreadsAndMore".to_string()],
+        },
+        did_resolver: Arc::new(resolver),
+        hide_seen_posts: false,
+        firehose: Arc::new(FirehoseHealth::default()),
+    }
+}
+
+/// A sendInteractions token for one of [VIEWERS]
+fn token(viewer: usize) -> String {
+    let header = URL_SAFE_NO_PAD.encode(json!({ "alg": "ES256K", "typ": "JWT" }).to_string());
+    let now = Utc::now().timestamp();
+    let claims = URL_SAFE_NO_PAD.encode(
+        json!({
+            "iss": VIEWERS[viewer],
+            "aud": "did:web:feed.example.com",
+            "lxm": "app.bsky.feed.sendInteractions",
+            "iat": now,
+            "exp": now + 60
+        })
+        .to_string(),
+    );
+    let signed_part = format!("{header}.{claims}");
+    let signature: Signature = viewer_key(viewer).sign(signed_part.as_bytes());
+    format!(
+        "{signed_part}.{}",
+        URL_SAFE_NO_PAD.encode(signature.to_bytes())
+    )
+}
+
+/// Sends the events as `viewer`, or signed out when there is none
+async fn send_interactions(db: &Connection, viewer: Option<usize>, events: &[&str]) -> u16 {
+    let interactions: Vec<_> = events
+        .iter()
+        .map(|event| json!({ "item": POST_URI, "event": event, "feedContext": "topic:rust|signal:blog" }))
+        .collect();
+    send_body(
+        db,
+        viewer,
+        &json!({
+            "feed": "at://did:plc:publisher/app.bsky.feed.generator/TechThreadsAndMore",
+            "interactions": interactions
+        }),
+    )
+    .await
+}
+
+async fn send_body(db: &Connection, viewer: Option<usize>, body: &serde_json::Value) -> u16 {
+    let mut request = warp::test::request()
+        .method("POST")
+        .path("/xrpc/app.bsky.feed.sendInteractions")
+        .json(body);
+    if let Some(viewer) = viewer {
+        request = request.header("authorization", format!("Bearer {}", token(viewer)));
+    }
+    request
+        .reply(&routes(server_state(db)))
+        .await
+        .status()
+        .as_u16()
+}
+
+#[tokio::test]
+async fn interactions_are_counted_per_post() {
+    let db = feed_with_post().await;
+    assert_eq!(
+        send_interactions(&db, Some(0), &[REQUEST_MORE, REQUEST_LESS]).await,
+        200
+    );
+    assert_eq!(send_interactions(&db, Some(1), &[REQUEST_LESS]).await, 200);
+
+    let counts = get_interaction_counts(&db, vec![POST_URI.to_string()])
+        .await
+        .unwrap();
+    let counts = counts.get(POST_URI).unwrap();
+    assert_eq!(counts.show_more, 1);
+    assert_eq!(counts.show_less, 2);
+}
+
+#[tokio::test]
+async fn show_less_only_hides_a_post_from_whoever_sent_it() {
+    let db = feed_with_post().await;
+    for viewer in 0..VIEWERS.len() {
+        send_interactions(&db, Some(viewer), &[REQUEST_LESS]).await;
+    }
+    assert_eq!(load_feed_from_db(&db, 10, 0).await.unwrap().len(), 1);
+    let viewer = ViewerFilter {
+        did: VIEWERS[0].to_string(),
+        hide_seen_before: None,
+    };
+    assert!(load_feed_for_viewer(&db, 10, 0, None, Some(viewer))
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn feedback_counts_once_per_viewer_and_needs_a_token() {
+    let db = feed_with_post().await;
+    assert_eq!(send_interactions(&db, None, &[REQUEST_LESS]).await, 401);
+    for _ in 0..3 {
+        assert_eq!(
+            send_interactions(&db, Some(2), &[REQUEST_LESS, REQUEST_LESS]).await,
+            200
+        );
+    }
+
+    let counts = get_interaction_counts(&db, vec![POST_URI.to_string()])
+        .await
+        .unwrap();
+    assert_eq!(counts.get(POST_URI).unwrap().show_less, 1);
+    //One viewer alone cannot take it out of everyone's feed
+    assert_eq!(load_feed_from_db(&db, 10, 0).await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn requests_are_bounded_and_only_posts_in_the_feed_are_kept() {
+    let db = feed_with_post().await;
+    let interactions = |count: usize, item: &str| {
+        json!({
+            "interactions": vec![json!({ "item": item, "event": REQUEST_MORE }); count]
+        })
+    };
+    assert_eq!(
+        send_body(
+            &db,
+            Some(0),
+            &interactions(MAX_INTERACTIONS_PER_REQUEST + 1, POST_URI)
+        )
+        .await,
+        400
+    );
+    let padding = "x".repeat(100 * 1024);
+    assert_eq!(
+        send_body(
+            &db,
+            Some(0),
+            &json!({ "interactions": [], "padding": padding })
+        )
+        .await,
+        413
+    );
+
+    let unknown = "at://did:plc:author/app.bsky.feed.post/unknown";
+    assert_eq!(
+        send_body(&db, Some(0), &interactions(1, unknown)).await,
+        200
+    );
+    let stored: u64 = db
+        .call(|db| {
+            Ok(db.query_row(
+                "SELECT (SELECT COUNT(*) FROM user_interactions) + (SELECT COUNT(*) FROM post_interactions)",
+                [],
+                |row| row.get(0),
+            )?)
+        })
+        .await
+        .unwrap();
+    assert_eq!(stored, 0);
+}
+
+#[tokio::test]
+async fn skeleton_items_carry_their_feed_context() {
+    let db = feed_with_post().await;
+    let response = warp::test::request()
+        .method("GET")
+        .path("/xrpc/app.bsky.feed.getFeedSkeleton?feed=at://did:plc:publisher/app.bsky.feed.generator/TechThreadsAndMore&limit=10")
+        .reply(&routes(server_state(&db)))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(
+        body,
+        json!({
+            "feed": [{ "post": POST_URI, "feedContext": "topic:rust|signal:blog" }]
+        })
+    );
+}
+
+#[tokio::test]
+async fn feedback_is_traced_back_to_the_feed_context() {
+    let db = feed_with_post().await;
+    send_interactions(&db, Some(0), &[REQUEST_LESS]).await;
+
+    let count: u64 = db
+        .call(|db| {
+            Ok(db.query_row(
+                "SELECT count FROM interaction_contexts WHERE feed_context = 'topic:rust|signal:blog' AND event = ?1",
+                params![REQUEST_LESS],
+                |row| row.get(0),
+            )?)
+        })
+        .await
+        .unwrap();
+    assert_eq!(count, 1);
+}
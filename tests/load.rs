@@ -0,0 +1,288 @@
+use bsky_thread_and_blog_feed::ingest::IncomingPost;
+use bsky_thread_and_blog_feed::models::{PostScoring, TextInPost};
+use bsky_thread_and_blog_feed::pipeline::FeedWrite;
+use bsky_thread_and_blog_feed::retention::RetentionPolicy;
+use bsky_thread_and_blog_feed::skeleton::load_skeleton_page;
+use bsky_thread_and_blog_feed::sqlite::{SqliteOptions, SqliteStore};
+use bsky_thread_and_blog_feed::store::FeedStore;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+//** NOTICE **
+// The feed and admin binaries share feed.db. The first test has two stores on one file play
+// them, the second runs the real binaries and is ignored by default since it builds both and
+// takes a while: cargo test --test load -- --ignored
+//
+
+/// 2025-01-10 12:00:00
+const NOW: i64 = 1736510400;
+const BATCHES: usize = 200;
+const POSTS_PER_BATCH: usize = 25;
+
+/// A db file nothing else uses, without what an earlier run left behind
+fn db_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("feed-load-{name}-{}.db", std::process::id()));
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = fs::remove_file(format!("{}{suffix}", path.display()));
+    }
+    path
+}
+
+fn post(number: usize) -> IncomingPost {
+    let author = format!("did:plc:author{}", number % 50);
+    IncomingPost {
+        uri: format!("at://{author}/app.bsky.feed.post/{number}"),
+        author_did: author,
+        texts: vec![TextInPost::Post(format!(
+            "New rust blog post {number} is up"
+        ))],
+        link_url: None,
+        timestamp: NOW + number as i64,
+        cid: None,
+        langs: vec!["en".to_string()],
+        embed_kind: None,
+        reply: None,
+    }
+}
+
+fn scoring() -> PostScoring {
+    PostScoring {
+        pinned: false,
+        deleted: false,
+        priority: 10,
+        feed_context: "rust".to_string(),
+        matched_rules: vec!["rust".to_string()],
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn the_admin_tool_works_while_the_feed_writes() {
+    let path = db_path("stores");
+    let options = SqliteOptions::default();
+    //Both binaries starting at once also migrate at once
+    let (feed, admin) = tokio::join!(
+        SqliteStore::open(&path, &options),
+        SqliteStore::open(&path, &options)
+    );
+    let (feed, admin) = (Arc::new(feed.unwrap()), Arc::new(admin.unwrap()));
+    let ingesting = Arc::new(AtomicBool::new(true));
+
+    let firehose = tokio::spawn({
+        let feed = feed.clone();
+        let ingesting = ingesting.clone();
+        async move {
+            let policy = RetentionPolicy {
+                max_posts: Some(2_000),
+                ..RetentionPolicy::default()
+            };
+            for batch in 0..BATCHES {
+                let writes = (0..POSTS_PER_BATCH)
+                    .map(|number| FeedWrite::Post {
+                        post: post(batch * POSTS_PER_BATCH + number),
+                        scoring: scoring(),
+                    })
+                    .collect();
+                feed.write_batch(writes).await.unwrap();
+                if batch % 20 == 19 {
+                    feed.apply_retention(&policy, NOW).await.unwrap();
+                }
+            }
+            ingesting.store(false, Ordering::Relaxed);
+        }
+    });
+
+    let serve_feed = tokio::spawn({
+        let feed = feed.clone();
+        let ingesting = ingesting.clone();
+        async move {
+            let mut pages = 0;
+            while ingesting.load(Ordering::Relaxed) {
+                load_skeleton_page(feed.as_ref(), Some(30), None, Some(3))
+                    .await
+                    .unwrap();
+                pages += 1;
+            }
+            pages
+        }
+    });
+
+    let admin_tool = tokio::spawn({
+        let admin = admin.clone();
+        let ingesting = ingesting.clone();
+        async move {
+            let mut removed = 0;
+            while ingesting.load(Ordering::Relaxed) {
+                let page = admin.load_feed(30, 0, None, None).await.unwrap();
+                admin.search_posts("rust".to_string(), 30, 0).await.unwrap();
+                if let Some(newest) = page.first() {
+                    admin
+                        .moderate_post(newest.uri.clone(), "load test".to_string())
+                        .await
+                        .unwrap();
+                    removed += 1;
+                }
+                admin.list_tombstones().await.unwrap();
+            }
+            removed
+        }
+    });
+
+    firehose.await.unwrap();
+    assert!(serve_feed.await.unwrap() > 0);
+    let removed = admin_tool.await.unwrap();
+    assert!(removed > 0);
+    assert_eq!(admin.list_tombstones().await.unwrap().len(), removed);
+    //Both see the same db once the writes are done
+    assert_eq!(
+        feed.posts_count().await.unwrap(),
+        admin.posts_count().await.unwrap()
+    );
+
+    let db = tokio_rusqlite::Connection::open(&path).await.unwrap();
+    let journal_mode: String = db
+        .call(|db| Ok(db.query_row("PRAGMA journal_mode", [], |row| row.get(0))?))
+        .await
+        .unwrap();
+    assert_eq!(journal_mode, "wal");
+}
+
+#[tokio::test]
+async fn readers_see_writes_once_committed() {
+    let path = db_path("readers");
+    let store = SqliteStore::open(
+        &path,
+        &SqliteOptions {
+            read_connections: 2,
+            busy_timeout: Duration::from_millis(100),
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(store.posts_count().await.unwrap(), 0);
+    store.store_post(post(1), scoring()).await.unwrap();
+    //Every reader in turn
+    for _ in 0..2 {
+        assert_eq!(store.posts_count().await.unwrap(), 1);
+    }
+}
+
+/// Jetstream events of accepted posts, for the feed binary to replay
+fn recording(path: &PathBuf, posts: usize) {
+    let events: Vec<String> = (0..posts)
+        .map(|number| {
+            serde_json::json!({
+                "did": format!("did:plc:author{}", number % 50),
+                "time_us": (NOW + number as i64) * 1_000_000,
+                "kind": "commit",
+                "commit": {
+                    "rev": format!("rev{number}"),
+                    "operation": "create",
+                    "collection": "app.bsky.feed.post",
+                    "rkey": number.to_string(),
+                    "record": {
+                        "$type": "app.bsky.feed.post",
+                        "text": format!("New rust blog post {number} is up"),
+                        "createdAt": chrono::DateTime::from_timestamp(NOW + number as i64, 0)
+                            .unwrap()
+                            .to_rfc3339(),
+                        "langs": ["en"],
+                    },
+                    "cid": format!("bafyrei{number}"),
+                },
+            })
+            .to_string()
+        })
+        .collect();
+    fs::write(path, events.join("\n")).unwrap();
+}
+
+#[tokio::test]
+#[ignore = "runs both binaries, see the notice at the top"]
+async fn both_binaries_run_against_one_db() {
+    let path = db_path("binaries");
+    let dir = std::env::temp_dir();
+    let events = dir.join(format!("feed-load-events-{}.jsonl", std::process::id()));
+    recording(&events, 20_000);
+    //Empty so a feed.toml in the working directory is not picked up
+    let config = dir.join(format!("feed-load-{}.toml", std::process::id()));
+    fs::write(&config, "").unwrap();
+    let xrpc_port = 40_000 + (std::process::id() % 10_000) as u16;
+
+    let mut feed = tokio::process::Command::new(env!("CARGO_BIN_EXE_feed"))
+        .arg("--config")
+        .arg(&config)
+        .env("DATABASE_PATH", &path)
+        .env("EVENT_SOURCE", "replay")
+        .env("REPLAY_FILE", &events)
+        .env("PUBLISHER_DID", "did:plc:publisher")
+        .env("FEED_GENERATOR_HOSTNAME", "localhost")
+        .env("BIND_ADDRESS", "127.0.0.1")
+        .env("XRPC_PORT", xrpc_port.to_string())
+        .env("PORT", (xrpc_port + 1).to_string())
+        .kill_on_drop(true)
+        .spawn()
+        .unwrap();
+
+    let base = format!("http://127.0.0.1:{xrpc_port}");
+    let client = reqwest::Client::new();
+    let mut started = false;
+    for _ in 0..100 {
+        if client.get(format!("{base}/healthz")).send().await.is_ok() {
+            started = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(started, "the feed never started listening");
+
+    let admin = |args: &[&str]| {
+        let mut command = tokio::process::Command::new(env!("CARGO_BIN_EXE_admin"));
+        command
+            .arg("--config")
+            .arg(&config)
+            .env("DATABASE_PATH", &path)
+            .args(args);
+        command
+    };
+    let skeleton = format!(
+        "{base}/xrpc/app.bsky.feed.getFeedSkeleton?feed=at://did:plc:publisher/app.bsky.feed.generator/TechThreadsAndMore&limit=30"
+    );
+    for round in 0..50 {
+        let response = client.get(&skeleton).send().await.unwrap();
+        assert_eq!(response.status().as_u16(), 200, "round {round}");
+        let page: serde_json::Value =
+            serde_json::from_str(&response.text().await.unwrap()).unwrap();
+
+        let mut runs = vec![vec!["removed".to_string()], vec!["authors".to_string()]];
+        if let Some(uri) = page["feed"][0]["post"].as_str() {
+            runs.push(vec!["remove".to_string(), uri.to_string()]);
+            runs.push(vec!["restore".to_string(), uri.to_string()]);
+        }
+        for args in runs {
+            let args: Vec<&str> = args.iter().map(String::as_str).collect();
+            let output = admin(&args).output().await.unwrap();
+            assert!(
+                output.status.success(),
+                "admin {args:?} failed in round {round}: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+    }
+
+    let metrics = client
+        .get(format!("{base}/metrics"))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(
+        !metrics.contains("feed_db_errors_total{"),
+        "the feed had failed db calls"
+    );
+    feed.kill().await.unwrap();
+}
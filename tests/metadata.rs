@@ -0,0 +1,68 @@
+use bsky_thread_and_blog_feed::db::initialize_db;
+use bsky_thread_and_blog_feed::events::FeedEvent;
+use bsky_thread_and_blog_feed::ingest::{ingest_post, IngestOutcome, ReplyUris};
+use tokio_rusqlite::Connection;
+
+const REPLY: &str = r#"{"did":"did:plc:alice","time_us":1736510400000000,"kind":"commit","commit":{"rev":"3lfhb2aaaa22a","operation":"create","collection":"app.bsky.feed.post","rkey":"2","record":{"$type":"app.bsky.feed.post","text":"Part two of the rust thread","createdAt":"2025-01-10T12:00:00.000Z","langs":["en","de"],"reply":{"parent":{"uri":"at://did:plc:alice/app.bsky.feed.post/1","cid":"bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm"},"root":{"uri":"at://did:plc:alice/app.bsky.feed.post/0","cid":"bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpl"}},"embed":{"$type":"app.bsky.embed.external","external":{"uri":"https://www.example.com/rust-thread/?utm_source=bsky","title":"Rust thread","description":""}}},"cid":"bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpn"}}"#;
+
+#[tokio::test]
+async fn stored_posts_keep_their_metadata() {
+    let db = Connection::open_in_memory().await.unwrap();
+    initialize_db(&db).await.unwrap();
+    let Some(FeedEvent::Post {
+        uri,
+        author_did,
+        cid,
+        record,
+        ..
+    }) = FeedEvent::from_jetstream_json(REPLY).unwrap()
+    else {
+        panic!("Expected a post");
+    };
+    let post = record.into_incoming_post(uri, author_did, cid, 1736510400);
+    assert_eq!(
+        post.reply,
+        Some(ReplyUris {
+            parent: "at://did:plc:alice/app.bsky.feed.post/1".to_string(),
+            root: "at://did:plc:alice/app.bsky.feed.post/0".to_string(),
+        })
+    );
+    assert_eq!(ingest_post(&db, post).await.unwrap(), IngestOutcome::Stored);
+
+    let row: [Option<String>; 8] = db
+        .call(|db| {
+            Ok(db.query_row(
+                "SELECT author_did, cid, langs, embed_kind, link_url, reply_parent, reply_root, matched_rules
+                 FROM posts WHERE uri = 'at://did:plc:alice/app.bsky.feed.post/2'",
+                [],
+                |row| {
+                    Ok([
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                        row.get(5)?,
+                        row.get(6)?,
+                        row.get(7)?,
+                    ])
+                },
+            )?)
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        row.map(|column| column.unwrap_or_default()),
+        [
+            "did:plc:alice",
+            "bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpn",
+            r#"["en","de"]"#,
+            "external",
+            "https://example.com/rust-thread",
+            "at://did:plc:alice/app.bsky.feed.post/1",
+            "at://did:plc:alice/app.bsky.feed.post/0",
+            r#"["programmer_jargon","blog_jargon"]"#,
+        ]
+        .map(String::from)
+    );
+}
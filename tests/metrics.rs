@@ -0,0 +1,125 @@
+use bsky_thread_and_blog_feed::auth::StaticDidResolver;
+use bsky_thread_and_blog_feed::db::initialize_db;
+use bsky_thread_and_blog_feed::health::FirehoseHealth;
+use bsky_thread_and_blog_feed::metrics::{
+    count_rule_matches, CLASSIFIER_RULE_MATCHES, DB_ERRORS, FIREHOSE_LAG_SECONDS,
+};
+use bsky_thread_and_blog_feed::server::{routes, FeedGeneratorIdentity, ServerState};
+use chrono::Utc;
+use std::sync::Arc;
+use tokio_rusqlite::{params, Connection};
+
+async fn feed_with_posts(count: usize) -> Connection {
+    let db = Connection::open_in_memory().await.unwrap();
+    initialize_db(&db).await.unwrap();
+    db.call(move |db| {
+        for i in 0..count {
+            db.execute(
+                "INSERT INTO posts (uri, text, pinned, deleted, priority, timestamp) VALUES (?1, 'rust blog', 0, 0, 40, ?2)",
+                params![format!("at://did:plc:author/app.bsky.feed.post/{i}"), i as i64],
+            )?;
+        }
+        Ok(())
+    })
+    .await
+    .unwrap();
+    db
+}
+
+fn server_state(db: &Connection) -> ServerState {
+    ServerState {
+        store: Arc::new(db.clone()),
+        max_posts_per_author: None,
+        identity: FeedGeneratorIdentity {
+            hostname: "feed.example.com".to_string(),
+            publisher_did: "did:plc:publisher".to_string(),
+            feed_names: vec!["TechThreadsAndMore".to_string()],
+        },
+        did_resolver: Arc::new(StaticDidResolver::default()),
+        hide_seen_posts: false,
+        firehose: Arc::new(FirehoseHealth::default()),
+    }
+}
+
+#[tokio::test]
+async fn metrics_endpoint_reports_serving_and_row_count() {
+    let db = feed_with_posts(3).await;
+    let routes = routes(server_state(&db));
+    warp::test::request()
+        .method("GET")
+        .path("/xrpc/app.bsky.feed.getFeedSkeleton?feed=at://did:plc:publisher/app.bsky.feed.generator/TechThreadsAndMore&limit=2")
+        .reply(&routes)
+        .await;
+
+    let response = warp::test::request()
+        .method("GET")
+        .path("/metrics")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body = String::from_utf8(response.body().to_vec()).unwrap();
+    assert!(body.contains("feed_posts_rows 3"));
+    assert!(body.contains("feed_serve_feed_seconds_count{server=\"xrpc\"}"));
+    assert!(body.contains("feed_page_size_bucket{server=\"xrpc\",le=\"1\"}"));
+    assert!(body.contains("feed_db_query_seconds_count{query=\"load_feed\"}"));
+}
+
+#[tokio::test]
+async fn db_failures_are_xrpc_errors_and_counted() {
+    let db = feed_with_posts(1).await;
+    db.clone().close().await.unwrap();
+    let routes = routes(server_state(&db));
+    let failures = |operation| DB_ERRORS.with_label_values(&[operation]).get();
+    let (load_feed_failures, count_failures) = (failures("load_feed"), failures("posts_count"));
+
+    let response = warp::test::request()
+        .method("GET")
+        .path("/xrpc/app.bsky.feed.getFeedSkeleton?feed=at://did:plc:publisher/app.bsky.feed.generator/TechThreadsAndMore")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status().as_u16(), 500);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["error"], "InternalServerError");
+    assert!(failures("load_feed") > load_feed_failures);
+
+    //The rest of the metrics are still served
+    let response = warp::test::request()
+        .method("GET")
+        .path("/metrics")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(failures("posts_count") > count_failures);
+}
+
+#[tokio::test]
+async fn firehose_lag_keeps_growing_without_a_readiness_check() {
+    let db = feed_with_posts(0).await;
+    let mut state = server_state(&db);
+    let firehose = FirehoseHealth::default();
+    //Nothing has come in for the last ten minutes
+    let ten_minutes_ago = Utc::now().timestamp() - 600;
+    firehose.record_event_at(ten_minutes_ago, Some(ten_minutes_ago - 5));
+    assert_eq!(FIREHOSE_LAG_SECONDS.get(), 5);
+    state.firehose = Arc::new(firehose);
+
+    let response = warp::test::request()
+        .method("GET")
+        .path("/metrics")
+        .reply(&routes(state))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(FIREHOSE_LAG_SECONDS.get() >= 605);
+}
+
+#[test]
+fn a_rule_is_counted_once_per_post() {
+    let matches = || {
+        CLASSIFIER_RULE_MATCHES
+            .with_label_values(&["test_rule", "accepted"])
+            .get()
+    };
+    let before = matches();
+    count_rule_matches(&["test_rule", "test_rule", "other_rule"], true);
+    assert_eq!(matches(), before + 1);
+}
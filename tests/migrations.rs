@@ -0,0 +1,146 @@
+use bsky_thread_and_blog_feed::db::migrate_db;
+use bsky_thread_and_blog_feed::migrations::{schema_version, MigrationError};
+use tokio_rusqlite::Connection;
+
+/// The schema feed.db had before any columns or tables were added to it
+const BASELINE_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS posts (
+        uri TEXT PRIMARY KEY,
+        text TEXT,
+        pinned INTEGER,
+        deleted INTEGER,
+        priority INTEGER,
+        timestamp INTEGER
+    );
+    CREATE TABLE IF NOT EXISTS likes (
+        post_uri TEXT,
+        like_uri TEXT,
+        PRIMARY KEY (post_uri, like_uri),
+        FOREIGN KEY (post_uri) REFERENCES posts(uri) ON DELETE CASCADE
+    );
+    CREATE INDEX IF NOT EXISTS idx_likes_post_uri ON likes(post_uri);
+";
+
+async fn user_version(db: &Connection) -> i64 {
+    db.call(|db| Ok(db.query_row("PRAGMA user_version", [], |row| row.get(0))?))
+        .await
+        .unwrap()
+}
+
+async fn columns(db: &Connection, table: &'static str) -> Vec<String> {
+    db.call(move |db| {
+        let mut stmt = db.prepare(&format!("PRAGMA table_info({table})"))?;
+        let rows = stmt
+            .query_map([], |row| row.get(1))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    })
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn new_database_is_migrated_to_the_latest_version() {
+    let db = Connection::open_in_memory().await.unwrap();
+    assert_eq!(migrate_db(&db).await.unwrap(), schema_version());
+    assert_eq!(user_version(&db).await, schema_version());
+    for table in [
+        "posts",
+        "likes",
+        "post_interactions",
+        "author_interactions",
+        "interaction_contexts",
+        "user_interactions",
+        "served_posts",
+        "firehose_cursor",
+        "moderation_tombstones",
+        "blocked_authors",
+        "trusted_authors",
+        "hidden_accounts",
+        "posts_fts",
+        "archived_posts",
+        "link_shares",
+    ] {
+        assert!(!columns(&db, table).await.is_empty(), "{table} is missing");
+    }
+}
+
+#[tokio::test]
+async fn baseline_database_keeps_its_posts() {
+    let db = Connection::open_in_memory().await.unwrap();
+    db.call(|db| {
+        db.execute_batch(BASELINE_SCHEMA)?;
+        db.execute(
+            "INSERT INTO posts (uri, text, pinned, deleted, priority, timestamp)
+             VALUES ('at://did:plc:alice/app.bsky.feed.post/1', 'New rust blog post is up', 0, 0, 1, 1736510400)",
+            [],
+        )?;
+        Ok(())
+    })
+    .await
+    .unwrap();
+    assert_eq!(user_version(&db).await, 0);
+
+    migrate_db(&db).await.unwrap();
+
+    let posts = columns(&db, "posts").await;
+    for column in [
+        "author_did",
+        "link_url",
+        "shares",
+        "feed_context",
+        "cid",
+        "langs",
+        "embed_kind",
+        "reply_parent",
+        "reply_root",
+        "matched_rules",
+    ] {
+        assert!(posts.iter().any(|name| name == column), "{column} missing");
+    }
+    let (text, shares): (String, i64) = db
+        .call(|db| {
+            Ok(db.query_row(
+                "SELECT text, shares FROM posts WHERE uri = 'at://did:plc:alice/app.bsky.feed.post/1'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )?)
+        })
+        .await
+        .unwrap();
+    assert_eq!(text, "New rust blog post is up");
+    assert_eq!(shares, 0);
+    assert_eq!(user_version(&db).await, schema_version());
+}
+
+#[tokio::test]
+async fn database_from_before_versioning_is_picked_up_where_it_is() {
+    let db = Connection::open_in_memory().await.unwrap();
+    migrate_db(&db).await.unwrap();
+    //Every table is already there, but nothing recorded a version yet
+    db.call(|db| Ok(db.pragma_update(None, "user_version", 0)?))
+        .await
+        .unwrap();
+
+    assert_eq!(migrate_db(&db).await.unwrap(), schema_version());
+    //And running again does nothing
+    assert_eq!(migrate_db(&db).await.unwrap(), schema_version());
+}
+
+#[tokio::test]
+async fn newer_database_is_refused() {
+    let db = Connection::open_in_memory().await.unwrap();
+    let newer = schema_version() + 1;
+    db.call(move |db| Ok(db.pragma_update(None, "user_version", newer)?))
+        .await
+        .unwrap();
+
+    match migrate_db(&db).await {
+        Err(MigrationError::NewerThanBinary { found, supported }) => {
+            assert_eq!(found, newer);
+            assert_eq!(supported, schema_version());
+        }
+        other => panic!("Expected the newer database to be refused, got {other:?}"),
+    }
+    assert!(columns(&db, "posts").await.is_empty());
+}
@@ -0,0 +1,101 @@
+use bsky_thread_and_blog_feed::db::{
+    delete_post, initialize_db, list_tombstones, load_feed_from_db, moderate_post, restore_post,
+};
+use bsky_thread_and_blog_feed::ingest::{ingest_post, IncomingPost, IngestOutcome};
+use bsky_thread_and_blog_feed::models::{PostScoring, TextInPost};
+use bsky_thread_and_blog_feed::pipeline::{FeedWrite, IngestOptions, IngestPipeline};
+use std::sync::Arc;
+use tokio_rusqlite::Connection;
+
+const URI: &str = "at://did:plc:alice/app.bsky.feed.post/1";
+
+async fn db() -> Connection {
+    let db = Connection::open_in_memory().await.unwrap();
+    initialize_db(&db).await.unwrap();
+    db
+}
+
+fn post() -> IncomingPost {
+    IncomingPost {
+        uri: URI.to_string(),
+        author_did: "did:plc:alice".to_string(),
+        texts: vec![TextInPost::Post("New rust blog post is up".to_string())],
+        link_url: None,
+        timestamp: 1736510400,
+        cid: None,
+        langs: vec![],
+        embed_kind: None,
+        reply: None,
+    }
+}
+
+async fn feed_uris(db: &Connection) -> Vec<String> {
+    load_feed_from_db(db, 10, 0)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|post| post.uri)
+        .collect()
+}
+
+#[tokio::test]
+async fn removed_posts_stay_out_of_the_feed() {
+    let db = db().await;
+    assert_eq!(
+        ingest_post(&db, post()).await.unwrap(),
+        IngestOutcome::Stored
+    );
+    moderate_post(&db, URI.to_string(), "Off topic".to_string())
+        .await
+        .unwrap();
+    assert!(feed_uris(&db).await.is_empty());
+
+    //Seen again on the firehose, and again after cleanup dropped the row
+    assert_eq!(
+        ingest_post(&db, post()).await.unwrap(),
+        IngestOutcome::Moderated
+    );
+    delete_post(&db, URI.to_string()).await.unwrap();
+    assert_eq!(
+        ingest_post(&db, post()).await.unwrap(),
+        IngestOutcome::Moderated
+    );
+
+    //And liked by the publisher, which replaces whatever is stored
+    let pipeline = IngestPipeline::start(Arc::new(db.clone()), "test", IngestOptions::default());
+    pipeline
+        .write(FeedWrite::CuratedPost {
+            post: post(),
+            scoring: PostScoring {
+                pinned: false,
+                deleted: false,
+                priority: 1,
+                feed_context: "curated|rust|blog".to_string(),
+                matched_rules: vec![],
+            },
+        })
+        .await;
+    pipeline.flush().await;
+    assert!(feed_uris(&db).await.is_empty());
+
+    let tombstones = list_tombstones(&db).await.unwrap();
+    assert_eq!(tombstones.len(), 1);
+    assert_eq!(tombstones[0].uri, URI);
+    assert_eq!(tombstones[0].reason, "Off topic");
+    assert!(tombstones[0].removed_at > 0);
+}
+
+#[tokio::test]
+async fn restored_posts_come_back() {
+    let db = db().await;
+    ingest_post(&db, post()).await.unwrap();
+    moderate_post(&db, URI.to_string(), "Off topic".to_string())
+        .await
+        .unwrap();
+
+    assert!(restore_post(&db, URI.to_string()).await.unwrap());
+    assert_eq!(feed_uris(&db).await, vec![URI.to_string()]);
+    assert!(list_tombstones(&db).await.unwrap().is_empty());
+    //Nothing to restore a second time
+    assert!(!restore_post(&db, URI.to_string()).await.unwrap());
+}
@@ -0,0 +1,202 @@
+use bsky_thread_and_blog_feed::db::initialize_db;
+use bsky_thread_and_blog_feed::ingest::IncomingPost;
+use bsky_thread_and_blog_feed::metrics::{INGEST_QUEUE_FULL, INGEST_WRITES_SKIPPED};
+use bsky_thread_and_blog_feed::models::{PostScoring, TextInPost};
+use bsky_thread_and_blog_feed::pipeline::{
+    FeedWrite, IngestOptions, IngestPipeline, QueueFullPolicy,
+};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_rusqlite::Connection;
+
+async fn db() -> Connection {
+    let db = Connection::open_in_memory().await.unwrap();
+    initialize_db(&db).await.unwrap();
+    db
+}
+
+fn post(rkey: &str, text: &str) -> IncomingPost {
+    IncomingPost {
+        uri: format!("at://did:plc:alice/app.bsky.feed.post/{rkey}"),
+        author_did: "did:plc:alice".to_string(),
+        texts: vec![TextInPost::Post(text.to_string())],
+        link_url: None,
+        timestamp: 1736510400,
+        cid: None,
+        langs: vec![],
+        embed_kind: None,
+        reply: None,
+    }
+}
+
+/// A post that skips the classifier
+fn scored(rkey: &str) -> FeedWrite {
+    FeedWrite::Post {
+        post: post(rkey, "New rust blog post is up"),
+        scoring: PostScoring {
+            pinned: false,
+            deleted: false,
+            priority: 1,
+            feed_context: "rust|blog".to_string(),
+            matched_rules: vec![],
+        },
+    }
+}
+
+async fn count(db: &Connection, table: &'static str) -> u64 {
+    db.call(move |db| {
+        Ok(
+            db.query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+                row.get(0)
+            })?,
+        )
+    })
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn events_are_written_in_the_order_they_arrived() {
+    let db = db().await;
+    let pipeline = IngestPipeline::start(
+        Arc::new(db.clone()),
+        "test",
+        IngestOptions {
+            batch_size: 2,
+            ..IngestOptions::default()
+        },
+    );
+
+    pipeline
+        .classify(post("1", "New rust blog post is up"))
+        .await;
+    pipeline.classify(post("2", "Lunch was good")).await;
+    pipeline
+        .classify(post("3", "Another rust blog post is up"))
+        .await;
+    pipeline
+        .write(FeedWrite::Like {
+            like_uri: "at://did:plc:bob/app.bsky.feed.like/1".to_string(),
+            liked_post_uri: "at://did:plc:alice/app.bsky.feed.post/1".to_string(),
+        })
+        .await;
+    pipeline
+        .write(FeedWrite::DeletePost {
+            uri: "at://did:plc:alice/app.bsky.feed.post/3".to_string(),
+        })
+        .await;
+    pipeline.flush().await;
+
+    //The like only sticks if the post was written first, and the delete has to come after its post
+    let uris: Vec<String> = db
+        .call(|db| {
+            let mut stmt = db.prepare("SELECT uri FROM posts ORDER BY uri")?;
+            let rows = stmt
+                .query_map([], |row| row.get(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(rows)
+        })
+        .await
+        .unwrap();
+    assert_eq!(uris, vec!["at://did:plc:alice/app.bsky.feed.post/1"]);
+    assert_eq!(count(&db, "likes").await, 1);
+}
+
+#[tokio::test]
+async fn a_full_queue_drops_events_when_told_to() {
+    let db = db().await;
+    let pipeline = IngestPipeline::start(
+        Arc::new(db.clone()),
+        "test",
+        IngestOptions {
+            classifier_workers: 1,
+            queue_capacity: 1,
+            batch_size: 1,
+            when_full: QueueFullPolicy::Drop,
+        },
+    );
+    let dropped_before = INGEST_QUEUE_FULL.with_label_values(&["dropped"]).get();
+
+    //Nothing yields between these on the test's single thread, so the writer never gets to run
+    pipeline.write(scored("1")).await;
+    pipeline.write(scored("2")).await;
+    pipeline.write(scored("3")).await;
+    pipeline.flush().await;
+
+    assert_eq!(count(&db, "posts").await, 1);
+    assert!(INGEST_QUEUE_FULL.with_label_values(&["dropped"]).get() >= dropped_before + 2);
+}
+
+#[tokio::test]
+async fn a_full_queue_holds_up_the_source_by_default() {
+    let db = db().await;
+    let pipeline = IngestPipeline::start(
+        Arc::new(db.clone()),
+        "test",
+        IngestOptions {
+            queue_capacity: 1,
+            batch_size: 1,
+            ..IngestOptions::default()
+        },
+    );
+    let waited_before = INGEST_QUEUE_FULL.with_label_values(&["waited"]).get();
+
+    for rkey in ["1", "2", "3"] {
+        pipeline.write(scored(rkey)).await;
+    }
+    pipeline.flush().await;
+
+    assert_eq!(count(&db, "posts").await, 3);
+    assert!(INGEST_QUEUE_FULL.with_label_values(&["waited"]).get() > waited_before);
+}
+
+#[tokio::test]
+async fn the_cursor_only_moves_past_stored_events() {
+    let db = db().await;
+    let mut pipeline =
+        IngestPipeline::start(Arc::new(db.clone()), "test", IngestOptions::default());
+    //Every write fails until the table is back
+    db.call(|db| Ok(db.execute_batch("ALTER TABLE posts RENAME TO posts_gone")?))
+        .await
+        .unwrap();
+
+    pipeline.event_seen(Some(1_736_510_400_000_000));
+    pipeline.write(scored("1")).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(pipeline.cursor().latest(), None);
+
+    db.call(|db| Ok(db.execute_batch("ALTER TABLE posts_gone RENAME TO posts")?))
+        .await
+        .unwrap();
+    pipeline.flush().await;
+    assert_eq!(count(&db, "posts").await, 1);
+    assert_eq!(pipeline.cursor().latest(), Some(1_736_510_400_000_000));
+}
+
+#[tokio::test]
+async fn a_write_the_db_always_rejects_is_skipped() {
+    let db = db().await;
+    db.call(|db| {
+        Ok(db.execute_batch(
+            "CREATE TRIGGER reject_post BEFORE INSERT ON posts
+             WHEN NEW.uri = 'at://did:plc:alice/app.bsky.feed.post/2'
+             BEGIN SELECT RAISE(ABORT, 'rejected'); END;",
+        )?)
+    })
+    .await
+    .unwrap();
+    let mut pipeline =
+        IngestPipeline::start(Arc::new(db.clone()), "test", IngestOptions::default());
+    let skipped_before = INGEST_WRITES_SKIPPED.get();
+
+    for (rkey, time_us) in [("1", 1), ("2", 2), ("3", 3)] {
+        pipeline.event_seen(Some(time_us));
+        pipeline.write(scored(rkey)).await;
+    }
+    pipeline.flush().await;
+
+    //The writes on either side of it still go in and the cursor moves past all three
+    assert_eq!(count(&db, "posts").await, 2);
+    assert!(INGEST_WRITES_SKIPPED.get() > skipped_before);
+    assert_eq!(pipeline.cursor().latest(), Some(3));
+}
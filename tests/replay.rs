@@ -0,0 +1,239 @@
+use bsky_thread_and_blog_feed::appview::AppViewClient;
+use bsky_thread_and_blog_feed::db::initialize_db;
+use bsky_thread_and_blog_feed::events::FeedEvent;
+use bsky_thread_and_blog_feed::handler::FeedIngestHandler;
+use bsky_thread_and_blog_feed::health::FirehoseHealth;
+use bsky_thread_and_blog_feed::pipeline::{IngestOptions, IngestPipeline};
+use bsky_thread_and_blog_feed::replay::{replay_events, replay_file, ReplayReport, ReplaySpeed};
+use serde_json::json;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_rusqlite::Connection;
+use warp::Filter;
+
+const PUBLISHER: &str = "did:plc:publisher";
+const RECORDING: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/fixtures/jetstream.jsonl"
+);
+
+/// Stands in for the AppView, answering getPosts for the post the publisher likes in the recording
+async fn mock_appview() -> SocketAddr {
+    let route = warp::path!("xrpc" / "app.bsky.feed.getPosts")
+        .and(warp::query::<HashMap<String, String>>())
+        .map(|query: HashMap<String, String>| {
+            let uri = query.get("uris").cloned().unwrap_or_default();
+            warp::reply::json(&json!({
+                "posts": [{
+                    "uri": uri,
+                    "cid": "bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpp",
+                    "author": { "did": "did:plc:carol", "handle": "carol.test" },
+                    "record": {
+                        "$type": "app.bsky.feed.post",
+                        "text": "New rust blog post is up",
+                        "createdAt": "2025-01-10T11:00:00.000Z"
+                    },
+                    "indexedAt": "2025-01-10T11:00:00.000Z"
+                }]
+            }))
+        });
+    let (address, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    address
+}
+
+async fn handler() -> (FeedIngestHandler, Connection) {
+    let db = Connection::open_in_memory().await.unwrap();
+    initialize_db(&db).await.unwrap();
+    let address = mock_appview().await;
+    let handler = FeedIngestHandler {
+        pipeline: IngestPipeline::start(Arc::new(db.clone()), "replay", IngestOptions::default()),
+        appview: Arc::new(AppViewClient::new(format!("http://{address}"))),
+        feed_author_did: PUBLISHER.to_string(),
+        firehose: Arc::new(FirehoseHealth::default()),
+    };
+    (handler, db)
+}
+
+#[tokio::test]
+async fn replaying_a_recording_stores_what_the_firehose_would() {
+    let (mut handler, db) = handler().await;
+
+    let report = replay_file(Path::new(RECORDING), &mut handler, ReplaySpeed::Instant)
+        .await
+        .unwrap();
+    assert_eq!(
+        report,
+        ReplayReport {
+            events: 7,
+            ignored: 2,
+            malformed: 1,
+        }
+    );
+
+    let posts: Vec<(String, Option<String>)> = db
+        .call(|db| {
+            let mut stmt = db.prepare("SELECT uri, feed_context FROM posts ORDER BY uri")?;
+            let rows = stmt
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(rows)
+        })
+        .await
+        .unwrap();
+    let uris: Vec<&str> = posts.iter().map(|(uri, _)| uri.as_str()).collect();
+    //The lunch post is not for the feed and the third post was deleted again
+    assert_eq!(
+        uris,
+        vec![
+            "at://did:plc:alice/app.bsky.feed.post/1",
+            "at://did:plc:carol/app.bsky.feed.post/9",
+        ]
+    );
+    assert!(posts[1]
+        .1
+        .as_deref()
+        .is_some_and(|context| context.starts_with("curated|")));
+
+    //Bob's like was taken back, the publisher's like is on the curated post
+    let likes: Vec<String> = db
+        .call(|db| {
+            let mut stmt = db.prepare("SELECT like_uri FROM likes")?;
+            let rows = stmt
+                .query_map([], |row| row.get(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(rows)
+        })
+        .await
+        .unwrap();
+    assert_eq!(likes, vec!["at://did:plc:publisher/app.bsky.feed.like/l2"]);
+
+    assert_eq!(handler.pipeline.cursor().latest(), Some(1736510408000000));
+}
+
+#[tokio::test]
+async fn replaying_twice_stores_nothing_new() {
+    let (mut handler, db) = handler().await;
+    replay_file(Path::new(RECORDING), &mut handler, ReplaySpeed::Instant)
+        .await
+        .unwrap();
+    replay_file(Path::new(RECORDING), &mut handler, ReplaySpeed::Instant)
+        .await
+        .unwrap();
+
+    let counts: (u64, u64) = db
+        .call(|db| {
+            Ok((
+                db.query_row("SELECT COUNT(*) FROM posts", [], |row| row.get(0))?,
+                db.query_row("SELECT COUNT(*) FROM likes", [], |row| row.get(0))?,
+            ))
+        })
+        .await
+        .unwrap();
+    assert_eq!(counts, (2, 1));
+}
+
+/// uri, cid, langs and matched_rules
+type MetadataRow = (String, Option<String>, Option<String>, Option<String>);
+
+#[tokio::test]
+async fn curated_posts_keep_their_metadata() {
+    let (mut handler, db) = handler().await;
+    replay_file(Path::new(RECORDING), &mut handler, ReplaySpeed::Instant)
+        .await
+        .unwrap();
+
+    let rows: Vec<MetadataRow> = db
+        .call(|db| {
+            let mut stmt =
+                db.prepare("SELECT uri, cid, langs, matched_rules FROM posts ORDER BY uri")?;
+            let rows = stmt
+                .query_map([], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(rows)
+        })
+        .await
+        .unwrap();
+    //The cid comes from the commit for firehose posts and from getPosts for curated ones
+    assert_eq!(
+        rows,
+        vec![
+            (
+                "at://did:plc:alice/app.bsky.feed.post/1".to_string(),
+                Some("bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm".to_string()),
+                Some(r#"["en"]"#.to_string()),
+                Some(r#"["programmer_jargon","blog_jargon"]"#.to_string()),
+            ),
+            (
+                "at://did:plc:carol/app.bsky.feed.post/9".to_string(),
+                Some("bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpp".to_string()),
+                Some("[]".to_string()),
+                Some(r#"["programmer_jargon","blog_jargon"]"#.to_string()),
+            ),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn posts_dated_in_the_future_are_stored_at_when_they_came_in() {
+    let (mut handler, db) = handler().await;
+    //Made a year after the relay saw it
+    let events = r#"{"did":"did:plc:alice","time_us":1736510400000000,"kind":"commit","commit":{"rev":"a","operation":"create","collection":"app.bsky.feed.post","rkey":"1","record":{"text":"New rust blog post is up","createdAt":"2026-01-10T12:00:00.000Z"},"cid":"bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm"}}
+{"did":"did:plc:bob","time_us":1736510401000000,"kind":"commit","commit":{"rev":"b","operation":"create","collection":"app.bsky.feed.post","rkey":"1","record":{"text":"My embedded rust thread","createdAt":"2025-01-09T12:00:00.000Z"},"cid":"bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpn"}}
+"#;
+    replay_events(events.as_bytes(), &mut handler, ReplaySpeed::Instant)
+        .await
+        .unwrap();
+
+    let timestamps: Vec<(String, i64)> = db
+        .call(|db| {
+            let mut stmt =
+                db.prepare("SELECT author_did, timestamp FROM posts ORDER BY author_did")?;
+            let rows = stmt
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(rows)
+        })
+        .await
+        .unwrap();
+    //Backdated posts keep their createdAt
+    assert_eq!(
+        timestamps,
+        vec![
+            ("did:plc:alice".to_string(), 1736510400),
+            ("did:plc:bob".to_string(), 1736424000),
+        ]
+    );
+}
+
+#[test]
+fn replay_speed_scales_the_recorded_gaps() {
+    assert_eq!(ReplaySpeed::Instant.delay(0, 5_000_000), Duration::ZERO);
+    assert_eq!(
+        ReplaySpeed::Scaled(1.0).delay(1_000_000, 3_000_000),
+        Duration::from_secs(2)
+    );
+    assert_eq!(
+        ReplaySpeed::Scaled(10.0).delay(1_000_000, 3_000_000),
+        Duration::from_millis(200)
+    );
+    //Out of order events do not wait
+    assert_eq!(
+        ReplaySpeed::Scaled(1.0).delay(3_000_000, 1_000_000),
+        Duration::ZERO
+    );
+}
+
+#[test]
+fn jetstream_events_outside_the_feed_are_ignored() {
+    let identity = r#"{"did":"did:plc:alice","time_us":1,"kind":"identity","identity":{"did":"did:plc:alice"}}"#;
+    assert!(FeedEvent::from_jetstream_json(identity).unwrap().is_none());
+    let update = r#"{"did":"did:plc:alice","time_us":1,"kind":"commit","commit":{"rev":"a","operation":"update","collection":"app.bsky.feed.post","rkey":"1","record":{"text":"edited"}}}"#;
+    assert!(FeedEvent::from_jetstream_json(update).unwrap().is_none());
+    assert!(FeedEvent::from_jetstream_json("{").is_err());
+}
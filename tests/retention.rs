@@ -0,0 +1,256 @@
+use bsky_thread_and_blog_feed::db::initialize_db;
+use bsky_thread_and_blog_feed::ingest::{ingest_post, IncomingPost};
+use bsky_thread_and_blog_feed::models::TextInPost;
+use bsky_thread_and_blog_feed::retention::{
+    apply_retention, ArchiveTarget, RetentionLimits, RetentionPolicy,
+};
+use flate2::read::MultiGzDecoder;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::time::Duration;
+use tokio_rusqlite::Connection;
+
+/// 2025-01-10 12:00:00
+const NOW: i64 = 1736510400;
+const DAY: i64 = 24 * 60 * 60;
+
+async fn db() -> Connection {
+    let db = Connection::open_in_memory().await.unwrap();
+    initialize_db(&db).await.unwrap();
+    db
+}
+
+/// A post for the feed made `days_ago`, liked once
+async fn store_post(db: &Connection, rkey: &str, days_ago: i64) {
+    let uri = format!("at://did:plc:alice/app.bsky.feed.post/{rkey}");
+    let like_uri = format!("at://did:plc:bob/app.bsky.feed.like/{rkey}");
+    ingest_post(
+        db,
+        IncomingPost {
+            uri: uri.clone(),
+            author_did: "did:plc:alice".to_string(),
+            texts: vec![TextInPost::Post("New rust blog post is up".to_string())],
+            link_url: None,
+            timestamp: NOW - days_ago * DAY,
+            cid: None,
+            langs: vec!["en".to_string()],
+            embed_kind: None,
+            reply: None,
+        },
+    )
+    .await
+    .unwrap();
+    db.call(move |db| {
+        db.execute(
+            "INSERT INTO likes (post_uri, like_uri) VALUES (?1, ?2)",
+            [&uri, &like_uri],
+        )?;
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+async fn pin(db: &Connection, rkey: &str) {
+    let uri = format!("at://did:plc:alice/app.bsky.feed.post/{rkey}");
+    db.call(move |db| Ok(db.execute("UPDATE posts SET pinned = 1 WHERE uri = ?1", [uri])?))
+        .await
+        .unwrap();
+}
+
+async fn rkeys(db: &Connection, query: &'static str) -> Vec<String> {
+    db.call(move |db| {
+        let mut stmt = db.prepare(query)?;
+        let uris = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(uris)
+    })
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|uri| uri.rsplit('/').next().unwrap().to_string())
+    .collect()
+}
+
+async fn count(db: &Connection, query: &'static str) -> u64 {
+    db.call(move |db| Ok(db.query_row(query, [], |row| row.get(0))?))
+        .await
+        .unwrap()
+}
+
+fn policy(max_posts: Option<usize>, max_age_days: Option<u64>) -> RetentionPolicy {
+    RetentionPolicy {
+        max_posts,
+        max_age: max_age_days.map(|days| Duration::from_secs(days * DAY as u64)),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn oldest_posts_past_the_limits_are_archived_and_pinned_ones_stay() {
+    let db = db().await;
+    for (rkey, days_ago) in [("1", 5), ("2", 4), ("3", 3), ("4", 2), ("5", 1)] {
+        store_post(&db, rkey, days_ago).await;
+    }
+    pin(&db, "1").await;
+
+    let archived = apply_retention(&db, &policy(Some(3), None), NOW)
+        .await
+        .unwrap();
+    assert_eq!(archived, 1);
+    assert_eq!(
+        rkeys(&db, "SELECT uri FROM posts ORDER BY uri").await,
+        vec!["1", "3", "4", "5"]
+    );
+
+    //Already within the count, only the age limit takes more
+    let archived = apply_retention(&db, &policy(Some(3), Some(2)), NOW)
+        .await
+        .unwrap();
+    assert_eq!(archived, 1);
+    assert_eq!(
+        rkeys(&db, "SELECT uri FROM posts ORDER BY uri").await,
+        vec!["1", "4", "5"]
+    );
+    assert_eq!(
+        rkeys(&db, "SELECT uri FROM archived_posts ORDER BY uri").await,
+        vec!["2", "3"]
+    );
+    //Their likes go with them
+    assert_eq!(count(&db, "SELECT COUNT(*) FROM likes").await, 3);
+
+    let archived = apply_retention(&db, &policy(None, None), NOW)
+        .await
+        .unwrap();
+    assert_eq!(archived, 0);
+}
+
+#[tokio::test]
+async fn posts_are_kept_while_any_feed_still_wants_them() {
+    let db = db().await;
+    for (rkey, days_ago) in [("1", 10), ("2", 6), ("3", 4), ("4", 2), ("5", 1)] {
+        store_post(&db, rkey, days_ago).await;
+    }
+    let feeds = |recent: RetentionLimits, archive: RetentionLimits| RetentionPolicy {
+        feeds: BTreeMap::from([
+            ("recent".to_string(), recent),
+            ("archive".to_string(), archive),
+        ]),
+        ..policy(Some(1), Some(1))
+    };
+
+    //recent only wants the newest two, archive everything from the last week
+    let policy = feeds(
+        RetentionLimits {
+            max_posts: Some(2),
+            max_age: None,
+        },
+        RetentionLimits {
+            max_posts: None,
+            max_age: Some(Duration::from_secs(7 * DAY as u64)),
+        },
+    );
+    assert_eq!(apply_retention(&db, &policy, NOW).await.unwrap(), 1);
+    assert_eq!(
+        rkeys(&db, "SELECT uri FROM posts ORDER BY uri").await,
+        vec!["2", "3", "4", "5"]
+    );
+
+    //Once archive also keeps only three it is what keeps the most
+    let policy = feeds(
+        RetentionLimits {
+            max_posts: Some(2),
+            max_age: None,
+        },
+        RetentionLimits {
+            max_posts: Some(3),
+            max_age: Some(Duration::from_secs(7 * DAY as u64)),
+        },
+    );
+    assert_eq!(apply_retention(&db, &policy, NOW).await.unwrap(), 1);
+    assert_eq!(
+        rkeys(&db, "SELECT uri FROM posts ORDER BY uri").await,
+        vec!["3", "4", "5"]
+    );
+
+    //A feed without limits keeps everything
+    let policy = feeds(
+        RetentionLimits {
+            max_posts: Some(1),
+            max_age: None,
+        },
+        RetentionLimits {
+            max_posts: None,
+            max_age: None,
+        },
+    );
+    assert_eq!(apply_retention(&db, &policy, NOW).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn archived_posts_keep_the_whole_row() {
+    let db = db().await;
+    store_post(&db, "1", 10).await;
+    apply_retention(&db, &policy(None, Some(7)), NOW)
+        .await
+        .unwrap();
+
+    let (post, archived_at): (String, i64) = db
+        .call(|db| {
+            Ok(
+                db.query_row("SELECT post, archived_at FROM archived_posts", [], |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })?,
+            )
+        })
+        .await
+        .unwrap();
+    assert_eq!(archived_at, NOW);
+    let post: serde_json::Value = serde_json::from_str(&post).unwrap();
+    assert_eq!(post["uri"], "at://did:plc:alice/app.bsky.feed.post/1");
+    assert_eq!(post["text"], "New rust blog post is up");
+    assert_eq!(post["timestamp"], NOW - 10 * DAY);
+    assert_eq!(post["langs"], serde_json::json!(["en"]));
+}
+
+#[tokio::test]
+async fn posts_can_be_archived_to_gzipped_jsonl() {
+    let db = db().await;
+    let dir = std::env::temp_dir().join(format!("feed-archive-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let policy = RetentionPolicy {
+        archive: ArchiveTarget::Jsonl(dir.clone()),
+        ..policy(Some(1), None)
+    };
+
+    store_post(&db, "1", 3).await;
+    store_post(&db, "2", 2).await;
+    store_post(&db, "3", 1).await;
+    assert_eq!(apply_retention(&db, &policy, NOW).await.unwrap(), 2);
+    //A second run the same day adds to the same file
+    store_post(&db, "4", 0).await;
+    assert_eq!(apply_retention(&db, &policy, NOW).await.unwrap(), 1);
+
+    let mut jsonl = String::new();
+    MultiGzDecoder::new(std::fs::File::open(dir.join("posts-2025-01-10.jsonl.gz")).unwrap())
+        .read_to_string(&mut jsonl)
+        .unwrap();
+    let archived: Vec<String> = jsonl
+        .lines()
+        .map(|line| {
+            let post: serde_json::Value = serde_json::from_str(line).unwrap();
+            post["uri"]
+                .as_str()
+                .unwrap()
+                .rsplit('/')
+                .next()
+                .unwrap()
+                .to_string()
+        })
+        .collect();
+    assert_eq!(archived, vec!["1", "2", "3"]);
+    assert_eq!(rkeys(&db, "SELECT uri FROM posts").await, vec!["4"]);
+    assert_eq!(count(&db, "SELECT COUNT(*) FROM archived_posts").await, 0);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,157 @@
+use bsky_thread_and_blog_feed::db::{delete_post, initialize_db, search_posts};
+use bsky_thread_and_blog_feed::ingest::{ingest_post, IncomingPost};
+use bsky_thread_and_blog_feed::models::{PostScoring, TextInPost};
+use bsky_thread_and_blog_feed::pipeline::{FeedWrite, IngestOptions, IngestPipeline};
+use std::sync::Arc;
+use tokio_rusqlite::Connection;
+
+async fn db() -> Connection {
+    let db = Connection::open_in_memory().await.unwrap();
+    initialize_db(&db).await.unwrap();
+    db
+}
+
+fn post(rkey: &str, texts: Vec<TextInPost>) -> IncomingPost {
+    IncomingPost {
+        uri: format!("at://did:plc:alice/app.bsky.feed.post/{rkey}"),
+        author_did: "did:plc:alice".to_string(),
+        texts,
+        link_url: None,
+        timestamp: 1736510400,
+        cid: None,
+        langs: vec![],
+        embed_kind: None,
+        reply: None,
+    }
+}
+
+async fn search(db: &Connection, query: &str) -> Vec<String> {
+    search_posts(db, query.to_string(), 10, 0)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|result| result.uri)
+        .collect()
+}
+
+async fn stored_posts(db: &Connection) {
+    ingest_post(
+        db,
+        post(
+            "1",
+            vec![TextInPost::Post("New rust blog post is up".to_string())],
+        ),
+    )
+    .await
+    .unwrap();
+    ingest_post(
+        db,
+        post(
+            "2",
+            vec![
+                TextInPost::Post("Wrote this up".to_string()),
+                TextInPost::External("Embedded Rust on the Pico".to_string()),
+                TextInPost::External("A deep dive".to_string()),
+            ],
+        ),
+    )
+    .await
+    .unwrap();
+    ingest_post(
+        db,
+        post(
+            "3",
+            vec![
+                TextInPost::Post("My soldering project thread".to_string()),
+                TextInPost::Picture("Wiring diagram of the ESP32 board".to_string()),
+            ],
+        ),
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn text_alt_text_and_link_text_are_searched() {
+    let db = db().await;
+    stored_posts(&db).await;
+
+    assert_eq!(
+        search(&db, "pico").await,
+        vec!["at://did:plc:alice/app.bsky.feed.post/2"]
+    );
+    assert_eq!(
+        search(&db, "wiring diagram").await,
+        vec!["at://did:plc:alice/app.bsky.feed.post/3"]
+    );
+    //Stemmed, so threads finds thread
+    assert_eq!(
+        search(&db, "threads").await,
+        vec!["at://did:plc:alice/app.bsky.feed.post/3"]
+    );
+    assert_eq!(search(&db, "rust").await.len(), 2);
+
+    let results = search_posts(&db, "pico".to_string(), 10, 0).await.unwrap();
+    assert_eq!(results[0].author_did.as_deref(), Some("did:plc:alice"));
+    assert!(
+        results[0].snippet.contains("[Pico]"),
+        "{}",
+        results[0].snippet
+    );
+}
+
+#[tokio::test]
+async fn search_input_is_taken_as_plain_words() {
+    let db = db().await;
+    stored_posts(&db).await;
+
+    assert!(search(&db, "").await.is_empty());
+    assert_eq!(search(&db, "\"rust").await.len(), 2);
+    assert!(search(&db, "rust AND (").await.is_empty());
+    assert!(search(&db, "NEAR(rust pico)").await.is_empty());
+}
+
+#[tokio::test]
+async fn index_follows_replaced_and_deleted_posts() {
+    let db = db().await;
+    stored_posts(&db).await;
+
+    //The publisher liked post 1 after it was edited elsewhere, the curated copy replaces it
+    let pipeline = IngestPipeline::start(Arc::new(db.clone()), "test", IngestOptions::default());
+    pipeline
+        .write(FeedWrite::CuratedPost {
+            post: post("1", vec![TextInPost::Post("A kernel tutorial".to_string())]),
+            scoring: PostScoring {
+                pinned: false,
+                deleted: false,
+                priority: 1,
+                feed_context: "curated|kernel|tutorial".to_string(),
+                matched_rules: vec![],
+            },
+        })
+        .await;
+    pipeline.flush().await;
+    assert_eq!(
+        search(&db, "kernel").await,
+        vec!["at://did:plc:alice/app.bsky.feed.post/1"]
+    );
+    assert_eq!(
+        search(&db, "rust").await,
+        vec!["at://did:plc:alice/app.bsky.feed.post/2"]
+    );
+
+    delete_post(&db, "at://did:plc:alice/app.bsky.feed.post/2".to_string())
+        .await
+        .unwrap();
+    assert!(search(&db, "pico").await.is_empty());
+    let integrity: Result<(), tokio_rusqlite::Error> = db
+        .call(|db| {
+            db.execute(
+                "INSERT INTO posts_fts (posts_fts, rank) VALUES ('integrity-check', 1)",
+                [],
+            )?;
+            Ok(())
+        })
+        .await;
+    assert!(integrity.is_ok(), "{integrity:?}");
+}
@@ -0,0 +1,273 @@
+use bsky_thread_and_blog_feed::db::{add_share_to_existing_link, initialize_db};
+use bsky_thread_and_blog_feed::skeleton::{
+    load_skeleton_page, SkeletonPage, DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE,
+};
+use bsky_thread_and_blog_feed::store::FeedError;
+use tokio_rusqlite::{params, Connection};
+
+async fn feed_with_posts(count: i64) -> Connection {
+    let db = Connection::open_in_memory().await.unwrap();
+    initialize_db(&db).await.unwrap();
+    for i in 0..count {
+        insert_post(&db, &format!("did:plc:author{i}"), i).await;
+    }
+    db
+}
+
+async fn insert_post(db: &Connection, author_did: &str, timestamp: i64) {
+    let author_did = author_did.to_string();
+    db.call(move |db| {
+        db.execute(
+            "INSERT INTO posts (uri, text, author_did, pinned, deleted, priority, timestamp) VALUES (?1, ?2, ?3, 0, 0, 40, ?4)",
+            params![
+                format!("at://{author_did}/app.bsky.feed.post/{timestamp}"),
+                "rust blog",
+                &author_did,
+                timestamp
+            ],
+        )?;
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn no_limit_uses_the_default_page_size() {
+    let db = feed_with_posts(120).await;
+    let page = load_skeleton_page(&db, None, None, None).await.unwrap();
+    assert_eq!(page.posts.len() as u64, DEFAULT_PAGE_SIZE);
+    assert!(page.cursor.is_some());
+}
+
+#[tokio::test]
+async fn limit_is_clamped_to_the_lexicon_range() {
+    let db = feed_with_posts(150).await;
+
+    let page = load_skeleton_page(&db, Some(0), None, None).await.unwrap();
+    assert_eq!(page.posts.len(), 1);
+    assert!(page.cursor.is_some());
+
+    let page = load_skeleton_page(&db, Some(u8::MAX), None, None)
+        .await
+        .unwrap();
+    assert_eq!(page.posts.len() as u64, MAX_PAGE_SIZE);
+    assert!(page.cursor.is_some());
+}
+
+#[tokio::test]
+async fn last_page_has_no_cursor() {
+    let db = feed_with_posts(30).await;
+
+    let page = load_skeleton_page(&db, Some(20), None, None).await.unwrap();
+    assert_eq!(page.posts.len(), 20);
+    assert!(page.cursor.is_some());
+
+    let page = load_skeleton_page(&db, Some(20), page.cursor.as_deref(), None)
+        .await
+        .unwrap();
+    assert_eq!(page.posts.len(), 10);
+    assert_eq!(page.cursor, None);
+}
+
+#[tokio::test]
+async fn empty_final_page_returns_no_cursor() {
+    let db = feed_with_posts(20).await;
+    let page = load_skeleton_page(&db, Some(20), None, None).await.unwrap();
+    assert_eq!(page.posts.len(), 20);
+    assert_eq!(page.cursor, None);
+
+    //The rest of the feed went away between two pages
+    let page = load_skeleton_page(&db, Some(10), None, None).await.unwrap();
+    db.call(|db| Ok(db.execute("UPDATE posts SET deleted = 1 WHERE timestamp < 10", [])?))
+        .await
+        .unwrap();
+    let page = load_skeleton_page(&db, Some(10), page.cursor.as_deref(), None)
+        .await
+        .unwrap();
+    assert!(page.posts.is_empty());
+    assert_eq!(page.cursor, None);
+
+    let empty_db = feed_with_posts(0).await;
+    let page = load_skeleton_page(&empty_db, None, None, None)
+        .await
+        .unwrap();
+    assert!(page.posts.is_empty());
+    assert_eq!(page.cursor, None);
+}
+
+#[tokio::test]
+async fn unknown_cursor_returns_an_empty_page() {
+    let db = feed_with_posts(20).await;
+    for cursor in [
+        "not-a-cursor",
+        "1",
+        "x|at://did:plc:a/app.bsky.feed.post/1||",
+    ] {
+        let page = load_skeleton_page(&db, None, Some(cursor), None)
+            .await
+            .unwrap();
+        assert!(page.posts.is_empty());
+        assert_eq!(page.cursor, None);
+    }
+}
+
+#[tokio::test]
+async fn author_cap_moves_overflow_to_later_pages() {
+    let db = Connection::open_in_memory().await.unwrap();
+    initialize_db(&db).await.unwrap();
+    //Newest first: 6 posts from a prolific author then 4 from others
+    for i in 0..4 {
+        insert_post(&db, &format!("did:plc:other{i}"), i).await;
+    }
+    for i in 4..10 {
+        insert_post(&db, "did:plc:prolific", i).await;
+    }
+
+    let mut served = vec![];
+    let mut cursor: Option<String> = None;
+    loop {
+        let page = load_skeleton_page(&db, Some(4), cursor.as_deref(), Some(2))
+            .await
+            .unwrap();
+        let prolific_on_page = page
+            .posts
+            .iter()
+            .filter(|post| post.author_did.as_deref() == Some("did:plc:prolific"))
+            .count();
+        assert!(prolific_on_page <= 2);
+        served.extend(page.posts.into_iter().map(|post| post.uri));
+        cursor = page.cursor;
+        if cursor.is_none() {
+            break;
+        }
+    }
+
+    //Nothing is dropped or served twice
+    assert_eq!(served.len(), 10);
+    served.sort();
+    served.dedup();
+    assert_eq!(served.len(), 10);
+}
+
+#[tokio::test]
+async fn the_next_page_stays_put_when_posts_arrive_or_the_limit_changes() {
+    let db = Connection::open_in_memory().await.unwrap();
+    initialize_db(&db).await.unwrap();
+    //Newest first: 6 posts from a prolific author then 6 from others
+    for i in 0..6 {
+        insert_post(&db, &format!("did:plc:other{i}"), i).await;
+    }
+    for i in 6..12 {
+        insert_post(&db, "did:plc:prolific", i).await;
+    }
+    let timestamps = |page: &SkeletonPage| -> Vec<String> {
+        page.posts
+            .iter()
+            .map(|post| post.uri.rsplit('/').next().unwrap().to_string())
+            .collect()
+    };
+
+    let first = load_skeleton_page(&db, Some(4), None, Some(2))
+        .await
+        .unwrap();
+    assert_eq!(timestamps(&first), vec!["11", "10", "5", "4"]);
+
+    //Newer posts than anything served do not push the rest of the feed down
+    for i in 20..25 {
+        insert_post(&db, &format!("did:plc:late{i}"), i).await;
+    }
+    let second = load_skeleton_page(&db, Some(6), first.cursor.as_deref(), Some(2))
+        .await
+        .unwrap();
+    assert_eq!(timestamps(&second), vec!["9", "8", "3", "2", "1", "0"]);
+
+    let third = load_skeleton_page(&db, Some(6), second.cursor.as_deref(), Some(2))
+        .await
+        .unwrap();
+    assert_eq!(timestamps(&third), vec!["7", "6"]);
+    assert_eq!(third.cursor, None);
+}
+
+#[tokio::test]
+async fn shares_of_the_same_link_collapse_into_one_post() {
+    let db = Connection::open_in_memory().await.unwrap();
+    initialize_db(&db).await.unwrap();
+    let link = "https://example.com/blog/rust".to_string();
+    db.call({
+        let link = link.clone();
+        move |db| {
+            db.execute(
+                "INSERT INTO posts (uri, text, author_did, pinned, deleted, priority, timestamp, link_url) VALUES ('at://did:plc:a/app.bsky.feed.post/1', 'rust blog', 'did:plc:a', 0, 0, 40, 1, ?1)",
+                params![&link],
+            )?;
+            db.execute(
+                "INSERT INTO posts (uri, text, author_did, pinned, deleted, priority, timestamp, link_url) VALUES ('at://did:plc:b/app.bsky.feed.post/2', 'rust blog', 'did:plc:b', 0, 0, 70, 2, ?1)",
+                params![&link],
+            )?;
+            Ok(())
+        }
+    })
+    .await
+    .unwrap();
+
+    let page = load_skeleton_page(&db, None, None, None).await.unwrap();
+    assert_eq!(page.posts.len(), 1);
+    assert_eq!(page.posts[0].uri, "at://did:plc:b/app.bsky.feed.post/2");
+
+    //The same post delivered again, by a replay or a reconnect, is still one share
+    for _ in 0..2 {
+        let shared = add_share_to_existing_link(
+            &db,
+            link.clone(),
+            "at://did:plc:c/app.bsky.feed.post/3".to_string(),
+        )
+        .await
+        .unwrap();
+        assert!(shared);
+    }
+    let shares: i64 = db
+        .call(|db| {
+            Ok(db.query_row(
+                "SELECT shares FROM posts WHERE uri = 'at://did:plc:b/app.bsky.feed.post/2'",
+                [],
+                |row| row.get(0),
+            )?)
+        })
+        .await
+        .unwrap();
+    assert_eq!(shares, 1);
+
+    let not_shared = add_share_to_existing_link(
+        &db,
+        "https://example.com/another".to_string(),
+        "at://did:plc:c/app.bsky.feed.post/4".to_string(),
+    )
+    .await
+    .unwrap();
+    assert!(!not_shared);
+}
+
+#[tokio::test]
+async fn a_malformed_row_is_an_error_not_a_panic() {
+    let db = feed_with_posts(2).await;
+    db.call(|db| {
+        db.execute(
+            "UPDATE posts SET text = X'00' WHERE uri = 'at://did:plc:author1/app.bsky.feed.post/1'",
+            [],
+        )?;
+        Ok(())
+    })
+    .await
+    .unwrap();
+    assert!(matches!(
+        load_skeleton_page(&db, None, None, None).await,
+        Err(FeedError::Sqlite(_))
+    ));
+
+    db.clone().close().await.unwrap();
+    assert!(matches!(
+        load_skeleton_page(&db, None, None, None).await,
+        Err(FeedError::Closed)
+    ));
+}
@@ -0,0 +1,124 @@
+use bsky_thread_and_blog_feed::appview::AppViewClient;
+use bsky_thread_and_blog_feed::db::initialize_db;
+use bsky_thread_and_blog_feed::events::FeedEvent;
+use bsky_thread_and_blog_feed::handler::FeedIngestHandler;
+use bsky_thread_and_blog_feed::health::FirehoseHealth;
+use bsky_thread_and_blog_feed::pipeline::{IngestOptions, IngestPipeline};
+use bsky_thread_and_blog_feed::source::{ChannelSource, EventSource, JetstreamSource};
+use futures_util::SinkExt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_rusqlite::Connection;
+use warp::ws::Message;
+use warp::Filter;
+
+const RECORDING: &str = include_str!("fixtures/jetstream.jsonl");
+
+async fn handler(source: &'static str) -> (FeedIngestHandler, Connection) {
+    let db = Connection::open_in_memory().await.unwrap();
+    initialize_db(&db).await.unwrap();
+    let handler = FeedIngestHandler {
+        pipeline: IngestPipeline::start(Arc::new(db.clone()), source, IngestOptions::default()),
+        //Nothing in these tests is liked by the publisher, so this is never called
+        appview: Arc::new(AppViewClient::new("http://127.0.0.1:9")),
+        feed_author_did: "did:plc:publisher".to_string(),
+        firehose: Arc::new(FirehoseHealth::default()),
+    };
+    (handler, db)
+}
+
+async fn stored_uris(db: &Connection) -> Vec<String> {
+    db.call(|db| {
+        let mut stmt = db.prepare("SELECT uri FROM posts ORDER BY uri")?;
+        let rows = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    })
+    .await
+    .unwrap()
+}
+
+/// Stands in for Jetstream, sends the first two recorded events on every connection then hangs up.
+/// Keeps the query string of each connection
+async fn mock_jetstream() -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+    let queries = Arc::new(Mutex::new(Vec::new()));
+    let seen = queries.clone();
+    let route = warp::path("subscribe")
+        .and(warp::query::raw())
+        .and(warp::ws())
+        .map(move |query: String, ws: warp::ws::Ws| {
+            seen.lock().unwrap().push(query);
+            ws.on_upgrade(|mut socket| async move {
+                for line in RECORDING.lines().take(2) {
+                    socket.send(Message::text(line)).await.unwrap();
+                }
+                let _ = socket.close().await;
+            })
+        });
+    let (address, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    (address, queries)
+}
+
+#[tokio::test]
+async fn jetstream_only_asks_for_feed_collections_and_resumes_from_the_cursor() {
+    let (address, queries) = mock_jetstream().await;
+    let (handler, db) = handler("jetstream").await;
+    let pipeline = handler.pipeline.clone();
+    let mut source = JetstreamSource::new(format!("ws://{address}/subscribe"), pipeline.cursor());
+    source.reconnect_delay = Duration::from_millis(10);
+
+    let running = tokio::spawn(async move {
+        let mut handler = handler;
+        source.run(&mut handler).await
+    });
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while queries.lock().unwrap().len() < 2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("Jetstream source never reconnected");
+    running.abort();
+    pipeline.flush().await;
+
+    let queries = queries.lock().unwrap().clone();
+    assert_eq!(
+        queries[0],
+        "wantedCollections=app.bsky.feed.post&wantedCollections=app.bsky.feed.like"
+    );
+    //Five seconds before the newest event stored from the first connection
+    assert_eq!(
+        queries[1],
+        "wantedCollections=app.bsky.feed.post&wantedCollections=app.bsky.feed.like&cursor=1736510396000000"
+    );
+    assert_eq!(
+        stored_uris(&db).await,
+        vec!["at://did:plc:alice/app.bsky.feed.post/1"]
+    );
+}
+
+#[tokio::test]
+async fn channel_source_hands_on_events_until_the_sender_is_gone() {
+    let (mut handler, db) = handler("firehose").await;
+    let cursor = handler.pipeline.cursor();
+    let (sender, receiver) = mpsc::channel(8);
+    for line in RECORDING.lines().take(2) {
+        let event = FeedEvent::from_jetstream_json(line).unwrap().unwrap();
+        sender.send(event).await.unwrap();
+    }
+    drop(sender);
+
+    ChannelSource::new(receiver)
+        .run(&mut handler)
+        .await
+        .unwrap();
+    assert_eq!(
+        stored_uris(&db).await,
+        vec!["at://did:plc:alice/app.bsky.feed.post/1"]
+    );
+    assert_eq!(cursor.latest(), Some(1736510401000000));
+}
@@ -0,0 +1,461 @@
+use bsky_thread_and_blog_feed::events::AccountStatus;
+use bsky_thread_and_blog_feed::ingest::{IncomingPost, IngestOutcome};
+use bsky_thread_and_blog_feed::models::{
+    AuthorList, PostScoring, TextInPost, ViewerFilter, REQUEST_LESS, REQUEST_MORE,
+};
+use bsky_thread_and_blog_feed::pipeline::FeedWrite;
+use bsky_thread_and_blog_feed::retention::RetentionPolicy;
+use bsky_thread_and_blog_feed::store::{BatchReport, FeedStore};
+use tokio_rusqlite::Connection;
+
+//** NOTICE **
+// Every test here runs against SQLite, and against Postgres too when built with the postgres
+// feature. Postgres tests need TEST_DATABASE_URL and each one gets a schema of its own in that db
+//
+
+/// 2025-01-10 12:00:00
+const NOW: i64 = 1736510400;
+
+macro_rules! store_tests {
+    ($($name:ident),* $(,)?) => {
+        mod sqlite {
+            $(
+                #[tokio::test]
+                async fn $name() {
+                    super::$name(&super::sqlite().await).await;
+                }
+            )*
+        }
+
+        #[cfg(feature = "postgres")]
+        mod postgres {
+            $(
+                #[tokio::test]
+                async fn $name() {
+                    super::$name(&super::postgres().await).await;
+                }
+            )*
+        }
+    };
+}
+
+store_tests!(
+    migrating_again_changes_nothing,
+    posts_sharing_a_link_are_counted_as_shares,
+    batches_are_written_in_order,
+    removed_posts_stay_removed,
+    blocked_authors_are_not_stored,
+    account_statuses_hide_and_purge_posts,
+    interactions_change_what_is_served,
+    served_posts_are_hidden_from_later_sessions,
+    posts_are_searchable,
+    retention_archives_the_oldest_posts,
+    cursors_are_kept_per_source,
+);
+
+async fn sqlite() -> Connection {
+    let db = Connection::open_in_memory().await.unwrap();
+    db.migrate().await.unwrap();
+    db
+}
+
+/// A store on a fresh schema of the TEST_DATABASE_URL db
+#[cfg(feature = "postgres")]
+async fn postgres() -> bsky_thread_and_blog_feed::postgres::PostgresStore {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    static SCHEMAS: AtomicU32 = AtomicU32::new(0);
+
+    let url = std::env::var("TEST_DATABASE_URL")
+        .expect("TEST_DATABASE_URL has to point at a Postgres db to test the postgres feature");
+    let schema = format!(
+        "feed_test_{}_{}_{}",
+        std::process::id(),
+        chrono::Utc::now().timestamp_micros(),
+        SCHEMAS.fetch_add(1, Ordering::Relaxed)
+    );
+    let (client, connection) = tokio_postgres::connect(&url, tokio_postgres::NoTls)
+        .await
+        .unwrap();
+    tokio::spawn(connection);
+    client
+        .batch_execute(&format!("CREATE SCHEMA {schema}"))
+        .await
+        .unwrap();
+
+    let separator = if url.contains('?') { '&' } else { '?' };
+    let store = bsky_thread_and_blog_feed::postgres::PostgresStore::connect(&format!(
+        "{url}{separator}options=-csearch_path%3D{schema}"
+    ))
+    .unwrap();
+    store.migrate().await.unwrap();
+    store
+}
+
+fn post(rkey: &str, author: &str, minutes_ago: i64) -> IncomingPost {
+    IncomingPost {
+        uri: format!("at://{author}/app.bsky.feed.post/{rkey}"),
+        author_did: author.to_string(),
+        texts: vec![TextInPost::Post(format!("New rust blog post {rkey} is up"))],
+        link_url: None,
+        timestamp: NOW - minutes_ago * 60,
+        cid: None,
+        langs: vec!["en".to_string()],
+        embed_kind: None,
+        reply: None,
+    }
+}
+
+fn scoring() -> PostScoring {
+    PostScoring {
+        pinned: false,
+        deleted: false,
+        priority: 10,
+        feed_context: "rust".to_string(),
+        matched_rules: vec!["rust".to_string()],
+    }
+}
+
+async fn store_post(store: &dyn FeedStore, post: IncomingPost) -> IngestOutcome {
+    store.store_post(post, scoring()).await.unwrap()
+}
+
+async fn feed(store: &dyn FeedStore, viewer: Option<&str>) -> Vec<String> {
+    let viewer = viewer.map(|did| ViewerFilter {
+        did: did.to_string(),
+        hide_seen_before: Some(NOW),
+    });
+    store
+        .load_feed(10, 0, None, viewer)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|post| post.uri.rsplit('/').next().unwrap().to_string())
+        .collect()
+}
+
+async fn migrating_again_changes_nothing(store: &dyn FeedStore) {
+    store_post(store, post("1", "did:plc:alice", 0)).await;
+    let version = store.migrate().await.unwrap();
+    assert_eq!(store.migrate().await.unwrap(), version);
+    assert_eq!(store.posts_count().await.unwrap(), 1);
+    store.ping().await.unwrap();
+}
+
+async fn posts_sharing_a_link_are_counted_as_shares(store: &dyn FeedStore) {
+    let link = Some("https://example.com/blog".to_string());
+    let first = IncomingPost {
+        link_url: link.clone(),
+        ..post("1", "did:plc:alice", 2)
+    };
+    let second = IncomingPost {
+        link_url: link,
+        ..post("2", "did:plc:bob", 1)
+    };
+    assert_eq!(store_post(store, first).await, IngestOutcome::Stored);
+    for _ in 0..2 {
+        assert_eq!(
+            store_post(store, second.clone()).await,
+            IngestOutcome::CountedAsShare
+        );
+    }
+    store_post(store, post("3", "did:plc:carol", 0)).await;
+
+    assert_eq!(feed(store, None).await, vec!["3", "1"]);
+    assert_eq!(store.posts_count().await.unwrap(), 2);
+    store
+        .delete_post("at://did:plc:carol/app.bsky.feed.post/3".to_string())
+        .await
+        .unwrap();
+    assert_eq!(feed(store, None).await, vec!["1"]);
+}
+
+async fn batches_are_written_in_order(store: &dyn FeedStore) {
+    let report = store
+        .write_batch(vec![
+            FeedWrite::Post {
+                post: post("1", "did:plc:alice", 1),
+                scoring: scoring(),
+            },
+            FeedWrite::Post {
+                post: post("2", "did:plc:alice", 0),
+                scoring: scoring(),
+            },
+            FeedWrite::Like {
+                like_uri: "at://did:plc:bob/app.bsky.feed.like/1".to_string(),
+                liked_post_uri: "at://did:plc:alice/app.bsky.feed.post/1".to_string(),
+            },
+            FeedWrite::DeletePost {
+                uri: "at://did:plc:alice/app.bsky.feed.post/2".to_string(),
+            },
+            FeedWrite::CuratedPost {
+                post: post("2", "did:plc:alice", 0),
+                scoring: scoring(),
+            },
+        ])
+        .await
+        .unwrap();
+    assert_eq!(
+        report,
+        BatchReport {
+            stored: 2,
+            curated: 1
+        }
+    );
+    assert_eq!(feed(store, None).await, vec!["2", "1"]);
+    //A like of a post that is not in the feed is not kept, and is no error
+    store
+        .like_post(
+            "at://did:plc:bob/app.bsky.feed.like/2".to_string(),
+            "at://did:plc:alice/app.bsky.feed.post/9".to_string(),
+        )
+        .await
+        .unwrap();
+    store
+        .delete_like("at://did:plc:bob/app.bsky.feed.like/1".to_string())
+        .await
+        .unwrap();
+}
+
+async fn removed_posts_stay_removed(store: &dyn FeedStore) {
+    let uri = "at://did:plc:alice/app.bsky.feed.post/1".to_string();
+    store_post(store, post("1", "did:plc:alice", 0)).await;
+    store
+        .moderate_post(uri.clone(), "Off topic".to_string())
+        .await
+        .unwrap();
+    assert!(feed(store, None).await.is_empty());
+    store.delete_post(uri.clone()).await.unwrap();
+    assert_eq!(
+        store_post(store, post("1", "did:plc:alice", 0)).await,
+        IngestOutcome::Moderated
+    );
+
+    let tombstones = store.list_tombstones().await.unwrap();
+    assert_eq!(tombstones.len(), 1);
+    assert_eq!(tombstones[0].uri, uri);
+    assert_eq!(tombstones[0].reason, "Off topic");
+
+    assert!(store.restore_post(uri.clone()).await.unwrap());
+    assert!(!store.restore_post(uri).await.unwrap());
+    assert_eq!(
+        store_post(store, post("1", "did:plc:alice", 0)).await,
+        IngestOutcome::Stored
+    );
+}
+
+async fn blocked_authors_are_not_stored(store: &dyn FeedStore) {
+    let alice = "did:plc:alice".to_string();
+    store
+        .add_to_author_list(
+            AuthorList::Blocked,
+            alice.clone(),
+            Some("Spam".to_string()),
+            None,
+        )
+        .await
+        .unwrap();
+    store
+        .add_to_author_list(
+            AuthorList::Trusted,
+            "did:plc:bob".to_string(),
+            None,
+            Some(NOW),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(
+        store.author_standing(alice.clone()).await.unwrap(),
+        Some(AuthorList::Blocked)
+    );
+    assert_eq!(
+        store_post(store, post("1", &alice, 0)).await,
+        IngestOutcome::Blocked
+    );
+    let lists = store.load_author_lists().await.unwrap();
+    assert!(lists.blocked.contains_key(&alice));
+    //Expired already
+    assert!(lists.trusted.is_empty());
+    let trusted = store.list_authors(AuthorList::Trusted).await.unwrap();
+    assert_eq!(trusted[0].did, "did:plc:bob");
+    assert_eq!(trusted[0].expires_at, Some(NOW));
+
+    assert!(store
+        .remove_from_author_list(AuthorList::Blocked, alice.clone())
+        .await
+        .unwrap());
+    assert_eq!(store.author_standing(alice.clone()).await.unwrap(), None);
+    assert_eq!(
+        store_post(store, post("1", &alice, 0)).await,
+        IngestOutcome::Stored
+    );
+}
+
+async fn account_statuses_hide_and_purge_posts(store: &dyn FeedStore) {
+    store_post(store, post("1", "did:plc:alice", 1)).await;
+    store_post(store, post("2", "did:plc:bob", 0)).await;
+
+    let alice = "did:plc:alice".to_string();
+    store
+        .apply_account_status(alice.clone(), AccountStatus::Deactivated)
+        .await
+        .unwrap();
+    assert_eq!(feed(store, None).await, vec!["2"]);
+    store
+        .apply_account_status(alice.clone(), AccountStatus::Active)
+        .await
+        .unwrap();
+    assert_eq!(feed(store, None).await, vec!["2", "1"]);
+    assert_eq!(
+        store
+            .apply_account_status(alice, AccountStatus::Deleted)
+            .await
+            .unwrap(),
+        1
+    );
+    assert_eq!(store.posts_count().await.unwrap(), 1);
+}
+
+async fn interactions_change_what_is_served(store: &dyn FeedStore) {
+    store_post(store, post("1", "did:plc:alice", 1)).await;
+    store_post(store, post("2", "did:plc:bob", 0)).await;
+    let first = "at://did:plc:alice/app.bsky.feed.post/1".to_string();
+    let second = "at://did:plc:bob/app.bsky.feed.post/2".to_string();
+
+    //Only the viewer who asked stops seeing it
+    store
+        .record_interactions(
+            "did:plc:viewer".to_string(),
+            vec![(second.clone(), REQUEST_MORE.to_string(), None)],
+        )
+        .await
+        .unwrap();
+    store
+        .record_interactions(
+            "did:plc:viewer".to_string(),
+            vec![(
+                first.clone(),
+                REQUEST_LESS.to_string(),
+                Some("rust".to_string()),
+            )],
+        )
+        .await
+        .unwrap();
+    assert_eq!(feed(store, Some("did:plc:viewer")).await, vec!["2"]);
+    assert_eq!(feed(store, None).await, vec!["2", "1"]);
+
+    let counts = store
+        .get_interaction_counts(vec![first.clone(), second.clone()])
+        .await
+        .unwrap();
+    assert_eq!(counts[&first].show_less, 1);
+    assert_eq!(counts[&first].show_more, 0);
+    assert_eq!(counts[&second].show_more, 1);
+
+    //The same feedback again is not counted again
+    for _ in 0..2 {
+        store
+            .record_interactions(
+                "did:plc:viewer".to_string(),
+                vec![(second.clone(), REQUEST_MORE.to_string(), None)],
+            )
+            .await
+            .unwrap();
+    }
+    let counts = store.get_interaction_counts(vec![second]).await.unwrap();
+    assert_eq!(counts.values().next().unwrap().show_more, 1);
+}
+
+async fn served_posts_are_hidden_from_later_sessions(store: &dyn FeedStore) {
+    store_post(store, post("1", "did:plc:alice", 1)).await;
+    store_post(store, post("2", "did:plc:alice", 0)).await;
+    store
+        .mark_posts_served(
+            "did:plc:viewer".to_string(),
+            vec!["at://did:plc:alice/app.bsky.feed.post/1".to_string()],
+            NOW - 60,
+        )
+        .await
+        .unwrap();
+    assert_eq!(feed(store, Some("did:plc:viewer")).await, vec!["2"]);
+    assert_eq!(feed(store, Some("did:plc:other")).await, vec!["2", "1"]);
+
+    assert_eq!(store.forget_served_posts_before(NOW).await.unwrap(), 1);
+    assert_eq!(feed(store, Some("did:plc:viewer")).await, vec!["2", "1"]);
+}
+
+async fn posts_are_searchable(store: &dyn FeedStore) {
+    store_post(store, post("1", "did:plc:alice", 1)).await;
+    store_post(
+        store,
+        IncomingPost {
+            texts: vec![
+                TextInPost::Post("Look at this".to_string()),
+                TextInPost::Picture("A crab holding a wrench".to_string()),
+            ],
+            ..post("2", "did:plc:bob", 0)
+        },
+    )
+    .await;
+
+    let results = store
+        .search_posts("crab wrench".to_string(), 10, 0)
+        .await
+        .unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].uri, "at://did:plc:bob/app.bsky.feed.post/2");
+    assert!(results[0].snippet.contains("[crab]"));
+    assert_eq!(
+        store
+            .search_posts("rust".to_string(), 10, 0)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+    assert!(store
+        .search_posts("  ".to_string(), 10, 0)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+async fn retention_archives_the_oldest_posts(store: &dyn FeedStore) {
+    for (rkey, minutes_ago) in [("1", 3), ("2", 2), ("3", 1)] {
+        store_post(store, post(rkey, "did:plc:alice", minutes_ago)).await;
+    }
+    let policy = RetentionPolicy {
+        max_posts: Some(1),
+        ..Default::default()
+    };
+    assert_eq!(store.apply_retention(&policy, NOW).await.unwrap(), 2);
+    assert_eq!(feed(store, None).await, vec!["3"]);
+    assert_eq!(store.apply_retention(&policy, NOW).await.unwrap(), 0);
+}
+
+async fn cursors_are_kept_per_source(store: &dyn FeedStore) {
+    assert_eq!(
+        store.load_cursor("firehose".to_string()).await.unwrap(),
+        None
+    );
+    store
+        .save_cursor("firehose".to_string(), 1, NOW)
+        .await
+        .unwrap();
+    store
+        .save_cursor("firehose".to_string(), 2, NOW)
+        .await
+        .unwrap();
+    store
+        .save_cursor("jetstream".to_string(), 5, NOW)
+        .await
+        .unwrap();
+    assert_eq!(
+        store.load_cursor("firehose".to_string()).await.unwrap(),
+        Some(2)
+    );
+    assert_eq!(
+        store.load_cursor("jetstream".to_string()).await.unwrap(),
+        Some(5)
+    );
+}